@@ -0,0 +1,253 @@
+use eyre::Result;
+use reth_db::DatabaseEnv;
+use reth_provider::ProviderFactory;
+use revm::primitives::{keccak256, B256};
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use tracing::{info, warn};
+
+use revmc_toolkit_build::{
+    CompilerOptions, OptimizationLevelDeseralizable, OptionOverrides, OptionsPolicy, PolicyMatch,
+    PolicyRule,
+};
+use revmc_toolkit_load::{EvmCompilerFns, RevmcExtCtx};
+use revmc_toolkit_sim::bytecode_touches;
+use revmc_toolkit_utils::evm::make_provider_factory;
+
+use crate::cli::{AutotuneArgsCli, AutotuneFlagCli, BytecodeSelectionCli};
+use crate::utils::{
+    self,
+    bench::{self as bench_utils, ExpectedExecution},
+    sim::{self as sim_utils, BytecodeSelection, SimConfig, SimRunType},
+};
+
+const AUTOTUNE_DIR: &str = "autotune";
+const POLICY_FILE: &str = "policy.toml";
+const MEASUREMENTS_FILE: &str = "measurements.csv";
+const NATIVE_VARIANT: &str = "native";
+
+#[derive(Debug, serde::Serialize)]
+struct VariantRecord {
+    bytecode_hash: B256,
+    variant: String,
+    /// Summed over the txs touching the contract [ns].
+    exe_time: Option<f64>,
+    err: Option<String>,
+}
+
+/// Times every contract on the txs touching it, once interpreted and once compiled with
+/// each variant, and writes the fastest variant of each contract as a policy rule. Variants
+/// whose txs don't match their receipts (eg. without gas metering) are refused.
+pub fn run_autotune_command(
+    dir_path: PathBuf,
+    args: AutotuneArgsCli,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<()> {
+    let provider_factory = make_provider_factory(&reth_db_path()?)?;
+    let txs = args
+        .tx_hashes
+        .iter()
+        .map(|tx_hash| Ok(B256::from_str(tx_hash)?))
+        .collect::<Result<Vec<_>>>()?;
+    let variants = variants(&args.opt_levels, &args.flags)?;
+
+    let policy_path = args
+        .out
+        .unwrap_or_else(|| dir_path.with_file_name(AUTOTUNE_DIR).join(POLICY_FILE));
+    let measurements_path = policy_path.with_file_name(MEASUREMENTS_FILE);
+    if let Some(dir) = policy_path.parent() {
+        revmc_toolkit_utils::misc::make_dir(dir)?;
+    }
+    let mut writer = csv::Writer::from_path(&measurements_path)?;
+
+    let txs_by_contract = txs_by_contract(&provider_factory, &txs)?;
+    let selection = args.selection.unwrap_or(BytecodeSelectionCli::Selected);
+    let bytecodes =
+        BytecodeSelection::from(selection).bytecodes(provider_factory.clone(), Some(txs))?;
+    let tuner = Autotuner {
+        provider_factory,
+        compile_opt: CompilerOptions::default()
+            .with_out_dir(dir_path)
+            .with_signing_key(utils::aot_signing_key_path()),
+        warmup_ms: args.warmup_ms,
+        measurement_ms: args.measurement_ms,
+    };
+    info!(
+        "Tuning {} contracts with {} variants each",
+        bytecodes.len(),
+        variants.len()
+    );
+
+    let mut policy = OptionsPolicy::default();
+    for bytecode in &bytecodes {
+        let bytecode_hash = keccak256(bytecode);
+        let Some(contract_txs) = txs_by_contract.get(&bytecode_hash) else {
+            warn!("None of the txs touch {bytecode_hash}, skipping it");
+            continue;
+        };
+        info!("Tuning {bytecode_hash} on {} txs", contract_txs.len());
+
+        let native = tuner.time_variant(bytecode, contract_txs, None);
+        writer.serialize(VariantRecord::new(bytecode_hash, NATIVE_VARIANT, &native))?;
+        let mut best: Option<(f64, &OptionOverrides)> = None;
+        for variant in &variants {
+            let label = variant_label(variant)?;
+            let res = tuner.time_variant(bytecode, contract_txs, Some(variant));
+            match &res {
+                Ok(exe_time) if best.map_or(true, |(best_time, _)| *exe_time < best_time) => {
+                    best = Some((*exe_time, variant));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Refused {label} for {bytecode_hash}: {e}"),
+            }
+            writer.serialize(VariantRecord::new(bytecode_hash, &label, &res))?;
+        }
+        writer.flush()?;
+
+        let Some((exe_time, overrides)) = best else {
+            warn!("No valid variant for {bytecode_hash}, leaving its options as they are");
+            continue;
+        };
+        match native {
+            Ok(native_time) => info!(
+                "Best for {bytecode_hash}: {} at {exe_time:.0} ns, {:.2}x native",
+                variant_label(overrides)?,
+                native_time / exe_time
+            ),
+            Err(_) => info!(
+                "Best for {bytecode_hash}: {} at {exe_time:.0} ns",
+                variant_label(overrides)?
+            ),
+        }
+        policy.rules.push(PolicyRule {
+            matcher: PolicyMatch {
+                bytecode_hashes: vec![bytecode_hash],
+                ..Default::default()
+            },
+            overrides: overrides.clone(),
+        });
+    }
+
+    policy.write(&policy_path)?;
+    info!(
+        "Wrote {} rules to {}, measurements to {}",
+        policy.rules.len(),
+        policy_path.display(),
+        measurements_path.display()
+    );
+    Ok(())
+}
+
+struct Autotuner {
+    provider_factory: ProviderFactory<DatabaseEnv>,
+    compile_opt: CompilerOptions,
+    warmup_ms: u32,
+    measurement_ms: u32,
+}
+
+impl Autotuner {
+    /// Execution time of the txs with only this contract compiled with the variant, or with
+    /// none compiled if no variant is given. Fails if any tx doesn't match its receipt.
+    fn time_variant(
+        &self,
+        bytecode: &[u8],
+        txs: &[B256],
+        variant: Option<&OptionOverrides>,
+    ) -> Result<f64> {
+        let (compiled_fns, expected) = match variant {
+            Some(overrides) => {
+                let compile_opt = overrides.apply(self.compile_opt.clone());
+                let (compiled_fns, summary) = sim_utils::make_compiled_fns_with_summary(
+                    &SimRunType::AOTCompiled,
+                    &[bytecode.to_vec()],
+                    Some(compile_opt),
+                )?;
+                if let Some(error) = summary.errors.first() {
+                    return Err(eyre::eyre!("{error}"));
+                }
+                if !summary.quarantined.is_empty() {
+                    return Err(eyre::eyre!("quarantined in an earlier run"));
+                }
+                (compiled_fns, ExpectedExecution::Compiled)
+            }
+            None => (EvmCompilerFns::default(), ExpectedExecution::Native),
+        };
+
+        let mut exe_time = 0.;
+        for tx_hash in txs {
+            let ext_ctx = RevmcExtCtx::from(compiled_fns.clone()).with_touch_tracking();
+            let mut sim =
+                SimConfig::new(self.provider_factory.clone(), ext_ctx).make_tx_sim(*tx_hash)?;
+            bench_utils::check_tx_sim_validity(
+                &self.provider_factory,
+                &mut sim,
+                vec![*tx_hash],
+                expected,
+            )?;
+            exe_time += bench_utils::measure_execution_time(
+                || sim.run(),
+                self.warmup_ms,
+                self.measurement_ms,
+            );
+        }
+        Ok(exe_time)
+    }
+}
+
+impl VariantRecord {
+    fn new(bytecode_hash: B256, variant: &str, res: &Result<f64>) -> Self {
+        Self {
+            bytecode_hash,
+            variant: variant.to_string(),
+            exe_time: res.as_ref().ok().copied(),
+            err: res.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Every opt level combined with every on/off combination of the flags.
+fn variants(opt_levels: &[u8], flags: &[AutotuneFlagCli]) -> Result<Vec<OptionOverrides>> {
+    let mut variants = vec![];
+    for level in opt_levels {
+        let opt_level = OptimizationLevelDeseralizable::try_from(*level)?;
+        for mask in 0..1u32 << flags.len() {
+            let mut overrides = OptionOverrides {
+                opt_level: Some(opt_level.clone()),
+                ..Default::default()
+            };
+            for (i, flag) in flags.iter().enumerate() {
+                let on = Some(mask & (1 << i) != 0);
+                match flag {
+                    AutotuneFlagCli::NoGas => overrides.no_gas = on,
+                    AutotuneFlagCli::InferLenChecks => overrides.infer_len_checks = on,
+                    AutotuneFlagCli::FramePointers => overrides.frame_pointers = on,
+                }
+            }
+            if !variants.contains(&overrides) {
+                variants.push(overrides);
+            }
+        }
+    }
+    Ok(variants)
+}
+
+fn variant_label(overrides: &OptionOverrides) -> Result<String> {
+    Ok(serde_json::to_string(overrides)?)
+}
+
+fn txs_by_contract(
+    provider_factory: &ProviderFactory<DatabaseEnv>,
+    txs: &[B256],
+) -> Result<HashMap<B256, Vec<B256>>> {
+    let mut txs_by_contract: HashMap<B256, Vec<B256>> = HashMap::new();
+    for tx_hash in txs {
+        let touched =
+            bytecode_touches::find_touched_bytecode(provider_factory.clone(), vec![*tx_hash])?;
+        for bytecode in touched {
+            txs_by_contract
+                .entry(keccak256(bytecode))
+                .or_default()
+                .push(*tx_hash);
+        }
+    }
+    Ok(txs_by_contract)
+}
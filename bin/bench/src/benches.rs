@@ -14,7 +14,9 @@ use crate::utils::{
     bench::{self as bench_utils, RunConfig},
     sim::{self as sim_utils, BytecodeSelection, SimCall, SimConfig, SimRunType},
 };
-use revmc_toolkit_build::OptimizationLevelDeseralizable;
+use revmc_toolkit_build::{
+    AdmissionPolicy, OptimizationLevelDeseralizable, OptionsPolicy, RemoteCache,
+};
 use revmc_toolkit_load::{EvmCompilerFns, RevmcExtCtx};
 use revmc_toolkit_sim::{
     bytecode_touches,
@@ -36,6 +38,8 @@ impl RunConfig<PathBuf, BytecodeSelection> {
             reth_db_path,
             compile_selection,
             comp_opt_level: Default::default(),
+            policy: None,
+            remote_cache: None,
         }
     }
 
@@ -70,6 +74,11 @@ impl RunConfig<PathBuf, BytecodeSelection> {
         let _guard = span.enter();
         let provider_factory = evm_utils::make_provider_factory(&self.reth_db_path)?;
 
+        let args = BlockRangeArgs {
+            policy: self.policy.clone(),
+            remote_cache: self.remote_cache.clone(),
+            ..args
+        };
         BlockRangeRunner::new(
             args,
             provider_factory,
@@ -111,7 +120,7 @@ impl RunConfig<PathBuf, BytecodeSelection> {
                 &provider_factory,
                 &mut sim,
                 txs.clone(),
-                matches!(run_type, SimRunType::Native),
+                (&run_type).into(),
             )?;
 
             criterion.bench_function(&format!("sim_{symbol}"), |b| b.iter(|| sim.run()));
@@ -157,13 +166,15 @@ impl<T> RunConfig<T, BytecodeSelection> {
     }
 
     pub fn set_bytecode_selection(&mut self, selection: BytecodeSelectionCli) {
-        self.compile_selection = match selection {
-            BytecodeSelectionCli::Selected => BytecodeSelection::Selected,
-            BytecodeSelectionCli::GasGuzzlers(config) => {
-                let (config, size_limit) = config.into();
-                BytecodeSelection::GasGuzzlers { config, size_limit }
-            }
-        };
+        self.compile_selection = selection.into();
+    }
+
+    pub fn set_policy(&mut self, policy: Option<OptionsPolicy>) {
+        self.policy = policy;
+    }
+
+    pub fn set_remote_cache(&mut self, remote_cache: Option<RemoteCache>) {
+        self.remote_cache = remote_cache;
     }
 
     pub fn set_compile_opt_level(&mut self, level: Option<u8>) -> Result<()> {
@@ -210,6 +221,13 @@ pub struct BlockRangeArgs {
     pub run_rnd_txs: bool,
     pub seed: Option<[u8; 32]>,
     pub comp_opt_level: OptimizationLevelDeseralizable,
+    pub aot_batch_size: Option<usize>,
+    pub admission: AdmissionPolicy,
+    pub compile_workers: Option<usize>,
+    #[serde(skip)]
+    pub policy: Option<OptionsPolicy>,
+    #[serde(skip)]
+    pub remote_cache: Option<RemoteCache>,
 }
 
 use csv::{Writer, WriterBuilder};
@@ -291,7 +309,7 @@ impl BlockRangeRunner {
                 &self.provider_factory,
                 &mut sim,
                 txs_for_block(&self.provider_factory, block_num)?,
-                matches!(run_type, SimRunType::Native),
+                run_type.into(),
             );
             if let Err(e) = &check_res {
                 warn!("Check failed for block {block_num} with: {e}");
@@ -368,7 +386,15 @@ impl BlockRangeRunner {
                 "Aquiring {} compiled fns for {run_type:?}",
                 self.bytecodes.len()
             );
-            sim_utils::make_compiled_fns(run_type, &self.bytecodes, Some(self.compile_opt()))
+            let (compiled_fns, summary) = sim_utils::make_compiled_fns_with_summary(
+                run_type,
+                &self.bytecodes,
+                Some(self.compile_opt()?),
+            )?;
+            summary.log();
+            let summary_path = self.args.out_dir_path.join("compile_summary.json");
+            serde_json::to_writer_pretty(File::create(summary_path)?, &summary)?;
+            Ok(compiled_fns)
         }
     }
 
@@ -426,9 +452,15 @@ impl BlockRangeRunner {
         Ok(writer)
     }
 
-    fn compile_opt(&self) -> CompilerOptions {
-        CompilerOptions::default()
+    fn compile_opt(&self) -> Result<CompilerOptions> {
+        Ok(CompilerOptions::default()
             .with_out_dir(self.aot_dir_path.clone())
+            .with_signing_key(crate::utils::aot_signing_key_path())
             .with_opt_lvl(self.args.comp_opt_level.clone())
+            .with_aot_batch_size(self.args.aot_batch_size)
+            .with_admission(self.args.admission.clone())
+            .with_workers(crate::utils::compile_workers(self.args.compile_workers)?)
+            .with_policy(self.args.policy.clone())
+            .with_remote_cache(self.args.remote_cache.clone()))
     }
 }
@@ -0,0 +1,364 @@
+use eyre::Result;
+use revm::primitives::{keccak256, B256};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tracing::{error, info};
+
+use revmc_toolkit_build::{
+    read_compile_stats, scan_cache, ArtifactFormat, CacheEntry, CacheEntryId, CompileMode,
+    CompileStats,
+};
+use revmc_toolkit_load::EvmCompilerFnLoader;
+
+use crate::cli::{CacheCommand, InCli, NotInCli, SelectionArgsCli};
+
+pub fn run_cache_command(
+    dir_path: PathBuf,
+    command: CacheCommand,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<()> {
+    if let CacheCommand::Import { path } = command {
+        let manifest = revmc_toolkit_build::import_cache(&dir_path, &path)?;
+        info!(
+            "Imported {} entries from {}",
+            manifest.entries.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+    if !dir_path.exists() {
+        info!("AOT dir {} doesn't exist", dir_path.display());
+        return Ok(());
+    }
+    if let CacheCommand::CompileStats { csv } = command {
+        return compile_stats(read_compile_stats(&dir_path)?, csv);
+    }
+    if let CacheCommand::Serve { addr, dir } = command {
+        let listener = std::net::TcpListener::bind(&addr)?;
+        info!("Serving remote cache from {} on {addr}", dir.display());
+        return revmc_toolkit_build::serve_remote_cache(listener, &dir);
+    }
+    let entries = scan_cache(&dir_path)?;
+    match command {
+        CacheCommand::Ls => ls(&entries),
+        CacheCommand::Stats => stats(&entries),
+        CacheCommand::Verify => verify(dir_path, &entries)?,
+        CacheCommand::Prune {
+            older_than,
+            not_in,
+            dry_run,
+        } => {
+            if older_than.is_none() && not_in.is_none() {
+                return Err(eyre::eyre!("Specify --older-than and/or a selection"));
+            }
+            let cutoff = older_than
+                .map(|d| {
+                    let duration = parse_duration(&d)?;
+                    SystemTime::now()
+                        .checked_sub(duration)
+                        .ok_or_else(|| eyre::eyre!("--older-than {d} reaches before the epoch"))
+                })
+                .transpose()?;
+            let keep = not_in
+                .map(|NotInCli::NotIn(args)| selection_hashes(args, reth_db_path))
+                .transpose()?;
+            let to_remove = entries.iter().filter(|entry| {
+                let is_old = match (cutoff, entry.created) {
+                    (Some(cutoff), Some(created)) => created < cutoff,
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+                // Static archives don't record their contracts, so only their age counts
+                let is_unused = match &keep {
+                    _ if matches!(entry.id, CacheEntryId::Archive(_)) => cutoff.is_some(),
+                    Some(keep) => !entry.bytecode_hashes.iter().any(|h| keep.contains(h)),
+                    None => true,
+                };
+                is_old && is_unused
+            });
+            remove(to_remove, dry_run)?;
+        }
+        CacheCommand::Gc { dry_run } => {
+            remove(entries.iter().filter(|entry| !entry.is_ok()), dry_run)?;
+        }
+        CacheCommand::Export { path, only } => {
+            let only = only
+                .map(|InCli::In(args)| selection_hashes(args, reth_db_path))
+                .transpose()?;
+            let manifest = revmc_toolkit_build::export_cache(&dir_path, &path, only.as_ref())?;
+            info!(
+                "Exported {} entries to {}",
+                manifest.entries.len(),
+                path.display()
+            );
+        }
+        CacheCommand::Import { .. }
+        | CacheCommand::CompileStats { .. }
+        | CacheCommand::Serve { .. } => {
+            unreachable!("Handled before scanning")
+        }
+    }
+    Ok(())
+}
+
+fn selection_hashes(
+    args: SelectionArgsCli,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<HashSet<B256>> {
+    Ok(args
+        .bytecodes(reth_db_path)?
+        .iter()
+        .map(keccak256)
+        .collect())
+}
+
+fn ls(entries: &[CacheEntry]) {
+    let now = SystemTime::now();
+    for entry in entries {
+        let age = entry
+            .created
+            .and_then(|created| now.duration_since(created).ok())
+            .map(format_duration)
+            .unwrap_or_else(|| "?".to_string());
+        let options = entry.options.as_ref().map_or("-".to_string(), |o| {
+            format!(
+                "{} spec={:?} opt={:?} target={}{}",
+                o.cache_key(),
+                o.spec_id,
+                o.opt_level,
+                o.target,
+                o.target_cpu
+                    .as_ref()
+                    .map(|cpu| format!("/{cpu}"))
+                    .unwrap_or_default()
+            )
+        });
+        let status = entry
+            .problem
+            .as_ref()
+            .map_or("ok".to_string(), ToString::to_string);
+        println!(
+            "{:<72} {:>10} {:>8} {options} [{status}]",
+            entry.id.to_string(),
+            format_size(entry.size),
+            age,
+        );
+    }
+}
+
+fn stats(entries: &[CacheEntry]) {
+    let mut by_options = BTreeMap::<String, (usize, u64)>::new();
+    let mut by_problem = BTreeMap::<String, (usize, u64)>::new();
+    let (mut artifacts, mut batches, mut archives, mut total_size) = (0, 0, 0, 0);
+    for entry in entries {
+        match entry.id {
+            CacheEntryId::Artifact(_) => artifacts += 1,
+            CacheEntryId::Batch(_) => batches += 1,
+            CacheEntryId::Archive(_) => archives += 1,
+        }
+        total_size += entry.size;
+        let key = entry
+            .options
+            .as_ref()
+            .map_or("unknown".to_string(), |o| o.cache_key());
+        let counter = by_options.entry(key).or_default();
+        counter.0 += 1;
+        counter.1 += entry.size;
+        if let Some(problem) = &entry.problem {
+            let counter = by_problem.entry(problem.to_string()).or_default();
+            counter.0 += 1;
+            counter.1 += entry.size;
+        }
+    }
+    println!("Artifacts: {artifacts}");
+    println!("Batches: {batches}");
+    println!("Static archives: {archives}");
+    println!("Total size: {}", format_size(total_size));
+    println!("By options:");
+    for (key, (count, size)) in by_options {
+        println!("  {key:<16} {count:>8} {:>10}", format_size(size));
+    }
+    println!("Broken:");
+    for (problem, (count, size)) in by_problem {
+        println!("  {problem:<40} {count:>8} {:>10}", format_size(size));
+    }
+}
+
+#[derive(serde::Serialize)]
+struct CompileStatsRow {
+    bytecode_hash: B256,
+    mode: CompileMode,
+    spec_id: String,
+    opt_level: String,
+    target: String,
+    bytecode_size: usize,
+    module_size: usize,
+    translate_ms: f64,
+    optimize_codegen_ms: f64,
+    link_ms: Option<f64>,
+    object_size: Option<u64>,
+    compiled_at: u64,
+}
+
+impl From<&CompileStats> for CompileStatsRow {
+    fn from(stats: &CompileStats) -> Self {
+        Self {
+            bytecode_hash: stats.bytecode_hash,
+            mode: stats.mode,
+            spec_id: format!("{:?}", stats.options.spec_id),
+            opt_level: format!("{:?}", stats.options.opt_level),
+            target: stats.options.target.clone(),
+            bytecode_size: stats.bytecode_size,
+            module_size: stats.module_size,
+            translate_ms: stats.translate_ms,
+            optimize_codegen_ms: stats.optimize_codegen_ms,
+            link_ms: stats.link_ms,
+            object_size: stats.object_size,
+            compiled_at: stats.compiled_at,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CompileCost {
+    contracts: usize,
+    bytecode_size: usize,
+    translate_ms: f64,
+    optimize_codegen_ms: f64,
+    link_ms: f64,
+}
+
+fn compile_stats(stats: Vec<CompileStats>, csv: Option<PathBuf>) -> Result<()> {
+    let mut by_group = BTreeMap::<String, CompileCost>::new();
+    for record in &stats {
+        let key = format!("{:?} {:?}", record.mode, record.options.opt_level);
+        let cost = by_group.entry(key).or_default();
+        // Module-wide times are shared by the contracts compiled together
+        let module_size = record.module_size.max(1) as f64;
+        cost.contracts += 1;
+        cost.bytecode_size += record.bytecode_size;
+        cost.translate_ms += record.translate_ms;
+        cost.optimize_codegen_ms += record.optimize_codegen_ms / module_size;
+        cost.link_ms += record.link_ms.unwrap_or_default() / module_size;
+    }
+    println!("Records: {}", stats.len());
+    for (key, cost) in by_group {
+        let total = cost.translate_ms + cost.optimize_codegen_ms + cost.link_ms;
+        let count = cost.contracts as f64;
+        let kb = cost.bytecode_size as f64 / 1024.;
+        println!(
+            "  {key:<20} {:>8} contracts {:>10.1} ms/contract {:>10.1} ms/KB",
+            cost.contracts,
+            total / count,
+            if kb > 0. { total / kb } else { 0. },
+        );
+        println!(
+            "  {:<20} translate {:.1}, optimize+codegen {:.1}, link {:.1} ms/contract",
+            "",
+            cost.translate_ms / count,
+            cost.optimize_codegen_ms / count,
+            cost.link_ms / count,
+        );
+    }
+    if let Some(path) = csv {
+        let mut writer = csv::Writer::from_path(&path)?;
+        for record in &stats {
+            writer.serialize(CompileStatsRow::from(record))?;
+        }
+        writer.flush()?;
+        info!("Wrote {} records to {}", stats.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Loads every entry with a shared object the way the sims do and reports those that fail
+/// to load. Entries of other formats only need to be intact.
+fn verify(dir_path: PathBuf, entries: &[CacheEntry]) -> Result<()> {
+    let mut failed = 0;
+    for entry in entries {
+        let res = match (&entry.problem, &entry.options) {
+            (Some(problem), _) => Err(eyre::eyre!("{problem}")),
+            (None, _) if entry.format != ArtifactFormat::SharedObject => Ok(()),
+            (None, None) => Err(eyre::eyre!("missing options")),
+            (None, Some(options)) => {
+                let loader =
+                    EvmCompilerFnLoader::new(&dir_path).with_artifact_options(options.clone());
+                match &entry.id {
+                    CacheEntryId::Artifact(bytecode_hash) => loader.load(bytecode_hash).map(|_| ()),
+                    CacheEntryId::Batch(_) => loader.load_batch(&entry.dir).map(|_| ()),
+                    CacheEntryId::Archive(_) => Ok(()),
+                }
+            }
+        };
+        if let Err(e) = res {
+            error!("{}: {e}", entry.id);
+            failed += 1;
+        }
+    }
+    info!("Verified {} entries, {failed} failed", entries.len());
+    if failed > 0 {
+        return Err(eyre::eyre!(
+            "{failed} broken entries, run `cache gc` to remove them"
+        ));
+    }
+    Ok(())
+}
+
+fn remove<'a>(entries: impl Iterator<Item = &'a CacheEntry>, dry_run: bool) -> Result<()> {
+    let (mut count, mut size) = (0, 0);
+    for entry in entries {
+        info!("Removing {} ({})", entry.id, entry.dir.display());
+        if !dry_run {
+            entry.remove()?;
+        }
+        count += 1;
+        size += entry.size;
+    }
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    info!("{verb} {count} entries, {}", format_size(size));
+    Ok(())
+}
+
+/// Parses durations like `30s`, `15m`, `12h` or `7d`.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration> {
+    let (value, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| eyre::eyre!("Invalid duration {s}"))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(eyre::eyre!(
+                "Invalid duration unit {unit}, expected s/m/h/d"
+            ))
+        }
+    };
+    let secs = value
+        .checked_mul(secs)
+        .ok_or_else(|| eyre::eyre!("Duration {s} is too long"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3_599 => format!("{}m", secs / 60),
+        3_600..=86_399 => format!("{}h", secs / 3_600),
+        _ => format!("{}d", secs / 86_400),
+    }
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        0..=1_023 => format!("{size}B"),
+        1_024..=1_048_575 => format!("{:.1}KiB", size as f64 / 1_024.),
+        _ => format!("{:.1}MiB", size as f64 / 1_048_576.),
+    }
+}
@@ -1,10 +1,11 @@
-use crate::{benches::BlockRangeArgs, utils, BlockPart};
+use crate::{benches::BlockRangeArgs, utils, utils::sim::BytecodeSelection, BlockPart};
 use clap::{Args, Parser, Subcommand};
 use eyre::Result;
-use revm::primitives::Bytes;
+use revm::primitives::{Bytes, SpecId, B256};
+use revmc_toolkit_build::{AdmissionPolicy, ArtifactFormat, CompilerOptions, LinkerConfig};
 use revmc_toolkit_sim::gas_guzzlers::GasGuzzlerConfig;
 use revmc_toolkit_utils::rnd as rnd_utils;
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -20,6 +21,307 @@ pub enum Commands {
     Run(RunArgsCli),
     #[command(subcommand)]
     Bench(Box<BenchType>),
+    #[command(subcommand, about = "Inspect and clean up the AOT compile dir.")]
+    Cache(CacheCommand),
+    #[command(about = "Compile a contract and dump its IR, assembly and codegen stats.")]
+    Inspect(InspectArgsCli),
+    #[command(about = "AOT compile contracts from files, accounts or a selection.")]
+    Compile(CompileArgsCli),
+    #[command(
+        about = "Time contracts under a matrix of compiler options and write the best per contract as a policy."
+    )]
+    Autotune(AutotuneArgsCli),
+    #[command(
+        hide = true,
+        about = "Serve compile requests on stdin, see `--compile-workers`."
+    )]
+    CompileWorker,
+}
+
+#[derive(Args, Debug)]
+pub struct AutotuneArgsCli {
+    #[arg(
+        long,
+        required = true,
+        value_delimiter = ',',
+        help = "Comma-separated hashes of representative txs, each contract is timed on the ones touching it."
+    )]
+    pub tx_hashes: Vec<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,2,3",
+        help = "Comma-separated optimization levels to try."
+    )]
+    pub opt_levels: Vec<u8>,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Comma-separated flags to try both off and on."
+    )]
+    pub flags: Vec<AutotuneFlagCli>,
+    #[arg(
+        long,
+        default_value = "500",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Warmup time per variant and tx [ms]."
+    )]
+    pub warmup_ms: u32,
+    #[arg(
+        long,
+        default_value = "2000",
+        help = "Measurement time per variant and tx [ms]."
+    )]
+    pub measurement_ms: u32,
+    #[arg(
+        short,
+        long,
+        help = "Policy file to write, TOML if it ends in `.toml` and JSON otherwise. Defaults to .data/autotune/policy.toml."
+    )]
+    pub out: Option<PathBuf>,
+    #[command(subcommand)]
+    pub selection: Option<BytecodeSelectionCli>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ArtifactFormatCli {
+    SharedObject,
+    StaticArchive,
+    Objects,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum AutotuneFlagCli {
+    NoGas,
+    // Stack length checks are only dropped where they are proven unnecessary, a variant
+    // without them would be picked for being fastest, although it's unsound on an overflow
+    InferLenChecks,
+    FramePointers,
+}
+
+#[derive(Args, Debug)]
+pub struct CompileArgsCli {
+    #[command(flatten)]
+    pub options: CompilerOptionsCli,
+    #[command(subcommand)]
+    pub input: CompileInputCli,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CompileInputCli {
+    #[command(about = "Files holding one hex encoded bytecode per line.")]
+    Hex { paths: Vec<PathBuf> },
+    #[command(about = "Dirs of `.bin` files, each holding a raw or hex encoded bytecode.")]
+    Dir { paths: Vec<PathBuf> },
+    #[command(about = "JSON files holding a list of hex encoded bytecodes.")]
+    Json { paths: Vec<PathBuf> },
+    #[command(about = "Code of accounts given as address@block.")]
+    Accounts {
+        accounts: Vec<String>,
+        #[arg(long, help = "File with one address@block per line.")]
+        file: Option<PathBuf>,
+    },
+    #[command(about = "Contracts of a bytecode selection.")]
+    Selection(SelectionArgsCli),
+}
+
+#[derive(Args, Debug)]
+pub struct CompilerOptionsCli {
+    #[arg(long, help = "Compiler optimization level.")]
+    pub comp_opt_level: Option<u8>,
+    #[arg(
+        long,
+        value_parser = parse_spec_id,
+        help = "Spec to compile for, eg. Shanghai. Defaults to Cancun."
+    )]
+    pub spec_id: Option<SpecId>,
+    #[arg(long, help = "Target triple. Defaults to the host.")]
+    pub target: Option<String>,
+    #[arg(long, help = "Target CPU, eg. znver3.")]
+    pub target_cpu: Option<String>,
+    #[arg(long, help = "LLVM target features, eg. +avx2,-avx512f.")]
+    pub target_features: Option<String>,
+    #[arg(long, help = "Compile without gas metering.")]
+    pub no_gas: bool,
+    #[arg(long, help = "Compile without stack length checks.")]
+    pub no_len_checks: bool,
+    #[arg(
+        long,
+        help = "Compile without stack length checks if the stack height is proven to stay in bounds."
+    )]
+    pub infer_len_checks: bool,
+    #[arg(long, help = "Keep frame pointers.")]
+    pub frame_pointers: bool,
+    #[arg(long, help = "Compile with debug assertions.")]
+    pub debug_assertions: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "shared-object",
+        help = "What to produce besides the objects. A static archive packs all compiled contracts."
+    )]
+    pub format: ArtifactFormatCli,
+    #[arg(long, help = "Only write the objects, same as --format objects.")]
+    pub no_link: bool,
+    #[arg(
+        long,
+        help = "C compiler driver linking shared objects. Defaults to cc."
+    )]
+    pub linker: Option<PathBuf>,
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        help = "Extra linker flag, can be repeated."
+    )]
+    pub linker_arg: Vec<String>,
+    #[arg(long, help = "Archiver packing static archives. Defaults to ar.")]
+    pub archiver: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "If present contracts are linked into shared libraries of this many contracts."
+    )]
+    pub aot_batch_size: Option<usize>,
+    #[arg(long, help = "Contracts taking longer to compile fail, eg. 30s or 5m.")]
+    pub compile_timeout: Option<String>,
+    #[arg(
+        long,
+        help = "TOML or JSON file with per-contract option overrides. Defaults to COMPILE_POLICY_PATH."
+    )]
+    pub policy: Option<PathBuf>,
+    #[command(flatten)]
+    pub admission: AdmissionArgsCli,
+    #[arg(
+        long,
+        help = "Compile in this many worker processes, so that a crash only fails the contract being compiled. Not used for batches."
+    )]
+    pub compile_workers: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct AdmissionArgsCli {
+    #[arg(
+        long,
+        help = "Leave bytecodes larger than this many bytes interpreted."
+    )]
+    pub max_bytecode_size: Option<usize>,
+    #[arg(
+        long,
+        help = "Leave bytecodes with more JUMPDESTs than this interpreted."
+    )]
+    pub max_jumpdests: Option<usize>,
+    #[arg(
+        long,
+        help = "Leave bytecodes with a larger estimated IR size (in instructions) interpreted."
+    )]
+    pub max_estimated_ir_size: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgsCli {
+    #[arg(help = "Bytecode hash of a contract in the AOT dir, address@block or bytecode hex.")]
+    pub target: String,
+    #[arg(long, help = "Compiler optimization level.")]
+    pub comp_opt_level: Option<u8>,
+    #[arg(long, help = "Compile without gas metering.")]
+    pub no_gas: bool,
+    #[arg(long, help = "Compile without stack length checks.")]
+    pub no_len_checks: bool,
+    #[arg(
+        short,
+        long,
+        help = "Dir the dumps are written to. Defaults to .data/inspect/{bytecode-hash}."
+    )]
+    pub out_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Comma-separated dumps to print."
+    )]
+    pub print: Vec<InspectDumpCli>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum InspectDumpCli {
+    UnoptIr,
+    OptIr,
+    Asm,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    #[command(about = "List entries with their size, age, options and status.")]
+    Ls,
+    #[command(about = "Summarize the dir size by options and broken entries.")]
+    Stats,
+    #[command(about = "Load every entry and check that its symbols resolve.")]
+    Verify,
+    #[command(about = "Remove entries matching all of the given filters.")]
+    Prune {
+        #[arg(
+            long,
+            help = "Only entries created before this long ago, eg. 12h or 7d."
+        )]
+        older_than: Option<String>,
+        #[arg(long, help = "Only print what would be removed.")]
+        dry_run: bool,
+        #[command(subcommand)]
+        not_in: Option<NotInCli>,
+    },
+    #[command(about = "Remove broken, stale and legacy entries.")]
+    Gc {
+        #[arg(long, help = "Only print what would be removed.")]
+        dry_run: bool,
+    },
+    #[command(about = "Pack loadable entries into a single archive.")]
+    Export {
+        #[arg(help = "Path of the archive to create.")]
+        path: PathBuf,
+        #[command(subcommand)]
+        only: Option<InCli>,
+    },
+    #[command(about = "Unpack an archive created with `export`.")]
+    Import {
+        #[arg(help = "Path of the archive.")]
+        path: PathBuf,
+    },
+    #[command(about = "Summarize the recorded compile times by mode and opt level.")]
+    CompileStats {
+        #[arg(long, help = "Also write every record to this CSV file.")]
+        csv: Option<PathBuf>,
+    },
+    #[command(about = "Serve a remote artifact cache over HTTP for AOT_REMOTE_CACHE_URL.")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080", help = "Address to listen on.")]
+        addr: String,
+        #[arg(long, help = "Dir to store artifacts in.")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NotInCli {
+    #[command(about = "Only entries whose bytecodes are all outside of the selection.")]
+    NotIn(SelectionArgsCli),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum InCli {
+    #[command(about = "Only entries holding any bytecode of the selection.")]
+    In(SelectionArgsCli),
+}
+
+#[derive(Args, Debug)]
+pub struct SelectionArgsCli {
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Comma-separated tx hashes for the `selected` selection."
+    )]
+    pub tx_hashes: Vec<String>,
+    #[command(subcommand)]
+    pub selection: BytecodeSelectionCli,
 }
 
 #[derive(Subcommand)]
@@ -101,6 +403,8 @@ pub struct GasGuzzlersCli {
     pub seed: Option<String>,
     #[arg(long, help = "Size limit for gas guzzlers selection.")]
     pub size_limit: usize,
+    #[arg(long, help = "Also select the initcode of creates.")]
+    pub include_initcode: bool,
 }
 
 #[derive(Args, Debug)]
@@ -138,7 +442,7 @@ pub struct BlockRangeArgsCli {
     pub sample_size: Option<u32>,
     #[arg(short, long, help = "Path to dir where measurements will be stored.")]
     pub out_dir: Option<String>,
-    #[arg(long, help = "Warmup time [ms].")]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help = "Warmup time [ms].")]
     pub warmup_ms: Option<u32>,
     #[arg(long, help = "Measurement time [ms].")]
     pub measurement_ms: Option<u32>,
@@ -158,6 +462,118 @@ pub struct BlockRangeArgsCli {
     pub blacklist: Option<String>,
     #[arg(long, help = "Compiler optimization level.")]
     pub comp_opt_level: Option<u8>,
+    #[arg(
+        long,
+        help = "If present AOT contracts are linked into shared libraries of this many contracts."
+    )]
+    pub aot_batch_size: Option<usize>,
+    #[command(flatten)]
+    pub admission: AdmissionArgsCli,
+    #[arg(
+        long,
+        help = "Compile in this many worker processes, so that a crash only fails the contract being compiled. Not used for batches."
+    )]
+    pub compile_workers: Option<usize>,
+}
+
+impl CompilerOptionsCli {
+    pub fn into_compiler_options(self, out_dir: PathBuf) -> Result<CompilerOptions> {
+        let compile_timeout = self
+            .compile_timeout
+            .map(|d| crate::cache::parse_duration(&d))
+            .transpose()?;
+        let mut linker = LinkerConfig::default().with_args(self.linker_arg);
+        if let Some(command) = self.linker {
+            linker = linker.with_command(command);
+        }
+        if let Some(archiver) = self.archiver {
+            linker = linker.with_archiver(archiver);
+        }
+        let mut options = CompilerOptions::default()
+            .with_out_dir(out_dir)
+            .with_target_cpu(self.target_cpu)
+            .with_target_features(self.target_features)
+            .with_no_gas(self.no_gas)
+            .with_no_len_checks(self.no_len_checks)
+            .with_infer_len_checks(self.infer_len_checks)
+            .with_frame_pointers(self.frame_pointers)
+            .with_debug_assertions(self.debug_assertions)
+            .with_format(match (self.no_link, self.format) {
+                (true, _) => ArtifactFormat::Objects,
+                (false, format) => format.into(),
+            })
+            .with_linker(linker)
+            .with_aot_batch_size(self.aot_batch_size)
+            .with_compile_timeout(compile_timeout)
+            .with_signing_key(utils::aot_signing_key_path())
+            .with_admission(self.admission.into())
+            .with_workers(utils::compile_workers(self.compile_workers)?)
+            .with_remote_cache(utils::remote_cache()?);
+        if let Some(level) = self.comp_opt_level {
+            options = options.with_opt_lvl(level.try_into()?);
+        }
+        if let Some(spec_id) = self.spec_id {
+            options = options.with_spec_id(spec_id);
+        }
+        if let Some(target) = self.target {
+            options = options.with_target(target);
+        }
+        Ok(options)
+    }
+}
+
+/// Parses hardfork names like `Cancun`, as `SpecId::from` silently maps unknown names to
+/// the latest spec.
+fn parse_spec_id(s: &str) -> Result<SpecId, String> {
+    let spec_id = SpecId::from(s);
+    if spec_id == SpecId::LATEST && s != "Latest" {
+        return Err(format!("unknown spec {s}, expected eg. Shanghai or Cancun"));
+    }
+    Ok(spec_id)
+}
+
+impl SelectionArgsCli {
+    pub fn bytecodes(self, reth_db_path: impl FnOnce() -> Result<PathBuf>) -> Result<Vec<Vec<u8>>> {
+        let provider_factory = revmc_toolkit_utils::evm::make_provider_factory(&reth_db_path()?)?;
+        let txs = self
+            .tx_hashes
+            .iter()
+            .map(|tx_hash| Ok(B256::from_str(tx_hash)?))
+            .collect::<Result<Vec<_>>>()?;
+        let txs = (!txs.is_empty()).then_some(txs);
+        BytecodeSelection::from(self.selection).bytecodes(provider_factory, txs)
+    }
+}
+
+impl From<ArtifactFormatCli> for ArtifactFormat {
+    fn from(format: ArtifactFormatCli) -> Self {
+        match format {
+            ArtifactFormatCli::SharedObject => ArtifactFormat::SharedObject,
+            ArtifactFormatCli::StaticArchive => ArtifactFormat::StaticArchive,
+            ArtifactFormatCli::Objects => ArtifactFormat::Objects,
+        }
+    }
+}
+
+impl From<AdmissionArgsCli> for AdmissionPolicy {
+    fn from(cli: AdmissionArgsCli) -> Self {
+        AdmissionPolicy::default()
+            .with_max_bytecode_size(cli.max_bytecode_size)
+            .with_max_jumpdests(cli.max_jumpdests)
+            .with_max_estimated_ir_size(cli.max_estimated_ir_size)
+    }
+}
+
+impl From<BytecodeSelectionCli> for BytecodeSelection {
+    fn from(selection: BytecodeSelectionCli) -> Self {
+        match selection {
+            BytecodeSelectionCli::Selected => BytecodeSelection::Selected,
+            BytecodeSelectionCli::GasGuzzlers(config) => {
+                let (config, size_limit) = config.into();
+                BytecodeSelection::GasGuzzlers { config, size_limit }
+            }
+        }
+    }
 }
 
 impl From<GasGuzzlersCli> for (GasGuzzlerConfig, usize) {
@@ -168,6 +584,7 @@ impl From<GasGuzzlersCli> for (GasGuzzlerConfig, usize) {
                 end_block: cli.end_block,
                 sample_size: cli.sample_size,
                 seed: cli.seed.map(hashed),
+                include_initcode: cli.include_initcode,
             },
             cli.size_limit,
         )
@@ -276,6 +693,11 @@ impl TryInto<BlockRangeArgs> for BlockRangeArgsCli {
             run_rnd_txs: self.run_rnd_txs,
             seed: self.hashed_seed(),
             comp_opt_level: self.comp_opt_level.unwrap_or_default().try_into()?,
+            aot_batch_size: self.aot_batch_size,
+            admission: self.admission.into(),
+            compile_workers: self.compile_workers,
+            policy: None,
+            remote_cache: None,
         })
     }
 }
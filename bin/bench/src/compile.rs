@@ -0,0 +1,215 @@
+use eyre::{Result, WrapErr};
+use revm::primitives::{hex, keccak256, Address, B256};
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+use revmc_toolkit_build::{CompileSummary, OptionsPolicy};
+
+use crate::cli::{BytecodeSelectionCli, CompileArgsCli, CompileInputCli};
+use crate::utils;
+
+pub fn run_compile_command(
+    dir_path: PathBuf,
+    args: CompileArgsCli,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<()> {
+    let policy = match &args.options.policy {
+        Some(path) => Some(OptionsPolicy::read(path)?),
+        None => utils::compile_policy()?,
+    };
+    let input = read_input(args.input, reth_db_path)?;
+    let mut seen = HashSet::new();
+    let bytecodes = input
+        .bytecodes
+        .into_iter()
+        .filter(|bytecode| !bytecode.is_empty() && seen.insert(keccak256(bytecode)))
+        .collect::<Vec<_>>();
+    if bytecodes.is_empty() {
+        return Err(eyre::eyre!("No bytecodes to compile"));
+    }
+    let policy = policy.map(|policy| {
+        let policy = policy.with_addresses(input.addresses);
+        if input.ranked {
+            policy.with_guzzler_ranks(bytecodes.iter().map(keccak256))
+        } else {
+            policy
+        }
+    });
+    let options = args
+        .options
+        .into_compiler_options(dir_path)?
+        .with_policy(policy);
+    info!(
+        "Compiling {} contracts into {}",
+        bytecodes.len(),
+        options.out_dir.display()
+    );
+
+    let on_progress = |done: usize, total: usize| {
+        eprint!("\rCompiled {done}/{total}");
+        let _ = std::io::stderr().flush();
+    };
+    let summary = if options.aot_batch_size.is_some() {
+        revmc_toolkit_build::compile_contracts_aot_batched_with_progress(
+            &bytecodes,
+            Some(options),
+            on_progress,
+        )?
+        .1
+    } else {
+        revmc_toolkit_build::compile_contracts_aot_with_progress(
+            &bytecodes,
+            Some(options),
+            on_progress,
+        )?
+    };
+    eprintln!();
+    print_summary(&summary);
+    if !summary.errors.is_empty() {
+        return Err(eyre::eyre!(
+            "{} contracts failed to compile",
+            summary.errors.len()
+        ));
+    }
+    Ok(())
+}
+
+struct CompileInput {
+    bytecodes: Vec<Vec<u8>>,
+    /// Addresses the bytecodes were read from, for the policy.
+    addresses: Vec<(B256, Address)>,
+    /// Whether the bytecodes are ordered by gas guzzler rank.
+    ranked: bool,
+}
+
+impl From<Vec<Vec<u8>>> for CompileInput {
+    fn from(bytecodes: Vec<Vec<u8>>) -> Self {
+        Self {
+            bytecodes,
+            addresses: vec![],
+            ranked: false,
+        }
+    }
+}
+
+fn read_input(
+    input: CompileInputCli,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<CompileInput> {
+    let bytecodes = match input {
+        CompileInputCli::Hex { paths } => paths.iter().try_fold(vec![], |mut acc, path| {
+            acc.extend(read_hex_lines(path)?);
+            Ok(acc)
+        })?,
+        CompileInputCli::Dir { paths } => paths.iter().try_fold(vec![], |mut acc, path| {
+            acc.extend(read_bin_dir(path)?);
+            Ok(acc)
+        })?,
+        CompileInputCli::Json { paths } => paths.iter().try_fold(vec![], |mut acc, path| {
+            let encoded: Vec<String> = serde_json::from_reader(std::fs::File::open(path)?)
+                .wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+            for bytecode in encoded {
+                acc.push(hex::decode(bytecode)?);
+            }
+            Ok(acc)
+        })?,
+        CompileInputCli::Accounts { mut accounts, file } => {
+            if let Some(file) = file {
+                accounts.extend(
+                    std::fs::read_to_string(file)?
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty())
+                        .map(String::from),
+                );
+            }
+            let accounts = accounts
+                .iter()
+                .map(|account| utils::parse_account_at(account))
+                .collect::<Result<Vec<_>>>()?;
+            let provider_factory =
+                revmc_toolkit_utils::evm::make_provider_factory(&reth_db_path()?)?;
+            let bytecodes = accounts
+                .iter()
+                .map(|(address, block_num)| {
+                    utils::account_code_at(&provider_factory, *address, *block_num)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let addresses = bytecodes
+                .iter()
+                .zip(&accounts)
+                .map(|(bytecode, (address, _))| (keccak256(bytecode), *address))
+                .collect();
+            return Ok(CompileInput {
+                bytecodes,
+                addresses,
+                ranked: false,
+            });
+        }
+        CompileInputCli::Selection(args) => {
+            // Gas guzzlers come ordered from the top one
+            let ranked = matches!(args.selection, BytecodeSelectionCli::GasGuzzlers(_));
+            return Ok(CompileInput {
+                bytecodes: args.bytecodes(reth_db_path)?,
+                addresses: vec![],
+                ranked,
+            });
+        }
+    };
+    Ok(bytecodes.into())
+}
+
+fn read_hex_lines(path: &Path) -> Result<Vec<Vec<u8>>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            hex::decode(line.trim())
+                .wrap_err_with(|| format!("Invalid hex at {}:{}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// Bytecodes of the `.bin` files in the dir. Files that are valid hex text are decoded,
+/// others are taken as raw bytecode.
+fn read_bin_dir(dir: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut bytecodes = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "bin") {
+            continue;
+        }
+        let content = std::fs::read(&path)?;
+        let decoded = std::str::from_utf8(&content)
+            .ok()
+            .and_then(|text| hex::decode(text.trim()).ok());
+        bytecodes.push(decoded.unwrap_or(content));
+    }
+    Ok(bytecodes)
+}
+
+fn print_summary(summary: &CompileSummary) {
+    println!("Compiled:     {}", summary.compiled);
+    println!("Cached:       {}", summary.cached);
+    println!("Fetched:      {}", summary.fetched);
+    println!("Quarantined:  {}", summary.quarantined.len());
+    println!("Rejected:     {}", summary.rejected.len());
+    println!("Failed:       {}", summary.errors.len());
+    for (kind, count) in summary.failures_by_kind() {
+        println!("  {kind:<10} {count}");
+    }
+    if let Some(archive) = &summary.archive {
+        println!("Archive:      {}", archive.display());
+    }
+    for rejection in &summary.rejected {
+        println!("{rejection}");
+    }
+    for error in &summary.errors {
+        println!("{error}");
+    }
+}
@@ -0,0 +1,130 @@
+use eyre::Result;
+use revm::primitives::{hex, B256};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use revmc_toolkit_build::{
+    scan_cache, CacheEntryId, Compiler, CompilerOptions, InspectReport, BYTECODE_FILE,
+};
+
+use crate::cli::{InspectArgsCli, InspectDumpCli};
+use crate::utils;
+
+const INSPECT_DIR: &str = "inspect";
+const REPORT_FILE: &str = "report.json";
+
+pub fn run_inspect_command(
+    dir_path: PathBuf,
+    args: InspectArgsCli,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<()> {
+    let bytecode = resolve_bytecode(&dir_path, &args.target, reth_db_path)?;
+    let bytecode_hash = revm::primitives::keccak256(&bytecode);
+
+    let mut options = CompilerOptions::default().with_out_dir(&dir_path);
+    if let Some(level) = args.comp_opt_level {
+        options = options.with_opt_lvl(level.try_into()?);
+    }
+    options.no_gas = args.no_gas;
+    options.no_len_checks = args.no_len_checks;
+
+    // Next to the AOT dir rather than in it, so the dumps don't show up as cache entries
+    let dump_dir = args.out_dir.unwrap_or_else(|| {
+        dir_path
+            .with_file_name(INSPECT_DIR)
+            .join(bytecode_hash.to_string())
+    });
+    info!("Inspecting {bytecode_hash} in {}", dump_dir.display());
+    let report = Compiler::from(options).inspect(&bytecode, &dump_dir)?;
+    serde_json::to_writer_pretty(std::fs::File::create(dump_dir.join(REPORT_FILE))?, &report)?;
+
+    for dump in args.print {
+        let path = match dump {
+            InspectDumpCli::UnoptIr => &report.unopt_ir,
+            InspectDumpCli::OptIr => &report.opt_ir,
+            InspectDumpCli::Asm => &report.asm,
+        };
+        match path {
+            Some(path) => println!("{}", std::fs::read_to_string(path)?),
+            None => return Err(eyre::eyre!("revmc didn't dump {dump:?}")),
+        }
+    }
+    print_report(&report);
+    Ok(())
+}
+
+/// Cached bytecode if `target` is a hash, the code of an account if it's `address@block`
+/// and otherwise the decoded hex.
+fn resolve_bytecode(
+    dir_path: &Path,
+    target: &str,
+    reth_db_path: impl FnOnce() -> Result<PathBuf>,
+) -> Result<Vec<u8>> {
+    if target.contains('@') {
+        let (address, block_num) = utils::parse_account_at(target)?;
+        let provider_factory = revmc_toolkit_utils::evm::make_provider_factory(&reth_db_path()?)?;
+        return utils::account_code_at(&provider_factory, address, block_num);
+    }
+    let decoded = hex::decode(target)?;
+    if decoded.len() != B256::len_bytes() {
+        return Ok(decoded);
+    }
+    let bytecode_hash = B256::from_slice(&decoded);
+    let path = scan_cache(dir_path)?
+        .into_iter()
+        .filter(|entry| entry.is_ok() && entry.bytecode_hashes.contains(&bytecode_hash))
+        .find_map(|entry| match entry.id {
+            CacheEntryId::Artifact(_) => Some(entry.dir.join(BYTECODE_FILE)),
+            CacheEntryId::Batch(_) => Some(entry.dir.join(format!("{bytecode_hash}.bin"))),
+            CacheEntryId::Archive(_) => None,
+        })
+        .ok_or_else(|| {
+            eyre::eyre!("{bytecode_hash} is not in the AOT dir, pass address@block or hex instead")
+        })?;
+    Ok(std::fs::read(path)?)
+}
+
+fn print_report(report: &InspectReport) {
+    println!("Bytecode hash:  {}", report.bytecode_hash);
+    println!("Bytecode size:  {} B", report.bytecode_size);
+    match report.function_size {
+        Some(size) => println!("Function size:  {size} B"),
+        None => println!("Function size:  unknown"),
+    }
+    let analysis = &report.analysis;
+    println!("Metadata size:  {} B", analysis.metadata_size);
+    println!(
+        "Instructions:   {} in {} blocks, {} edges, cyclomatic complexity {}",
+        analysis.instructions,
+        analysis.basic_blocks,
+        analysis.edges,
+        analysis.cyclomatic_complexity
+    );
+    println!(
+        "Jumps:          {} static, {} dynamic, {} JUMPDESTs",
+        analysis.static_jumps, analysis.dynamic_jumps, analysis.jumpdests
+    );
+    match analysis.max_stack_height {
+        Some(height) => println!("Stack height:   at most {height}"),
+        None => println!("Stack height:   unbounded"),
+    }
+    println!("Opcode classes:");
+    for (class, count) in &analysis.opcode_classes {
+        println!("  {:<20} {count}", format!("{class:?}"));
+    }
+    println!("Builtin calls:  {}", report.total_builtin_calls());
+    for (builtin, count) in &report.builtin_calls {
+        println!("  {builtin:<20} {count}");
+    }
+    for (label, path) in [
+        ("Unoptimized IR", &report.unopt_ir),
+        ("Optimized IR", &report.opt_ir),
+        ("Assembly", &report.asm),
+    ] {
+        if let Some(path) = path {
+            println!("{label:<15} {}", path.display());
+        }
+    }
+    println!("{:<15} {}", "Object", report.object.display());
+    println!("{:<15} {}", "CFG", report.cfg.display());
+}
@@ -1,5 +1,9 @@
+mod autotune;
 mod benches;
+mod cache;
 mod cli;
+mod compile;
+mod inspect;
 mod runners;
 mod utils;
 
@@ -17,16 +21,24 @@ use utils::{
 };
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    if let Commands::CompileWorker = cli.command {
+        // Responses go to stdout, so logs must not
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
     dotenv::dotenv()?;
 
-    let reth_db_path: PathBuf = std::env::var("RETH_DB_PATH")?.parse()?;
     let dir_path = revmc_toolkit_build::default_dir();
 
-    let cli = Cli::parse();
     match cli.command {
         Commands::Run(run_args) => {
-            let mut config = RunConfig::new(dir_path, reth_db_path, BytecodeSelection::Selected);
+            let mut config = RunConfig::new(dir_path, reth_db_path()?, BytecodeSelection::Selected);
+            config.set_policy(utils::compile_policy()?);
+            config.set_remote_cache(utils::remote_cache()?);
 
             match run_args {
                 RunArgsCli::Tx {
@@ -74,7 +86,9 @@ fn main() -> Result<()> {
             }
         }
         Commands::Bench(bench_args) => {
-            let mut config = RunConfig::new(dir_path, reth_db_path, BytecodeSelection::Selected);
+            let mut config = RunConfig::new(dir_path, reth_db_path()?, BytecodeSelection::Selected);
+            config.set_policy(utils::compile_policy()?);
+            config.set_remote_cache(utils::remote_cache()?);
 
             match *bench_args {
                 BenchType::Tx {
@@ -126,6 +140,28 @@ fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Cache(command) => {
+            cache::run_cache_command(dir_path, command, reth_db_path)?;
+        }
+        Commands::Inspect(args) => {
+            inspect::run_inspect_command(dir_path, args, reth_db_path)?;
+        }
+        Commands::Compile(args) => {
+            compile::run_compile_command(dir_path, args, reth_db_path)?;
+        }
+        Commands::Autotune(args) => {
+            autotune::run_autotune_command(dir_path, args, reth_db_path)?;
+        }
+        Commands::CompileWorker => {
+            revmc_toolkit_build::run_compile_worker(
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+            )?;
+        }
     }
     Ok(())
 }
+
+fn reth_db_path() -> Result<PathBuf> {
+    Ok(std::env::var("RETH_DB_PATH")?.parse()?)
+}
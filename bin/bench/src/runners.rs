@@ -17,16 +17,20 @@ impl RunConfig<PathBuf, BytecodeSelection> {
         println!("TxHash: {tx_hash:?}");
 
         let provider_factory = make_provider_factory(&self.reth_db_path)?;
-        let (ext_ctx, is_native_exe) = match &run_type {
+        let ext_ctx = match &run_type {
             SimRunType::AOTCompiled | SimRunType::JITCompiled => {
                 let bytecodes = self
                     .compile_selection
                     .bytecodes(provider_factory.clone(), Some(vec![tx_hash]))?;
-                let ctx = sim_utils::make_ext_ctx(&run_type, &bytecodes, Some(self.compile_opt()))?
-                    .with_touch_tracking();
-                (ctx, false)
+                sim_utils::make_ext_ctx(&run_type, &bytecodes, Some(self.compile_opt()))?
+                    .with_touch_tracking()
+            }
+            // Tiered compiles what gets hot, lazy loads only what is already in the AOT dir
+            SimRunType::TieredJIT | SimRunType::AOTLazy => {
+                sim_utils::make_ext_ctx(&run_type, &[], Some(self.compile_opt()))?
+                    .with_touch_tracking()
             }
-            SimRunType::Native => (RevmcExtCtx::default().with_touch_tracking(), true),
+            SimRunType::Native => RevmcExtCtx::default().with_touch_tracking(),
         };
 
         let mut sim = SimConfig::new(provider_factory.clone(), ext_ctx).make_tx_sim(tx_hash)?;
@@ -36,7 +40,7 @@ impl RunConfig<PathBuf, BytecodeSelection> {
             &provider_factory,
             &mut sim,
             vec![tx_hash],
-            is_native_exe,
+            (&run_type).into(),
         )?;
 
         println!("Elapsed: {:?}", elapsed);
@@ -64,7 +68,6 @@ impl RunConfig<PathBuf, BytecodeSelection> {
             block_txs = block_chunk.split_txs(block_txs).0;
         }
 
-        let is_native_exe = matches!(run_type, SimRunType::Native);
         let ext_ctx = match run_type {
             SimRunType::AOTCompiled | SimRunType::JITCompiled => {
                 let bytecodes = self
@@ -73,6 +76,10 @@ impl RunConfig<PathBuf, BytecodeSelection> {
                 sim_utils::make_ext_ctx(&run_type, &bytecodes, Some(self.compile_opt()))?
                     .with_touch_tracking()
             }
+            SimRunType::TieredJIT | SimRunType::AOTLazy => {
+                sim_utils::make_ext_ctx(&run_type, &[], Some(self.compile_opt()))?
+                    .with_touch_tracking()
+            }
             SimRunType::Native => RevmcExtCtx::default().with_touch_tracking(),
         };
 
@@ -80,7 +87,12 @@ impl RunConfig<PathBuf, BytecodeSelection> {
             .make_block_sim(block_num, block_chunk)?;
         let (_result, elapsed) = bench_utils::time_fn(|| sim.run())?;
 
-        bench_utils::check_tx_sim_validity(&provider_factory, &mut sim, block_txs, is_native_exe)?;
+        bench_utils::check_tx_sim_validity(
+            &provider_factory,
+            &mut sim,
+            block_txs,
+            (&run_type).into(),
+        )?;
 
         println!("Elapsed: {:?}", elapsed);
 
@@ -108,5 +120,8 @@ impl<T, U> RunConfig<T, U> {
         CompilerOptions::default()
             .with_opt_lvl(self.comp_opt_level.clone())
             .with_out_dir(self.aot_dir_path.clone())
+            .with_signing_key(crate::utils::aot_signing_key_path())
+            .with_policy(self.policy.clone())
+            .with_remote_cache(self.remote_cache.clone())
     }
 }
@@ -1,4 +1,4 @@
-use revmc_toolkit_build::OptimizationLevelDeseralizable;
+use revmc_toolkit_build::{OptimizationLevelDeseralizable, OptionsPolicy, RemoteCache};
 use std::time::{Duration, Instant};
 use tracing::info;
 
@@ -48,6 +48,8 @@ pub(crate) struct RunConfig<T, U> {
     pub reth_db_path: T,
     pub compile_selection: U,
     pub comp_opt_level: OptimizationLevelDeseralizable,
+    pub policy: Option<OptionsPolicy>,
+    pub remote_cache: Option<RemoteCache>,
 }
 
 use eyre::{OptionExt, Result};
@@ -57,16 +59,44 @@ use revm::primitives::B256;
 use revmc_toolkit_load::RevmcExtCtx;
 use revmc_toolkit_sim::sim_builder::{Simulation, StateProviderCacheDB};
 use std::path::PathBuf;
+
+use super::sim::SimRunType;
 use tracing::warn;
 
+/// How the frames of a simulation are expected to execute, see `check_tx_sim_validity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedExecution {
+    /// Every frame is interpreted.
+    Native,
+    /// Every frame runs compiled.
+    Compiled,
+    /// Frames are interpreted until their contract gets hot and then run compiled, so any mix
+    /// is valid.
+    Mixed,
+}
+
+impl From<&SimRunType> for ExpectedExecution {
+    fn from(run_type: &SimRunType) -> Self {
+        match run_type {
+            SimRunType::Native => ExpectedExecution::Native,
+            SimRunType::TieredJIT => ExpectedExecution::Mixed,
+            SimRunType::AOTCompiled | SimRunType::JITCompiled | SimRunType::AOTLazy => {
+                ExpectedExecution::Compiled
+            }
+        }
+    }
+}
+
 // todo: check pre-execution result + optimize for sequential txs
 // Expect some native touches for cases where bytecode of the contract is changed during the block execution
 pub fn check_tx_sim_validity(
     provider_factory: &ProviderFactory<DatabaseEnv>,
     sim: &mut Simulation<RevmcExtCtx, StateProviderCacheDB>,
     tx_hashes: Vec<B256>,
-    native_exe: bool,
+    expected: ExpectedExecution,
 ) -> Result<()> {
+    let native_exe = expected == ExpectedExecution::Native;
+    let compiled_exe = expected == ExpectedExecution::Compiled;
     let sim_results = sim.run()?;
 
     for (i, tx_hash) in tx_hashes.into_iter().enumerate() {
@@ -120,6 +150,13 @@ pub fn check_tx_sim_validity(
         overall += touch_counter.overall;
         non_native += touch_counter.non_native;
 
+        if touch_counter.forced_native > 0 {
+            info!(
+                "{account:?} was forced to the interpreter {} times",
+                touch_counter.forced_native
+            );
+        }
+
         if touch_counter.overall == 0 {
             warn!("invalid touch count for {account:?}: expected >0");
         }
@@ -130,7 +167,7 @@ pub fn check_tx_sim_validity(
                 ratio * 100.
             );
         }
-        if !native_exe && (touch_counter.non_native != touch_counter.overall) {
+        if compiled_exe && (touch_counter.non_native != touch_counter.overall) {
             let _ratio = touch_counter.non_native as f32 / touch_counter.overall as f32;
             // todo: will always be wrong for gas guzzlers
             // warn!("invalid touch count for {account:?}: expected all non-native, found {:.2?}%", ratio*100.);
@@ -149,7 +186,11 @@ pub fn check_tx_sim_validity(
             return Err(eyre::eyre!("{msg}"));
         }
     }
-    if !native_exe && (non_native != overall) {
+    if expected == ExpectedExecution::Mixed && overall > 0 {
+        let ratio = non_native as f32 / overall as f32;
+        info!("{:.2?}% of touches ran compiled", ratio * 100.);
+    }
+    if compiled_exe && (non_native != overall) {
         let ratio = non_native as f32 / overall as f32;
         // todo: will always be wrong for gas guzzlers
         let msg = format!(
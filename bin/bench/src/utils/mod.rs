@@ -1,6 +1,11 @@
 pub mod bench;
 pub mod sim;
 
+use reth_db::DatabaseEnv;
+use reth_provider::{ProviderFactory, StateProvider};
+use revm::primitives::Address;
+use std::str::FromStr;
+
 pub fn make_dir(dir_path: &std::path::PathBuf) -> eyre::Result<()> {
     if !dir_path.exists() {
         std::fs::create_dir_all(dir_path)?;
@@ -14,3 +19,70 @@ pub fn epoch_now() -> eyre::Result<u64> {
         .as_secs();
     Ok(epoch)
 }
+
+/// Key used to sign AOT artifacts and to verify them on load.
+pub fn aot_signing_key_path() -> Option<std::path::PathBuf> {
+    std::env::var("AOT_SIGNING_KEY_PATH").ok().map(Into::into)
+}
+
+/// Compile workers running the hidden `compile-worker` subcommand of this executable.
+pub fn compile_workers(
+    workers: Option<usize>,
+) -> eyre::Result<Option<revmc_toolkit_build::WorkerConfig>> {
+    let Some(workers) = workers else {
+        return Ok(None);
+    };
+    let config = revmc_toolkit_build::WorkerConfig::new(std::env::current_exe()?)
+        .with_args(["compile-worker"])
+        .with_workers(workers);
+    Ok(Some(config))
+}
+
+/// Per-contract compiler option overrides, read from the file at `COMPILE_POLICY_PATH`.
+pub fn compile_policy() -> eyre::Result<Option<revmc_toolkit_build::OptionsPolicy>> {
+    std::env::var("COMPILE_POLICY_PATH")
+        .ok()
+        .map(|path| revmc_toolkit_build::OptionsPolicy::read(path.as_ref()))
+        .transpose()
+}
+
+/// Remote AOT artifact cache at `AOT_REMOTE_CACHE_URL`, eg. `http://localhost:8080`.
+pub fn remote_cache() -> eyre::Result<Option<revmc_toolkit_build::RemoteCache>> {
+    std::env::var("AOT_REMOTE_CACHE_URL")
+        .ok()
+        .map(|url| revmc_toolkit_build::RemoteCache::new(&url))
+        .transpose()
+}
+
+/// Contracts forced to the interpreter, read from the file at `AOT_OVERRIDES_PATH`.
+pub fn execution_overrides() -> eyre::Result<Option<revmc_toolkit_load::ExecutionOverrides>> {
+    std::env::var("AOT_OVERRIDES_PATH")
+        .ok()
+        .map(|path| revmc_toolkit_load::ExecutionOverrides::read(path.as_ref()))
+        .transpose()
+}
+
+/// Whether loaded AOT functions are compared against the interpreter before use.
+pub fn aot_smoke_test() -> bool {
+    std::env::var("AOT_SMOKE_TEST").is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Parses an account given as `address@block`.
+pub fn parse_account_at(account: &str) -> eyre::Result<(Address, u64)> {
+    let (address, block_num) = account
+        .split_once('@')
+        .ok_or_else(|| eyre::eyre!("Expected address@block, found {account}"))?;
+    Ok((Address::from_str(address)?, block_num.parse()?))
+}
+
+pub fn account_code_at(
+    provider_factory: &ProviderFactory<DatabaseEnv>,
+    address: Address,
+    block_num: u64,
+) -> eyre::Result<Vec<u8>> {
+    let code = provider_factory
+        .history_by_block_number(block_num)?
+        .account_code(address)?
+        .ok_or_else(|| eyre::eyre!("No code at {address} in block {block_num}"))?;
+    Ok(code.original_bytes().into())
+}
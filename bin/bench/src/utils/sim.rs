@@ -8,9 +8,10 @@ use revm::{
 use eyre::Result;
 use std::str::FromStr;
 
-use revmc_toolkit_build::CompilerOptions;
+use revmc_toolkit_build::{quarantine_errors_with, CompileSummary, CompilerOptions};
 use revmc_toolkit_load::{
-    revmc_register_handler, EvmCompilerFnLoader, EvmCompilerFns, RevmcExtCtx,
+    revmc_register_handler, EvmCompilerFnLoader, EvmCompilerFns, LazyAotFns, RevmcExtCtx,
+    TieredJit, TieredJitConfig,
 };
 use revmc_toolkit_sim::sim_builder::{
     self, BlockPart, CallSimBuilderExt, Simulation, StateProviderCacheDB, TxsSimBuilderExt,
@@ -86,6 +87,8 @@ pub enum SimRunType {
     Native,
     AOTCompiled,
     JITCompiled,
+    TieredJIT,
+    AOTLazy,
 }
 
 impl FromStr for SimRunType {
@@ -96,6 +99,8 @@ impl FromStr for SimRunType {
             "native" => Ok(SimRunType::Native),
             "jit" => Ok(SimRunType::JITCompiled),
             "aot" => Ok(SimRunType::AOTCompiled),
+            "tiered" => Ok(SimRunType::TieredJIT),
+            "aot-lazy" => Ok(SimRunType::AOTLazy),
             _ => Err(eyre::eyre!("Invalid run type")),
         }
     }
@@ -106,6 +111,32 @@ pub fn make_ext_ctx(
     bytecodes: &[Vec<u8>],
     compile_opt: Option<CompilerOptions>,
 ) -> Result<RevmcExtCtx> {
+    let ext_ctx = make_ext_ctx_without_overrides(run_type, bytecodes, compile_opt)?;
+    Ok(match super::execution_overrides()? {
+        Some(overrides) => ext_ctx.with_overrides(overrides),
+        None => ext_ctx,
+    })
+}
+
+fn make_ext_ctx_without_overrides(
+    run_type: &SimRunType,
+    bytecodes: &[Vec<u8>],
+    compile_opt: Option<CompilerOptions>,
+) -> Result<RevmcExtCtx> {
+    if let SimRunType::TieredJIT = run_type {
+        let config =
+            TieredJitConfig::default().with_compiler_options(compile_opt.unwrap_or_default());
+        return Ok(RevmcExtCtx::default().with_tiered_jit(TieredJit::new(config)));
+    }
+    if let SimRunType::AOTLazy = run_type {
+        let compile_opt = compile_opt.unwrap_or_default();
+        let lazy_aot = LazyAotFns::new(
+            &compile_opt,
+            compile_opt.read_signing_key()?,
+            super::aot_smoke_test(),
+        );
+        return Ok(RevmcExtCtx::default().with_lazy_aot(lazy_aot));
+    }
     make_compiled_fns(run_type, bytecodes, compile_opt).map(Into::into)
 }
 
@@ -114,21 +145,58 @@ pub fn make_compiled_fns(
     bytecodes: &[Vec<u8>],
     compile_opt: Option<CompilerOptions>,
 ) -> Result<EvmCompilerFns> {
+    let (compiled_fns, summary) = make_compiled_fns_with_summary(run_type, bytecodes, compile_opt)?;
+    summary.log();
+    Ok(compiled_fns)
+}
+
+/// Contracts that fail to compile or load are skipped instead of failing the run.
+pub fn make_compiled_fns_with_summary(
+    run_type: &SimRunType,
+    bytecodes: &[Vec<u8>],
+    compile_opt: Option<CompilerOptions>,
+) -> Result<(EvmCompilerFns, CompileSummary)> {
     Ok(match run_type {
-        SimRunType::Native => EvmCompilerFns::default(),
+        SimRunType::Native | SimRunType::TieredJIT | SimRunType::AOTLazy => Default::default(),
         SimRunType::JITCompiled => {
-            revmc_toolkit_build::compile_contracts_jit(bytecodes, compile_opt)?.into()
+            let out = revmc_toolkit_build::compile_contracts_jit(bytecodes, compile_opt)?;
+            let summary = CompileSummary {
+                rejected: out.rejected.clone(),
+                errors: out.errors.clone(),
+                ..Default::default()
+            };
+            (out.into(), summary)
         }
         SimRunType::AOTCompiled => {
             let compile_opt = compile_opt.unwrap_or_default();
             let aot_out_dir = compile_opt.out_dir.clone();
-            let bytecode_hashes = bytecodes.iter().map(keccak256).collect();
-            revmc_toolkit_build::compile_contracts_aot(bytecodes, Some(compile_opt))?
-                .into_iter()
-                .collect::<Result<Vec<_>>>()?;
-            EvmCompilerFnLoader::new(&aot_out_dir)
-                .load_selected(bytecode_hashes)
-                .into()
+            let spec_id = compile_opt.spec_id;
+            let loader = EvmCompilerFnLoader::new(&aot_out_dir)
+                .with_contract_options(&compile_opt, bytecodes)
+                .with_verification_key(compile_opt.read_signing_key()?)
+                .with_smoke_test(super::aot_smoke_test());
+            if compile_opt.aot_batch_size.is_some() {
+                let (batch_dirs, summary) = revmc_toolkit_build::compile_contracts_aot_batched(
+                    bytecodes,
+                    Some(compile_opt),
+                )?;
+                ((spec_id, loader.load_batches(&batch_dirs)).into(), summary)
+            } else {
+                let mut summary =
+                    revmc_toolkit_build::compile_contracts_aot(bytecodes, Some(compile_opt))?;
+                let skipped = summary.skipped_hashes();
+                let bytecode_hashes = bytecodes
+                    .iter()
+                    .map(keccak256)
+                    .filter(|bytecode_hash| !skipped.contains(bytecode_hash))
+                    .collect();
+                let (compiled_fns, load_errors) = loader.load_many(bytecode_hashes);
+                quarantine_errors_with(&aot_out_dir, &load_errors, |bytecode_hash| {
+                    loader.options_for(bytecode_hash)
+                })?;
+                summary.errors.extend(load_errors);
+                ((spec_id, compiled_fns).into(), summary)
+            }
         }
     })
 }
@@ -20,4 +20,6 @@ pub struct Cli {
         help = "If true instead of bytecode hash of bytecode will be returned"
     )]
     pub hashed: bool,
+    #[arg(long, help = "Also report the initcode of creates")]
+    pub include_initcode: bool,
 }
@@ -13,7 +13,12 @@ use std::path::Path;
 fn main() -> Result<()> {
     let args = cli::Cli::parse();
 
-    let gas_guzzlers = find_gas_guzzlers(args.start_block, args.end_block, args.sample_size)?;
+    let gas_guzzlers = find_gas_guzzlers(
+        args.start_block,
+        args.end_block,
+        args.sample_size,
+        args.include_initcode,
+    )?;
     let parsed = parse_gas_guzzlers(gas_guzzlers, args.take, args.gas_limit);
     stdout(parsed, args.hashed)?;
 
@@ -24,12 +29,14 @@ fn find_gas_guzzlers(
     start_block: u64,
     end_block: u64,
     sample_size: u64,
+    include_initcode: bool,
 ) -> Result<Vec<BytecodeStat<Bytecode>>> {
     let provider_factory = make_provider_factory()?;
     Ok(GasGuzzlerConfig::default()
         .with_start_block(start_block)
         .with_end_block(end_block)
         .with_sample_size(sample_size)
+        .with_include_initcode(include_initcode)
         .find_gas_guzzlers(provider_factory.clone())?
         .into_top_guzzlers_stats(None))
 }
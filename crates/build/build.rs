@@ -0,0 +1,20 @@
+// Records the revmc revision the toolkit is built against, so that AOT artifacts can be
+// invalidated once the compiler changes. It's read from the dependency declared in this crate's
+// own manifest, which is there wherever the crate is built, and pinned to a revision so that the
+// compiled revmc can't differ from it.
+fn main() {
+    let manifest_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+
+    let manifest = std::fs::read_to_string(&manifest_path).expect("failed to read Cargo.toml");
+    let version = revmc_version(&manifest)
+        .expect("revmc must be a dependency of revmc-toolkit-build pinned to a git `rev`");
+    println!("cargo:rustc-env=REVMC_VERSION={version}");
+}
+
+fn revmc_version(manifest: &str) -> Option<String> {
+    let manifest: toml::Table = manifest.parse().ok()?;
+    let revmc = manifest.get("dependencies")?.get("revmc")?;
+    let rev = revmc.get("rev")?.as_str()?;
+    Some(format!("git+{}", rev.get(..8).unwrap_or(rev)))
+}
@@ -0,0 +1,194 @@
+use revm::{interpreter::opcode::JUMPDEST, primitives::B256};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::analysis::{disassemble, metadata_len, OpcodeClass};
+
+/// Limits on the contracts that are compiled at all, so that pathological bytecodes (eg.
+/// near the size limit with thousands of JUMPDESTs) don't hold up the compile pool for
+/// minutes in LLVM. Rejected contracts are left to the interpreter. No limits by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionPolicy {
+    pub max_bytecode_size: Option<usize>,
+    pub max_jumpdests: Option<usize>,
+    /// See `estimate_ir_size`.
+    pub max_estimated_ir_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "limit")]
+pub enum RejectReason {
+    BytecodeSize { size: usize, max: usize },
+    Jumpdests { count: usize, max: usize },
+    EstimatedIrSize { estimate: usize, max: usize },
+}
+
+/// A contract the admission policy left to the interpreter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rejection {
+    pub bytecode_hash: B256,
+    pub reason: RejectReason,
+}
+
+impl AdmissionPolicy {
+    pub fn with_max_bytecode_size(mut self, max_bytecode_size: Option<usize>) -> Self {
+        self.max_bytecode_size = max_bytecode_size;
+        self
+    }
+    pub fn with_max_jumpdests(mut self, max_jumpdests: Option<usize>) -> Self {
+        self.max_jumpdests = max_jumpdests;
+        self
+    }
+    pub fn with_max_estimated_ir_size(mut self, max_estimated_ir_size: Option<usize>) -> Self {
+        self.max_estimated_ir_size = max_estimated_ir_size;
+        self
+    }
+
+    /// Why the contract isn't compiled, if it exceeds any limit. The cheap checks go first,
+    /// and the bytecode is only disassembled if a limit needs it.
+    pub fn reject_reason(&self, bytecode: &[u8]) -> Option<RejectReason> {
+        if let Some(max) = self.max_bytecode_size {
+            if bytecode.len() > max {
+                return Some(RejectReason::BytecodeSize {
+                    size: bytecode.len(),
+                    max,
+                });
+            }
+        }
+        if self.max_jumpdests.is_none() && self.max_estimated_ir_size.is_none() {
+            return None;
+        }
+        let code = &bytecode[..bytecode.len() - metadata_len(bytecode)];
+        let instructions = disassemble(code);
+        if let Some(max) = self.max_jumpdests {
+            let count = instructions.iter().filter(|i| i.opcode == JUMPDEST).count();
+            if count > max {
+                return Some(RejectReason::Jumpdests { count, max });
+            }
+        }
+        if let Some(max) = self.max_estimated_ir_size {
+            let estimate = estimate_ir_size(instructions.iter().map(|i| i.opcode));
+            if estimate > max {
+                return Some(RejectReason::EstimatedIrSize { estimate, max });
+            }
+        }
+        None
+    }
+
+    pub(crate) fn check(&self, bytecode_hash: B256, bytecode: &[u8]) -> Option<Rejection> {
+        self.reject_reason(bytecode).map(|reason| Rejection {
+            bytecode_hash,
+            reason,
+        })
+    }
+}
+
+/// Rough number of LLVM IR instructions revmc emits for the opcodes, before optimization.
+/// Every opcode comes with gas and stack checks, opcodes calling into builtins also check
+/// the returned status, and every JUMPDEST adds a case to the dynamic jump table.
+pub fn estimate_ir_size(opcodes: impl IntoIterator<Item = u8>) -> usize {
+    opcodes
+        .into_iter()
+        .map(|op| match OpcodeClass::of(op) {
+            OpcodeClass::Push | OpcodeClass::Dup | OpcodeClass::Swap | OpcodeClass::Stack => 6,
+            OpcodeClass::Arithmetic | OpcodeClass::Logic => 10,
+            OpcodeClass::ControlFlow => 12,
+            OpcodeClass::Halt | OpcodeClass::Invalid => 4,
+            OpcodeClass::Memory => 20,
+            OpcodeClass::Keccak
+            | OpcodeClass::Environment
+            | OpcodeClass::Block
+            | OpcodeClass::Storage
+            | OpcodeClass::Log
+            | OpcodeClass::Call => 16,
+        })
+        .sum()
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::BytecodeSize { size, max } => {
+                write!(f, "bytecode size {size} exceeds {max}")
+            }
+            RejectReason::Jumpdests { count, max } => {
+                write!(f, "{count} JUMPDESTs exceed {max}")
+            }
+            RejectReason::EstimatedIrSize { estimate, max } => {
+                write!(f, "estimated IR size {estimate} exceeds {max}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rejected: {}", self.bytecode_hash, self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PUSH1 3, JUMP, JUMPDEST, JUMPDEST, STOP
+    const CODE: [u8; 6] = [0x60, 0x03, 0x56, 0x5b, 0x5b, 0x00];
+
+    #[test]
+    fn no_limits_admit_everything() {
+        assert_eq!(AdmissionPolicy::default().reject_reason(&CODE), None);
+    }
+
+    #[test]
+    fn limits_reject_larger_contracts() {
+        let policy = AdmissionPolicy::default().with_max_bytecode_size(Some(5));
+        assert_eq!(
+            policy.reject_reason(&CODE),
+            Some(RejectReason::BytecodeSize { size: 6, max: 5 })
+        );
+        let policy = AdmissionPolicy::default().with_max_jumpdests(Some(1));
+        assert_eq!(
+            policy.reject_reason(&CODE),
+            Some(RejectReason::Jumpdests { count: 2, max: 1 })
+        );
+        let estimate = estimate_ir_size([0x60, 0x56, 0x5b, 0x5b, 0x00]);
+        let policy = AdmissionPolicy::default().with_max_estimated_ir_size(Some(estimate - 1));
+        assert_eq!(
+            policy.reject_reason(&CODE),
+            Some(RejectReason::EstimatedIrSize {
+                estimate,
+                max: estimate - 1
+            })
+        );
+        let policy = AdmissionPolicy::default()
+            .with_max_bytecode_size(Some(6))
+            .with_max_jumpdests(Some(2))
+            .with_max_estimated_ir_size(Some(estimate));
+        assert_eq!(policy.reject_reason(&CODE), None);
+    }
+
+    #[test]
+    fn policy_and_rejection_round_trip_through_json() {
+        let policy = AdmissionPolicy::default()
+            .with_max_bytecode_size(Some(24576))
+            .with_max_jumpdests(Some(1000));
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(
+            serde_json::from_str::<AdmissionPolicy>(&json).unwrap(),
+            policy
+        );
+
+        let rejection = policy
+            .with_max_jumpdests(Some(1))
+            .check(B256::repeat_byte(1), &CODE)
+            .unwrap();
+        let json = serde_json::to_value(&rejection).unwrap();
+        assert_eq!(json["reason"]["limit"], "jumpdests");
+        let read = serde_json::from_value::<Rejection>(json).unwrap();
+        assert_eq!(read.bytecode_hash, rejection.bytecode_hash);
+        assert_eq!(read.reason, rejection.reason);
+    }
+}
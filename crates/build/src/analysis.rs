@@ -0,0 +1,520 @@
+use revm::interpreter::{
+    opcode::{
+        self, OpCode, INVALID, JUMP, JUMPDEST, JUMPI, PUSH0, PUSH1, PUSH32, RETURN, REVERT,
+        SELFDESTRUCT, STOP,
+    },
+    STACK_LIMIT,
+};
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Write},
+    ops::Range,
+};
+
+/// File the CFG of an inspected contract is written to, in DOT format.
+pub const CFG_FILE: &str = "cfg.dot";
+
+/// Coarse grouping of opcodes, to compare contracts by what they spend their code on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OpcodeClass {
+    Arithmetic,
+    /// Comparison and bitwise operations.
+    Logic,
+    Keccak,
+    Environment,
+    Block,
+    Stack,
+    Memory,
+    Storage,
+    /// JUMP, JUMPI and JUMPDEST.
+    ControlFlow,
+    /// STOP, RETURN, REVERT and SELFDESTRUCT.
+    Halt,
+    Push,
+    Dup,
+    Swap,
+    Log,
+    /// Calls and creates.
+    Call,
+    /// INVALID and opcodes undefined for legacy bytecode.
+    Invalid,
+}
+
+impl OpcodeClass {
+    pub fn of(op: u8) -> Self {
+        match op {
+            opcode::ADD..=opcode::SIGNEXTEND => Self::Arithmetic,
+            opcode::LT..=opcode::SAR => Self::Logic,
+            opcode::KECCAK256 => Self::Keccak,
+            opcode::ADDRESS..=opcode::EXTCODEHASH | opcode::PC | opcode::GAS => Self::Environment,
+            opcode::BLOCKHASH..=opcode::BLOBBASEFEE => Self::Block,
+            opcode::POP => Self::Stack,
+            opcode::MLOAD | opcode::MSTORE | opcode::MSTORE8 | opcode::MSIZE | opcode::MCOPY => {
+                Self::Memory
+            }
+            opcode::SLOAD | opcode::SSTORE | opcode::TLOAD | opcode::TSTORE => Self::Storage,
+            JUMP | JUMPI | JUMPDEST => Self::ControlFlow,
+            STOP | RETURN | REVERT | SELFDESTRUCT => Self::Halt,
+            PUSH0..=PUSH32 => Self::Push,
+            opcode::DUP1..=opcode::DUP16 => Self::Dup,
+            opcode::SWAP1..=opcode::SWAP16 => Self::Swap,
+            opcode::LOG0..=opcode::LOG4 => Self::Log,
+            opcode::CREATE
+            | opcode::CALL
+            | opcode::CALLCODE
+            | opcode::DELEGATECALL
+            | opcode::CREATE2
+            | opcode::STATICCALL => Self::Call,
+            _ => Self::Invalid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub pc: usize,
+    pub opcode: u8,
+    /// PUSH immediate, shorter than the opcode implies if the code ends within it.
+    pub immediate: Vec<u8>,
+}
+
+impl Instruction {
+    pub fn class(&self) -> OpcodeClass {
+        OpcodeClass::of(self.opcode)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self.class() {
+            OpcodeClass::Invalid if self.opcode != INVALID => "UNKNOWN",
+            _ => OpCode::new(self.opcode).map_or("UNKNOWN", |op| op.as_str()),
+        }
+    }
+
+    /// Whether execution can't continue with the next instruction.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self.class(), OpcodeClass::Halt | OpcodeClass::Invalid) || self.opcode == JUMP
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.opcode == JUMP || self.opcode == JUMPI
+    }
+
+    /// Value pushed by a PUSH, if it fits a `usize`. Missing immediate bytes read as zero.
+    pub fn push_value(&self) -> Option<usize> {
+        if !(PUSH0..=PUSH32).contains(&self.opcode) {
+            return None;
+        }
+        let len = (self.opcode - PUSH0) as usize;
+        let mut value = 0usize;
+        for i in 0..len {
+            let byte = self.immediate.get(i).copied().unwrap_or_default();
+            value = value.checked_mul(256)?.checked_add(byte as usize)?;
+        }
+        Some(value)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06x}: {}", self.pc, self.mnemonic())?;
+        if !self.immediate.is_empty() {
+            write!(f, " 0x{}", hex::encode(&self.immediate))?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles legacy bytecode, including any trailing metadata.
+pub fn disassemble(code: &[u8]) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        let imm_len = if (PUSH1..=PUSH32).contains(&op) {
+            (op - PUSH0) as usize
+        } else {
+            0
+        };
+        let imm_end = (pc + 1 + imm_len).min(code.len());
+        instructions.push(Instruction {
+            pc,
+            opcode: op,
+            immediate: code[pc + 1..imm_end].to_vec(),
+        });
+        pc += 1 + imm_len;
+    }
+    instructions
+}
+
+/// Size of the CBOR metadata solc and vyper append to the code, including its two byte
+/// length suffix. Zero if the code doesn't end with metadata.
+pub fn metadata_len(code: &[u8]) -> usize {
+    let Some(suffix) = code.len().checked_sub(2).map(|i| &code[i..]) else {
+        return 0;
+    };
+    let len = u16::from_be_bytes([suffix[0], suffix[1]]) as usize + 2;
+    // Metadata is a CBOR map with a few entries
+    match code
+        .len()
+        .checked_sub(len)
+        .filter(|start| *start > 0)
+        .map(|start| code[start])
+    {
+        Some(0xa1..=0xa5) => len,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Pc of the first instruction.
+    pub start: usize,
+    /// Indices into `Cfg::instructions`.
+    pub instructions: Range<usize>,
+    /// Indices of the blocks control can flow to, without the targets of a dynamic jump.
+    pub successors: Vec<usize>,
+    /// Whether the block ends with a jump whose target is only known at runtime.
+    pub dynamic_jump: bool,
+    /// Whether the block ends with a static jump to a target that isn't a JUMPDEST.
+    pub invalid_jump: bool,
+}
+
+/// Basic blocks of legacy bytecode, including its trailing metadata, as jumps can land in
+/// it. A jump is static if its target is pushed right before it, jumps to a target that
+/// isn't a JUMPDEST have no jump successor.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub instructions: Vec<Instruction>,
+    pub blocks: Vec<BasicBlock>,
+    pub metadata_size: usize,
+}
+
+impl Cfg {
+    pub fn new(bytecode: &[u8]) -> Self {
+        let metadata_size = metadata_len(bytecode);
+        let instructions = disassemble(bytecode);
+
+        let mut starts = vec![];
+        for (i, inst) in instructions.iter().enumerate() {
+            let after_terminator = i > 0 && {
+                let prev = &instructions[i - 1];
+                prev.is_terminator() || prev.opcode == JUMPI
+            };
+            if i == 0 || inst.opcode == JUMPDEST || after_terminator {
+                starts.push(i);
+            }
+        }
+        let block_by_pc = starts
+            .iter()
+            .enumerate()
+            .map(|(block, &i)| (instructions[i].pc, block))
+            .collect::<HashMap<_, _>>();
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block, &start)| {
+                let end = starts.get(block + 1).copied().unwrap_or(instructions.len());
+                let last = &instructions[end - 1];
+                let mut successors = vec![];
+                let (mut dynamic_jump, mut invalid_jump) = (false, false);
+                if last.is_jump() {
+                    let push = (end - 1 > start)
+                        .then(|| &instructions[end - 2])
+                        .filter(|prev| prev.class() == OpcodeClass::Push);
+                    match push {
+                        // Targets too large for a `usize` can't be a JUMPDEST either
+                        Some(push) => {
+                            let target = push
+                                .push_value()
+                                .and_then(|target| block_by_pc.get(&target))
+                                .filter(|&&block| instructions[starts[block]].opcode == JUMPDEST);
+                            invalid_jump = target.is_none();
+                            successors.extend(target);
+                        }
+                        None => dynamic_jump = true,
+                    }
+                }
+                if !last.is_terminator() && end < instructions.len() {
+                    successors.push(block + 1);
+                }
+                BasicBlock {
+                    start: instructions[start].pc,
+                    instructions: start..end,
+                    successors,
+                    dynamic_jump,
+                    invalid_jump,
+                }
+            })
+            .collect();
+
+        Self {
+            instructions,
+            blocks,
+            metadata_size,
+        }
+    }
+
+    pub fn block_instructions(&self, block: &BasicBlock) -> &[Instruction] {
+        &self.instructions[block.instructions.clone()]
+    }
+
+    pub fn edges(&self) -> usize {
+        self.blocks.iter().map(|b| b.successors.len()).sum()
+    }
+
+    /// Highest stack the code can reach, if it's proven to neither exceed `STACK_LIMIT` nor
+    /// underflow on any path from the entry. Dynamic jumps are assumed to reach every
+    /// JUMPDEST, so it's conservative for code that relies on them. Code that can reach a
+    /// static jump to a target that isn't a JUMPDEST is never proven.
+    pub fn max_stack_height(&self) -> Option<usize> {
+        if self.blocks.is_empty() {
+            return Some(0);
+        }
+        let effects = self
+            .blocks
+            .iter()
+            .map(|block| StackEffect::of(self.block_instructions(block)))
+            .collect::<Vec<_>>();
+        let jumpdests = (0..self.blocks.len())
+            .filter(|&b| self.instructions[self.blocks[b].instructions.start].opcode == JUMPDEST)
+            .collect::<Vec<_>>();
+        // Entry heights as `(min, max)` by block. The extra node stands for the targets of
+        // dynamic jumps and leads to every JUMPDEST.
+        let dynamic = self.blocks.len();
+        let mut entries = vec![None; self.blocks.len() + 1];
+        entries[0] = Some((0usize, 0usize));
+        let mut worklist = vec![0];
+        let mut max_height = 0;
+        while let Some(node) = worklist.pop() {
+            let (min, max) = entries[node].expect("queued nodes have an entry height");
+            let (exit, successors) = if node == dynamic {
+                ((min, max), jumpdests.clone())
+            } else {
+                let (effect, block) = (&effects[node], &self.blocks[node]);
+                if min < effect.required
+                    || max + effect.max_growth > STACK_LIMIT
+                    || block.invalid_jump
+                {
+                    return None;
+                }
+                max_height = max_height.max(max + effect.max_growth);
+                let mut successors = block.successors.clone();
+                if block.dynamic_jump {
+                    successors.push(dynamic);
+                }
+                let exit = (
+                    min.checked_add_signed(effect.diff)?,
+                    max.checked_add_signed(effect.diff)?,
+                );
+                (exit, successors)
+            };
+            // Heights only widen and are bounded, so this terminates
+            for succ in successors {
+                let merged = match entries[succ] {
+                    Some((min, max)) => (min.min(exit.0), max.max(exit.1)),
+                    None => exit,
+                };
+                if entries[succ] != Some(merged) {
+                    entries[succ] = Some(merged);
+                    worklist.push(succ);
+                }
+            }
+        }
+        Some(max_height)
+    }
+
+    pub fn metrics(&self, bytecode_size: usize) -> BytecodeMetrics {
+        let mut opcode_classes = BTreeMap::new();
+        let (mut jumpdests, mut static_jumps, mut dynamic_jumps) = (0, 0, 0);
+        for inst in &self.instructions {
+            *opcode_classes.entry(inst.class()).or_default() += 1;
+            jumpdests += (inst.opcode == JUMPDEST) as usize;
+        }
+        for block in &self.blocks {
+            let is_jump = self.instructions[block.instructions.end - 1].is_jump();
+            match (is_jump, block.dynamic_jump) {
+                (true, true) => dynamic_jumps += 1,
+                (true, false) => static_jumps += 1,
+                _ => {}
+            }
+        }
+        let edges = self.edges();
+        BytecodeMetrics {
+            bytecode_size,
+            metadata_size: self.metadata_size,
+            instructions: self.instructions.len(),
+            basic_blocks: self.blocks.len(),
+            edges,
+            cyclomatic_complexity: (edges + 2).saturating_sub(self.blocks.len()),
+            jumpdests,
+            static_jumps,
+            dynamic_jumps,
+            opcode_classes,
+            max_stack_height: self.max_stack_height(),
+        }
+    }
+
+    /// Graphviz graph of the blocks. Dynamic jumps point to a single `dynamic` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box fontname=monospace];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for inst in self.block_instructions(block) {
+                let _ = write!(label, "{inst}\\l");
+            }
+            let _ = writeln!(dot, "    b{i} [label=\"{label}\"];");
+            for succ in &block.successors {
+                let _ = writeln!(dot, "    b{i} -> b{succ};");
+            }
+            if block.dynamic_jump {
+                let _ = writeln!(dot, "    b{i} -> dynamic [style=dashed];");
+            }
+        }
+        if self.blocks.iter().any(|b| b.dynamic_jump) {
+            dot.push_str("    dynamic [shape=ellipse];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Stack requirements of a basic block, relative to its entry height.
+struct StackEffect {
+    /// Items the block pops below its entry height.
+    required: usize,
+    max_growth: usize,
+    diff: isize,
+}
+
+impl StackEffect {
+    fn of(instructions: &[Instruction]) -> Self {
+        let (mut height, mut required, mut max_growth) = (0isize, 0isize, 0isize);
+        for inst in instructions {
+            // Halts before touching the stack
+            let Some(op) =
+                OpCode::new(inst.opcode).filter(|_| inst.class() != OpcodeClass::Invalid)
+            else {
+                break;
+            };
+            let (inputs, outputs) = (op.inputs() as isize, op.outputs() as isize);
+            required = required.max(inputs - height);
+            height += outputs - inputs;
+            max_growth = max_growth.max(height);
+        }
+        Self {
+            required: required as usize,
+            max_growth: max_growth as usize,
+            diff: height,
+        }
+    }
+}
+
+/// Structure of a contract, to relate compile results to it.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BytecodeMetrics {
+    pub bytecode_size: usize,
+    /// Trailing CBOR metadata, not part of the executable code.
+    pub metadata_size: usize,
+    pub instructions: usize,
+    pub basic_blocks: usize,
+    /// Static CFG edges, dynamic jumps add none.
+    pub edges: usize,
+    /// `edges - basic_blocks + 2` of the static CFG.
+    pub cyclomatic_complexity: usize,
+    pub jumpdests: usize,
+    pub static_jumps: usize,
+    pub dynamic_jumps: usize,
+    pub opcode_classes: BTreeMap<OpcodeClass, usize>,
+    /// See `Cfg::max_stack_height`.
+    pub max_stack_height: Option<usize>,
+}
+
+impl BytecodeMetrics {
+    pub fn of(bytecode: &[u8]) -> Self {
+        Cfg::new(bytecode).metrics(bytecode.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_stack_height(code: &str) -> Option<usize> {
+        Cfg::new(&hex::decode(code).unwrap()).max_stack_height()
+    }
+
+    #[test]
+    fn metadata_len_of_cbor_suffix() {
+        // STOP, then a one entry CBOR map of three bytes and its length
+        assert_eq!(metadata_len(&hex::decode("00a141000003").unwrap()), 5);
+        assert_eq!(metadata_len(&hex::decode("600100").unwrap()), 0);
+        assert_eq!(metadata_len(&hex::decode("00ff41000003").unwrap()), 0);
+        assert_eq!(metadata_len(&[]), 0);
+    }
+
+    #[test]
+    fn disassemble_keeps_truncated_immediates() {
+        let instructions = disassemble(&hex::decode("5b6101").unwrap());
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[1].immediate, vec![0x01]);
+        assert_eq!(instructions[1].push_value(), Some(0x0100));
+    }
+
+    #[test]
+    fn cfg_links_static_jumps_and_fallthrough() {
+        // PUSH0, PUSH1 6, JUMPI | STOP | STOP | JUMPDEST, STOP
+        let cfg = Cfg::new(&hex::decode("5f60065700005b00").unwrap());
+        let starts = cfg.blocks.iter().map(|b| b.start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 4, 5, 6]);
+        assert_eq!(cfg.blocks[0].successors, vec![3, 1]);
+        assert!(!cfg.blocks[0].dynamic_jump && !cfg.blocks[0].invalid_jump);
+        assert!(cfg.blocks[1].successors.is_empty());
+    }
+
+    #[test]
+    fn max_stack_height_of_straight_code() {
+        assert_eq!(max_stack_height(""), Some(0));
+        assert_eq!(max_stack_height("5f5f0100"), Some(2));
+        // ADD on an empty stack
+        assert_eq!(max_stack_height("01"), None);
+    }
+
+    #[test]
+    fn max_stack_height_of_loops() {
+        // JUMPDEST, PUSH1 0, JUMP keeps the height
+        assert_eq!(max_stack_height("5b600056"), Some(1));
+        // JUMPDEST, PUSH0, PUSH1 0, JUMP grows it every iteration
+        assert_eq!(max_stack_height("5b5f600056"), None);
+    }
+
+    #[test]
+    fn max_stack_height_of_dynamic_jumps() {
+        // PUSH0, CALLDATALOAD, JUMP to any JUMPDEST
+        let cfg = Cfg::new(&hex::decode("5f35565b00").unwrap());
+        assert!(cfg.blocks[0].dynamic_jump);
+        assert_eq!(cfg.max_stack_height(), Some(1));
+        // A JUMPDEST that pushes and jumps dynamically again can grow without bound
+        assert_eq!(max_stack_height("5f35565b5f5f3556"), None);
+    }
+
+    #[test]
+    fn max_stack_height_rejects_invalid_jump_targets() {
+        // To a PUSH, into a PUSH immediate and past the end of the code
+        assert_eq!(max_stack_height("600356605b00"), None);
+        assert_eq!(max_stack_height("600456605b00"), None);
+        assert_eq!(max_stack_height("60ff56"), None);
+    }
+
+    #[test]
+    fn max_stack_height_follows_jumps_into_metadata() {
+        // PUSH1 4, JUMP into what looks like metadata: JUMPDEST, PUSH0, PUSH1 4, JUMP
+        let code = hex::decode("600456a15b5f6004560006").unwrap();
+        assert_eq!(metadata_len(&code), 8);
+        assert_eq!(Cfg::new(&code).max_stack_height(), None);
+    }
+}
@@ -0,0 +1,442 @@
+use revm::primitives::B256;
+use revmc::llvm::inkwell::targets::TargetMachine;
+
+use eyre::{OptionExt, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+use tracing::debug;
+
+use crate::cache::{scan_artifact, scan_cache, CacheEntry, CacheEntryId};
+use crate::manifest::{ArtifactOptions, BATCH_INDEX_FILE, MANIFEST_FILE, REVMC_VERSION};
+use crate::utils::tmp_path;
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"RVMCAR01";
+/// Largest header read, as its length comes from the archive.
+const MAX_HEADER_SIZE: u64 = 64 << 20;
+
+/// Machine the artifacts are compiled for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetInfo {
+    pub triple: String,
+    pub cpu: String,
+    /// LLVM feature string, eg. `+sse2,+avx2,-avx512f`.
+    pub features: String,
+}
+
+impl TargetInfo {
+    pub fn host() -> Self {
+        Self {
+            triple: TargetMachine::get_default_triple()
+                .as_str()
+                .to_string_lossy()
+                .into_owned(),
+            cpu: TargetMachine::get_host_cpu_name().to_string(),
+            features: TargetMachine::get_host_cpu_features().to_string(),
+        }
+    }
+
+    /// Resolves `native` to the host, as that's what the compiler does.
+    pub fn of_options(options: &ArtifactOptions, host: &TargetInfo) -> Self {
+        if options.target == "native" {
+            Self {
+                triple: host.triple.clone(),
+                cpu: options
+                    .target_cpu
+                    .clone()
+                    .unwrap_or_else(|| host.cpu.clone()),
+                features: options
+                    .target_features
+                    .clone()
+                    .unwrap_or_else(|| host.features.clone()),
+            }
+        } else {
+            Self {
+                triple: options.target.clone(),
+                cpu: options
+                    .target_cpu
+                    .clone()
+                    .unwrap_or_else(|| "generic".to_string()),
+                features: options.target_features.clone().unwrap_or_default(),
+            }
+        }
+    }
+
+    /// Errors if code compiled for `self` can't run on `host`.
+    pub fn check_runs_on(&self, host: &TargetInfo) -> Result<()> {
+        if self.triple != host.triple {
+            return Err(eyre::eyre!(
+                "compiled for {} but the host is {}",
+                self.triple,
+                host.triple
+            ));
+        }
+        let host_features = enabled_features(&host.features);
+        let missing = enabled_features(&self.features)
+            .difference(&host_features)
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(eyre::eyre!(
+                "host is missing CPU features {}",
+                missing.join(",")
+            ));
+        }
+        // Without features the instruction set is only implied by the CPU
+        if self.features.is_empty() && self.cpu != "generic" && self.cpu != host.cpu {
+            return Err(eyre::eyre!(
+                "compiled for CPU {} but the host is {}",
+                self.cpu,
+                host.cpu
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn enabled_features(features: &str) -> HashSet<&str> {
+    features
+        .split(',')
+        .filter_map(|f| f.strip_prefix('+'))
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveFile {
+    /// Relative to the AOT out dir.
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveEntry {
+    pub bytecode_hashes: Vec<B256>,
+    pub options: ArtifactOptions,
+    pub target: TargetInfo,
+    /// Manifest or index is the last file, so a partial import leaves no loadable entry.
+    pub files: Vec<ArchiveFile>,
+}
+
+/// Header of an archive, followed by the contents of all files in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveManifest {
+    pub revmc_version: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl ArchiveManifest {
+    pub fn bytecode_hashes(&self) -> impl Iterator<Item = &B256> {
+        self.entries.iter().flat_map(|e| &e.bytecode_hashes)
+    }
+}
+
+/// Packs the loadable entries of the AOT out dir into a single archive. If `bytecode_hashes`
+/// is set only the entries holding any of them are exported. Static archives are left out,
+/// as they are linked from the exported objects.
+pub fn export_cache(
+    out_dir: &Path,
+    archive_path: &Path,
+    bytecode_hashes: Option<&HashSet<B256>>,
+) -> Result<ArchiveManifest> {
+    let selected = scan_cache(out_dir)?
+        .into_iter()
+        .filter(|entry| entry.is_ok() && !matches!(entry.id, CacheEntryId::Archive(_)))
+        .filter(|entry| {
+            bytecode_hashes.map_or(true, |hashes| {
+                entry.bytecode_hashes.iter().any(|h| hashes.contains(h))
+            })
+        })
+        .collect::<Vec<_>>();
+    debug!(
+        "Exporting {} entries to {}",
+        selected.len(),
+        archive_path.display()
+    );
+    let mut writer = BufWriter::new(File::create(archive_path)?);
+    let manifest = write_archive(out_dir, selected, &mut writer)?;
+    writer.flush()?;
+    Ok(manifest)
+}
+
+/// Packs a single artifact of the out dir, as archive bytes.
+pub(crate) fn pack_artifact(
+    out_dir: &Path,
+    artifact_dir: &Path,
+    bytecode_hash: B256,
+) -> Result<Vec<u8>> {
+    let entry = scan_artifact(artifact_dir, bytecode_hash)?;
+    if let Some(problem) = &entry.problem {
+        return Err(eyre::eyre!("Broken artifact: {problem}"));
+    }
+    let mut archive = vec![];
+    write_archive(out_dir, vec![entry], &mut archive)?;
+    Ok(archive)
+}
+
+/// Writes the header followed by the files of the entries.
+fn write_archive(
+    out_dir: &Path,
+    selected: Vec<CacheEntry>,
+    writer: &mut impl Write,
+) -> Result<ArchiveManifest> {
+    let host = TargetInfo::host();
+    let mut entries = vec![];
+    let mut paths = vec![];
+    for entry in selected {
+        let options = entry.options.ok_or_eyre("Missing options")?;
+        let mut files = vec![];
+        for dir_entry in std::fs::read_dir(&entry.dir)? {
+            let path = dir_entry?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        // Manifest last, see `ArchiveEntry::files`
+        files.sort_by_key(|path| {
+            let name = path.file_name().and_then(|n| n.to_str());
+            matches!(name, Some(MANIFEST_FILE | BATCH_INDEX_FILE))
+        });
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(out_dir)?;
+                let relative = relative.to_str().ok_or_eyre("Invalid file path")?;
+                let file = ArchiveFile {
+                    path: relative.replace(std::path::MAIN_SEPARATOR, "/"),
+                    size: std::fs::metadata(&path)?.len(),
+                };
+                paths.push(path);
+                Ok(file)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.push(ArchiveEntry {
+            bytecode_hashes: entry.bytecode_hashes,
+            target: TargetInfo::of_options(&options, &host),
+            options,
+            files,
+        });
+    }
+    let manifest = ArchiveManifest {
+        revmc_version: REVMC_VERSION.to_string(),
+        entries,
+    };
+
+    let header = serde_json::to_vec(&manifest)?;
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    for path in paths {
+        std::io::copy(&mut File::open(&path)?, writer)
+            .wrap_err_with(|| format!("Failed to archive {}", path.display()))?;
+    }
+    Ok(manifest)
+}
+
+pub fn read_archive_manifest(archive_path: &Path) -> Result<ArchiveManifest> {
+    read_header(&mut BufReader::new(File::open(archive_path)?))
+}
+
+/// Unpacks an archive into the AOT out dir. Nothing is written unless all entries were
+/// compiled by the current revmc version for a target the host can run.
+pub fn import_cache(out_dir: &Path, archive_path: &Path) -> Result<ArchiveManifest> {
+    debug!("Importing {}", archive_path.display());
+    unpack_archive(
+        out_dir,
+        Path::new(""),
+        BufReader::new(File::open(archive_path)?),
+    )
+}
+
+/// Like `import_cache`, for an archive read from `reader` whose files all have to be within
+/// `within`, relative to the out dir.
+pub(crate) fn unpack_archive(
+    out_dir: &Path,
+    within: &Path,
+    mut reader: impl Read,
+) -> Result<ArchiveManifest> {
+    let manifest = read_header(&mut reader)?;
+    if manifest.revmc_version != REVMC_VERSION {
+        return Err(eyre::eyre!(
+            "Archive was created with revmc {}, current is {REVMC_VERSION}",
+            manifest.revmc_version
+        ));
+    }
+    let host = TargetInfo::host();
+    for entry in &manifest.entries {
+        entry
+            .target
+            .check_runs_on(&host)
+            .wrap_err_with(|| format!("Incompatible entry for {:?}", entry.bytecode_hashes))?;
+        for file in &entry.files {
+            let path = Path::new(&file.path);
+            let is_relative = path.components().all(|c| matches!(c, Component::Normal(_)));
+            if !is_relative || !path.starts_with(within) {
+                return Err(eyre::eyre!("Invalid path in archive: {}", file.path));
+            }
+        }
+    }
+
+    debug!("Unpacking {} entries", manifest.entries.len());
+    for file in manifest.entries.iter().flat_map(|e| &e.files) {
+        let path = out_dir.join(&file.path);
+        revmc_toolkit_utils::misc::make_dir(path.parent().ok_or_eyre("Invalid path")?)?;
+        unpack_file(&mut reader, &path, file.size)
+            .wrap_err_with(|| format!("Failed to unpack {}", file.path))?;
+    }
+    Ok(manifest)
+}
+
+/// Writes the file aside and renames it over `path`, as truncating a library in place would
+/// crash processes that have it loaded.
+fn unpack_file(reader: &mut impl Read, path: &Path, size: u64) -> Result<()> {
+    let tmp = tmp_path(path);
+    let copied = File::create(&tmp)
+        .and_then(|mut dst| std::io::copy(&mut reader.by_ref().take(size), &mut dst));
+    let result = match copied {
+        Ok(copied) if copied == size => std::fs::rename(&tmp, path).map_err(Into::into),
+        Ok(_) => Err(eyre::eyre!("Archive is truncated")),
+        Err(e) => Err(e.into()),
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn read_header(reader: &mut impl Read) -> Result<ArchiveManifest> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(eyre::eyre!("Not an AOT cache archive"));
+    }
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > MAX_HEADER_SIZE {
+        return Err(eyre::eyre!(
+            "Archive header of {len} bytes exceeds the limit of {MAX_HEADER_SIZE}"
+        ));
+    }
+    let mut header = vec![];
+    header
+        .try_reserve_exact(len as usize)
+        .map_err(|e| eyre::eyre!("Failed to allocate the archive header: {e}"))?;
+    reader.by_ref().take(len).read_to_end(&mut header)?;
+    if header.len() as u64 != len {
+        return Err(eyre::eyre!("Archive header is truncated"));
+    }
+    Ok(serde_json::from_slice(&header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_dir, write_artifact};
+    use crate::CompilerOptions;
+
+    #[test]
+    fn export_import_round_trip() {
+        let (src, dst) = (temp_dir("archive-src"), temp_dir("archive-dst"));
+        let options = CompilerOptions::default().artifact_options();
+        let bytecode_hash = write_artifact(&src, &[0x60, 0x00, 0x00], &options);
+        let archive_path = src.join("cache.rvmcar");
+
+        let exported = export_cache(&src, &archive_path, None).unwrap();
+        assert_eq!(
+            exported.bytecode_hashes().collect::<Vec<_>>(),
+            [&bytecode_hash]
+        );
+        let imported = import_cache(&dst, &archive_path).unwrap();
+        assert_eq!(imported.entries.len(), 1);
+
+        let entries = scan_cache(&dst).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_ok(), "{:?}", entries[0].problem);
+        for file in &imported.entries[0].files {
+            let (exported, imported) = (src.join(&file.path), dst.join(&file.path));
+            assert_eq!(
+                std::fs::read(exported).unwrap(),
+                std::fs::read(imported).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn export_filters_by_bytecode_hash() {
+        let dir = temp_dir("archive-filter");
+        let options = CompilerOptions::default().artifact_options();
+        let kept = write_artifact(&dir, &[0x60, 0x01, 0x00], &options);
+        write_artifact(&dir, &[0x60, 0x02, 0x00], &options);
+
+        let only = HashSet::from([kept]);
+        let manifest = export_cache(&dir, &dir.join("cache.rvmcar"), Some(&only)).unwrap();
+        assert_eq!(manifest.bytecode_hashes().collect::<Vec<_>>(), [&kept]);
+    }
+
+    #[test]
+    fn read_header_rejects_oversized_length() {
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.extend(u64::MAX.to_le_bytes());
+        assert!(read_header(&mut archive.as_slice()).is_err());
+    }
+
+    #[test]
+    fn read_header_rejects_truncated_header() {
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.extend(100u64.to_le_bytes());
+        archive.extend(b"{}");
+        assert!(read_header(&mut archive.as_slice()).is_err());
+    }
+
+    #[test]
+    fn unpack_rejects_paths_outside_the_out_dir() {
+        let dir = temp_dir("archive-escape");
+        let manifest = ArchiveManifest {
+            revmc_version: REVMC_VERSION.to_string(),
+            entries: vec![ArchiveEntry {
+                bytecode_hashes: vec![],
+                options: CompilerOptions::default().artifact_options(),
+                target: TargetInfo::host(),
+                files: vec![ArchiveFile {
+                    path: "../escaped".to_string(),
+                    size: 0,
+                }],
+            }],
+        };
+        let header = serde_json::to_vec(&manifest).unwrap();
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.extend((header.len() as u64).to_le_bytes());
+        archive.extend(header);
+        assert!(unpack_archive(&dir.join("out"), Path::new(""), archive.as_slice()).is_err());
+        assert!(!dir.join("escaped").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unpack_replaces_files_instead_of_truncating_them() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (src, dst) = (
+            temp_dir("archive-replace-src"),
+            temp_dir("archive-replace-dst"),
+        );
+        let options = CompilerOptions::default().artifact_options();
+        let bytecode_hash = write_artifact(&src, &[0x60, 0x03, 0x00], &options);
+        let archive_path = src.join("cache.rvmcar");
+        export_cache(&src, &archive_path, None).unwrap();
+        import_cache(&dst, &archive_path).unwrap();
+
+        let library = crate::artifact_dir(&dst, &bytecode_hash, &options).join("a.so");
+        let before = std::fs::metadata(&library).unwrap().ino();
+        import_cache(&dst, &archive_path).unwrap();
+        assert_ne!(std::fs::metadata(&library).unwrap().ino(), before);
+    }
+}
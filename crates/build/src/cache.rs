@@ -0,0 +1,355 @@
+use revm::primitives::B256;
+
+use eyre::Result;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use crate::link::ArtifactFormat;
+use crate::manifest::{
+    ArtifactManifest, ArtifactOptions, BatchIndex, ARCHIVES_DIR, BATCHES_DIR, BATCH_INDEX_FILE,
+    MANIFEST_FILE, REVMC_VERSION, STATIC_LIB_NAME,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEntryId {
+    Artifact(B256),
+    Batch(String),
+    /// Static archive of `ArtifactFormat::StaticArchive`, see `archive_path`.
+    Archive(String),
+}
+
+impl fmt::Display for CacheEntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheEntryId::Artifact(bytecode_hash) => write!(f, "{bytecode_hash}"),
+            CacheEntryId::Batch(batch_id) => write!(f, "batch:{batch_id}"),
+            CacheEntryId::Archive(archive_id) => write!(f, "archive:{archive_id}"),
+        }
+    }
+}
+
+/// Reason why an entry can't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEntryProblem {
+    /// Artifact from before the directories were keyed by compiler options.
+    LegacyLayout,
+    /// Compilation or linking didn't finish, as the manifest is written last.
+    MissingManifest,
+    InvalidManifest(String),
+    /// Compiled by a different revmc version.
+    StaleVersion(String),
+    /// The library of the entry's format is missing.
+    MissingLibrary(String),
+}
+
+impl fmt::Display for CacheEntryProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheEntryProblem::LegacyLayout => write!(f, "legacy layout"),
+            CacheEntryProblem::MissingManifest => write!(f, "missing manifest"),
+            CacheEntryProblem::InvalidManifest(e) => write!(f, "invalid manifest: {e}"),
+            CacheEntryProblem::StaleVersion(version) => {
+                write!(
+                    f,
+                    "stale revmc version {version}, current is {REVMC_VERSION}"
+                )
+            }
+            CacheEntryProblem::MissingLibrary(file) => write!(f, "missing {file}"),
+        }
+    }
+}
+
+/// Artifact or batch found in an AOT out dir.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub id: CacheEntryId,
+    pub dir: PathBuf,
+    /// Bytecodes the entry holds a function for.
+    pub bytecode_hashes: Vec<B256>,
+    pub options: Option<ArtifactOptions>,
+    /// What the entry holds besides its objects, the default if unknown.
+    pub format: ArtifactFormat,
+    /// Size of the entry's files in bytes.
+    pub size: u64,
+    pub created: Option<SystemTime>,
+    pub problem: Option<CacheEntryProblem>,
+}
+
+impl CacheEntry {
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        if self.problem == Some(CacheEntryProblem::LegacyLayout) {
+            // Option-keyed artifacts of the same bytecode live in the same dir
+            for entry in std::fs::read_dir(&self.dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    std::fs::remove_file(entry.path())?;
+                }
+            }
+        } else {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        if let Some(parent) = self.dir.parent() {
+            // Fails if other entries are left, which is fine
+            let _ = std::fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+}
+
+/// Lists all artifacts, batches and static archives in the AOT out dir, including broken
+/// ones.
+pub fn scan_cache(out_dir: &Path) -> Result<Vec<CacheEntry>> {
+    let mut entries = vec![];
+    for dir_entry in std::fs::read_dir(out_dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = dir_entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == BATCHES_DIR {
+            for batch_entry in std::fs::read_dir(dir_entry.path())? {
+                let batch_entry = batch_entry?;
+                if batch_entry.file_type()?.is_dir() {
+                    entries.push(scan_batch(&batch_entry.path())?);
+                }
+            }
+        } else if name == ARCHIVES_DIR {
+            for archive_entry in std::fs::read_dir(dir_entry.path())? {
+                let archive_entry = archive_entry?;
+                if archive_entry.file_type()?.is_dir() {
+                    entries.push(scan_archive(&archive_entry.path())?);
+                }
+            }
+        } else if let Ok(bytecode_hash) = B256::from_str(name) {
+            entries.extend(scan_bytecode_dir(&dir_entry.path(), bytecode_hash)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn scan_bytecode_dir(dir: &Path, bytecode_hash: B256) -> Result<Vec<CacheEntry>> {
+    let mut entries = vec![];
+    let mut legacy_files = vec![];
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_type()?.is_dir() {
+            entries.push(scan_artifact(&dir_entry.path(), bytecode_hash)?);
+        } else {
+            legacy_files.push(dir_entry.path());
+        }
+    }
+    if !legacy_files.is_empty() {
+        entries.push(CacheEntry {
+            id: CacheEntryId::Artifact(bytecode_hash),
+            dir: dir.to_path_buf(),
+            bytecode_hashes: vec![bytecode_hash],
+            options: None,
+            format: ArtifactFormat::default(),
+            size: legacy_files.iter().map(|f| file_size(f)).sum(),
+            created: created(dir),
+            problem: Some(CacheEntryProblem::LegacyLayout),
+        });
+    }
+    Ok(entries)
+}
+
+pub(crate) fn scan_artifact(dir: &Path, bytecode_hash: B256) -> Result<CacheEntry> {
+    let manifest = read_if_exists(dir, MANIFEST_FILE, ArtifactManifest::read);
+    let options = manifest.as_ref().ok().map(|m| m.options.clone());
+    let format = manifest
+        .as_ref()
+        .map_or(ArtifactFormat::default(), |m| m.format);
+    let problem = match &manifest {
+        Err(problem) => Some(problem.clone()),
+        Ok(m) if m.bytecode_hash != bytecode_hash => {
+            Some(CacheEntryProblem::InvalidManifest(format!(
+                "expected bytecode hash {bytecode_hash} found {}",
+                m.bytecode_hash
+            )))
+        }
+        Ok(m) if !dir.ends_with(m.options.cache_key()) => Some(CacheEntryProblem::InvalidManifest(
+            "options don't match the dir name".to_string(),
+        )),
+        Ok(m) => check_library(dir, &m.revmc_version, m.format),
+    };
+    Ok(CacheEntry {
+        id: CacheEntryId::Artifact(bytecode_hash),
+        dir: dir.to_path_buf(),
+        bytecode_hashes: vec![bytecode_hash],
+        options,
+        format,
+        size: dir_size(dir)?,
+        created: created(&dir.join(MANIFEST_FILE)).or_else(|| created(dir)),
+        problem,
+    })
+}
+
+fn scan_batch(dir: &Path) -> Result<CacheEntry> {
+    let batch_id = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let index = read_if_exists(dir, BATCH_INDEX_FILE, BatchIndex::read);
+    let problem = match &index {
+        Err(problem) => Some(problem.clone()),
+        Ok(index) => check_library(dir, &index.revmc_version, index.format),
+    };
+    let (bytecode_hashes, options, format) = match index {
+        Ok(index) => (index.bytecode_hashes, Some(index.options), index.format),
+        Err(_) => (vec![], None, ArtifactFormat::default()),
+    };
+    Ok(CacheEntry {
+        id: CacheEntryId::Batch(batch_id),
+        dir: dir.to_path_buf(),
+        bytecode_hashes,
+        options,
+        format,
+        size: dir_size(dir)?,
+        created: created(&dir.join(BATCH_INDEX_FILE)).or_else(|| created(dir)),
+        problem,
+    })
+}
+
+/// Static archives have no manifest, they only need their library. Which contracts they hold
+/// isn't recorded, so they can't be selected by bytecode hash.
+fn scan_archive(dir: &Path) -> Result<CacheEntry> {
+    let archive_id = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let library = format!("lib{STATIC_LIB_NAME}.a");
+    let library_path = dir.join(&library);
+    let problem = (!library_path.exists()).then_some(CacheEntryProblem::MissingLibrary(library));
+    Ok(CacheEntry {
+        id: CacheEntryId::Archive(archive_id),
+        dir: dir.to_path_buf(),
+        bytecode_hashes: vec![],
+        options: None,
+        format: ArtifactFormat::StaticArchive,
+        size: dir_size(dir)?,
+        created: created(&library_path).or_else(|| created(dir)),
+        problem,
+    })
+}
+
+fn read_if_exists<T>(
+    dir: &Path,
+    file_name: &str,
+    read: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T, CacheEntryProblem> {
+    if !dir.join(file_name).exists() {
+        return Err(CacheEntryProblem::MissingManifest);
+    }
+    read(dir).map_err(|e| CacheEntryProblem::InvalidManifest(e.to_string()))
+}
+
+fn check_library(
+    dir: &Path,
+    revmc_version: &str,
+    format: ArtifactFormat,
+) -> Option<CacheEntryProblem> {
+    if revmc_version != REVMC_VERSION {
+        return Some(CacheEntryProblem::StaleVersion(revmc_version.to_string()));
+    }
+    let library = format.library_file()?;
+    (!dir.join(library).exists()).then(|| CacheEntryProblem::MissingLibrary(library.to_string()))
+}
+
+fn created(path: &Path) -> Option<SystemTime> {
+    let metadata = std::fs::metadata(path).ok()?;
+    metadata.created().or_else(|_| metadata.modified()).ok()
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        size += if entry.file_type()?.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            entry.metadata()?.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::archive_path;
+    use crate::test_utils::{temp_dir, write_artifact, write_artifact_with_format};
+    use crate::CompilerOptions;
+
+    fn problem_of(entries: &[CacheEntry], bytecode_hash: B256) -> Option<CacheEntryProblem> {
+        let id = CacheEntryId::Artifact(bytecode_hash);
+        entries.iter().find(|e| e.id == id).unwrap().problem.clone()
+    }
+
+    #[test]
+    fn artifacts_only_need_the_library_of_their_format() {
+        let out_dir = temp_dir("cache-formats");
+        let options = CompilerOptions::default().artifact_options();
+        let shared = write_artifact(&out_dir, &[0x00], &options);
+        let objects =
+            write_artifact_with_format(&out_dir, &[0x01], &options, ArtifactFormat::Objects);
+        let archived =
+            write_artifact_with_format(&out_dir, &[0x02], &options, ArtifactFormat::StaticArchive);
+        let missing = write_artifact(&out_dir, &[0x03], &options);
+        std::fs::remove_file(artifact_dir(&out_dir, &missing, &options).join("a.so")).unwrap();
+
+        let entries = scan_cache(&out_dir).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(problem_of(&entries, shared), None);
+        assert_eq!(problem_of(&entries, objects), None);
+        assert_eq!(problem_of(&entries, archived), None);
+        assert_eq!(
+            problem_of(&entries, missing),
+            Some(CacheEntryProblem::MissingLibrary("a.so".to_string()))
+        );
+    }
+
+    #[test]
+    fn static_archives_are_scanned_and_removable() {
+        let out_dir = temp_dir("cache-archives");
+        let path = archive_path(&out_dir, &[out_dir.join("a.o")]);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"archive").unwrap();
+        let empty = out_dir.join(ARCHIVES_DIR).join("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+
+        let entries = scan_cache(&out_dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        let archive = entries
+            .iter()
+            .find(|e| e.dir == path.parent().unwrap())
+            .unwrap();
+        assert!(archive.is_ok() && matches!(archive.id, CacheEntryId::Archive(_)));
+        assert_eq!(archive.format, ArtifactFormat::StaticArchive);
+        let broken = entries.iter().find(|e| e.dir == empty).unwrap();
+        assert!(matches!(
+            broken.problem,
+            Some(CacheEntryProblem::MissingLibrary(_))
+        ));
+
+        archive.remove().unwrap();
+        broken.remove().unwrap();
+        assert!(!out_dir.join(ARCHIVES_DIR).exists());
+    }
+}
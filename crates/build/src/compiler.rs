@@ -1,67 +1,42 @@
 use revm::primitives::{SpecId, B256};
-use revmc::{llvm::inkwell::context::Context, EvmCompiler, EvmCompilerFn, EvmLlvmBackend};
+use revmc::{llvm::inkwell::context::Context, EvmCompiler, EvmLlvmBackend};
 
-use eyre::{OptionExt, Result};
-use serde::Deserialize;
-use std::path::{Path, PathBuf};
-use tracing::debug;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
 
+use crate::admission::AdmissionPolicy;
+use crate::analysis::Cfg;
+use crate::error::{CompileError, CompileErrorKind, CompileResultExt};
+use crate::inspect::InspectReport;
+use crate::integrity::ArtifactKey;
+use crate::jit::{JitCompileOut, JitFunction, JitModule};
+use crate::link::{ArtifactFormat, LinkerConfig};
+use crate::manifest::{self, ArtifactManifest, ArtifactOptions, BatchIndex, BYTECODE_FILE};
+use crate::policy::OptionsPolicy;
+use crate::remote::RemoteCache;
+use crate::stats::{elapsed_ms, CompileMode, CompileStats};
 use crate::utils::{self, OptimizationLevelDeseralizable};
-
-#[derive(Default)]
-pub struct JitCompileOut {
-    pub entries: Vec<(B256, EvmCompilerFn)>,
-    pub ctx: JitCompileCtx,
-}
-
-impl JitCompileOut {
-    pub fn merge(&mut self, other: Self) {
-        self.entries.extend(other.entries);
-        self.ctx.0.extend(other.ctx.0);
-    }
-}
-
-#[derive(Default)]
-pub struct JitCompileCtx(
-    Vec<PtrWrapper<EvmCompiler<EvmLlvmBackend<'static>>, PtrWrapper<Context>>>,
-);
-
-#[derive(Debug)]
-pub struct PtrWrapper<T, D = ()> {
-    x: *const T,
-    dep: Option<D>,
-}
-impl<T, D> PtrWrapper<T, D> {
-    pub fn new_with_dep(x: *const T, dep: D) -> Self {
-        Self { x, dep: Some(dep) }
-    }
-}
-impl<T> PtrWrapper<T, ()> {
-    pub fn new(x: *const T) -> Self {
-        Self { x, dep: None }
-    }
-}
-impl<T, D> Drop for PtrWrapper<T, D> {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = Box::from_raw(self.x as *mut T);
-        }
-        if let Some(dep) = self.dep.take() {
-            drop(dep);
-        }
-    }
-}
-unsafe impl<T, D> Sync for PtrWrapper<T, D> {}
-unsafe impl<T, D> Send for PtrWrapper<T, D> {}
+use crate::worker::{WorkerConfig, WorkerPool};
 
 /**
  * Performance considerations:
  * - Disabled gas metering can improve performance, but it could result in an infinite loop.
  * - Without length checks performance may be improved, but it could result in undefined behaviour if stack overflows.
+ * - Inferred length checks are only left out for contracts whose stack height is proven to stay in bounds, so they are safe.
  * - Frame pointers are useful for debugging, but they can be disabled to slightly improve performance.
  * - Useful for debugging, but it can be disabled for moderate performance improvement.
  */
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CompilerOptions {
     pub out_dir: PathBuf,
@@ -72,23 +47,146 @@ pub struct CompilerOptions {
     pub target: String,
 
     pub opt_level: OptimizationLevelDeseralizable,
-    pub no_link: bool,
+    /// AOT only: what is produced besides the objects.
+    #[serde(default)]
+    pub format: ArtifactFormat,
+    /// AOT only: commands producing the libraries of `format`.
+    #[serde(default)]
+    pub linker: LinkerConfig,
+    /// AOT only: link contracts into shared libraries holding up to this many contracts each.
+    pub aot_batch_size: Option<usize>,
+    /// AOT only: file with the key used to sign artifact manifests.
+    pub signing_key: Option<PathBuf>,
+    /// AOT only: contracts taking longer to compile are reported as failed.
+    pub compile_timeout: Option<Duration>,
+    /// AOT only: compile contracts in worker processes, so that a crash only fails the
+    /// contract being compiled. Batches are still compiled in process.
+    #[serde(skip)]
+    pub workers: Option<WorkerConfig>,
+    /// AOT only: store checked for artifacts before compiling and given the new ones.
+    /// Batches are neither fetched nor uploaded.
+    #[serde(skip)]
+    pub remote_cache: Option<RemoteCache>,
     pub no_gas: bool,
     pub no_len_checks: bool,
+    /// Leave out stack length checks for contracts whose stack height is proven to stay in
+    /// bounds on every path. Ignored if `no_len_checks` is set.
+    pub infer_len_checks: bool,
     pub frame_pointers: bool,
     pub debug_assertions: bool,
+    /// Contracts exceeding these limits are not compiled and stay interpreted.
+    #[serde(default)]
+    pub admission: AdmissionPolicy,
+    /// Per-contract overrides of these options, see `CompilerOptions::for_contract`.
+    #[serde(skip)]
+    pub policy: Option<Arc<OptionsPolicy>>,
 }
 
-// todo: add the rest setters
 impl CompilerOptions {
     pub fn with_out_dir(mut self, out_dir: impl Into<PathBuf>) -> Self {
         self.out_dir = out_dir.into();
         self
     }
+    pub fn with_spec_id(mut self, spec_id: SpecId) -> Self {
+        self.spec_id = spec_id;
+        self
+    }
     pub fn with_opt_lvl(mut self, opt_level: OptimizationLevelDeseralizable) -> Self {
         self.opt_level = opt_level;
         self
     }
+    pub fn with_aot_batch_size(mut self, aot_batch_size: Option<usize>) -> Self {
+        self.aot_batch_size = aot_batch_size;
+        self
+    }
+    pub fn with_signing_key(mut self, signing_key: Option<PathBuf>) -> Self {
+        self.signing_key = signing_key;
+        self
+    }
+    pub fn with_compile_timeout(mut self, compile_timeout: Option<Duration>) -> Self {
+        self.compile_timeout = compile_timeout;
+        self
+    }
+    pub fn with_workers(mut self, workers: Option<WorkerConfig>) -> Self {
+        self.workers = workers;
+        self
+    }
+    pub fn with_remote_cache(mut self, remote_cache: Option<RemoteCache>) -> Self {
+        self.remote_cache = remote_cache;
+        self
+    }
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+    pub fn with_target_cpu(mut self, target_cpu: Option<String>) -> Self {
+        self.target_cpu = target_cpu;
+        self
+    }
+    pub fn with_target_features(mut self, target_features: Option<String>) -> Self {
+        self.target_features = target_features;
+        self
+    }
+    pub fn with_format(mut self, format: ArtifactFormat) -> Self {
+        self.format = format;
+        self
+    }
+    pub fn with_linker(mut self, linker: LinkerConfig) -> Self {
+        self.linker = linker;
+        self
+    }
+    pub fn with_no_gas(mut self, no_gas: bool) -> Self {
+        self.no_gas = no_gas;
+        self
+    }
+    pub fn with_no_len_checks(mut self, no_len_checks: bool) -> Self {
+        self.no_len_checks = no_len_checks;
+        self
+    }
+    pub fn with_infer_len_checks(mut self, infer_len_checks: bool) -> Self {
+        self.infer_len_checks = infer_len_checks;
+        self
+    }
+    pub fn with_frame_pointers(mut self, frame_pointers: bool) -> Self {
+        self.frame_pointers = frame_pointers;
+        self
+    }
+    pub fn with_debug_assertions(mut self, debug_assertions: bool) -> Self {
+        self.debug_assertions = debug_assertions;
+        self
+    }
+    pub fn with_admission(mut self, admission: AdmissionPolicy) -> Self {
+        self.admission = admission;
+        self
+    }
+    pub fn with_policy(mut self, policy: Option<OptionsPolicy>) -> Self {
+        self.policy = policy.map(Arc::new);
+        self
+    }
+
+    /// Options the contract is compiled with, after applying the policy. Each variant is
+    /// cached separately, as the artifact dir is keyed by the resulting options.
+    pub fn for_contract(&self, bytecode: &[u8]) -> CompilerOptions {
+        match &self.policy {
+            Some(policy) => policy.apply(bytecode, self.clone()),
+            None => self.clone(),
+        }
+    }
+
+    pub fn artifact_options(&self) -> ArtifactOptions {
+        self.into()
+    }
+
+    pub fn artifact_dir(&self, bytecode_hash: &B256) -> PathBuf {
+        manifest::artifact_dir(&self.out_dir, bytecode_hash, &self.artifact_options())
+    }
+
+    pub fn read_signing_key(&self) -> Result<Option<ArtifactKey>> {
+        self.signing_key
+            .as_deref()
+            .map(ArtifactKey::read)
+            .transpose()
+    }
 }
 
 impl Default for CompilerOptions {
@@ -100,9 +198,18 @@ impl Default for CompilerOptions {
             target_features: None,
             no_gas: false,
             no_len_checks: false,
+            infer_len_checks: false,
             frame_pointers: false,
             debug_assertions: false,
-            no_link: false,
+            admission: AdmissionPolicy::default(),
+            policy: None,
+            format: ArtifactFormat::default(),
+            linker: LinkerConfig::default(),
+            aot_batch_size: None,
+            signing_key: None,
+            compile_timeout: None,
+            workers: None,
+            remote_cache: None,
             opt_level: OptimizationLevelDeseralizable::Default,
             spec_id: SpecId::CANCUN,
         }
@@ -115,63 +222,426 @@ impl From<CompilerOptions> for Compiler {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Compiler {
     opt: CompilerOptions,
 }
 
 impl Compiler {
-    pub fn compile_aot(&self, bytecode: &[u8]) -> Result<()> {
-        let name = utils::bytecode_hash_str(bytecode);
+    pub fn compile_aot(&self, bytecode: &[u8]) -> Result<CompileStats, CompileError> {
+        self.compile_aot_until(bytecode, &AtomicBool::new(false))
+    }
+
+    /// Like `compile_aot`, but stops before writing the next file of the artifact once
+    /// `cancelled` is set. The manifest is written last, so an artifact left behind is never
+    /// taken for a complete one.
+    fn compile_aot_until(
+        &self,
+        bytecode: &[u8],
+        cancelled: &AtomicBool,
+    ) -> Result<CompileStats, CompileError> {
+        let bytecode_hash = revm::primitives::keccak256(bytecode);
+        let name = bytecode_hash.to_string();
         debug!("Compiling AOT contract with name {}", name);
+        let mut stats = CompileStats::new(
+            bytecode_hash,
+            CompileMode::Aot,
+            self.opt.artifact_options(),
+            bytecode.len(),
+        );
 
         let ctx = Context::create();
-        let mut compiler = self.create_compiler(&ctx, &name, true)?;
-        compiler.translate(&name, bytecode, self.opt.spec_id)?;
+        let mut compiler = self
+            .create_compiler(&ctx, &name, true)
+            .compile_err(bytecode_hash, CompileErrorKind::Codegen)?;
+        let max_stack_height = self.set_len_checks(&mut compiler, bytecode);
+        let start = Instant::now();
+        compiler
+            .translate(&name, bytecode, self.opt.spec_id)
+            .compile_err(bytecode_hash, CompileErrorKind::Translate)?;
+        stats.translate_ms = elapsed_ms(start);
+
+        let check_cancelled = || {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(CompileError::new(
+                    bytecode_hash,
+                    CompileErrorKind::Timeout,
+                    "cancelled after the timeout",
+                ));
+            }
+            Ok(())
+        };
+        check_cancelled()?;
+        let out_dir = self
+            .out_dir(&bytecode_hash)
+            .and_then(|out_dir| {
+                std::fs::write(out_dir.join(BYTECODE_FILE), bytecode)?;
+                Ok(out_dir)
+            })
+            .compile_err(bytecode_hash, CompileErrorKind::Io)?;
+        let start = Instant::now();
+        let obj = Self::write_precompiled_obj(&mut compiler, &name, &out_dir)
+            .compile_err(bytecode_hash, CompileErrorKind::Codegen)?;
+        stats.optimize_codegen_ms = elapsed_ms(start);
+        stats.object_size = std::fs::metadata(&obj).ok().map(|m| m.len());
+        if let Some(library) = self.opt.format.library_file() {
+            check_cancelled()?;
+            let start = Instant::now();
+            self.opt
+                .linker
+                .link_shared(&[&obj], &out_dir.join(library))
+                .compile_err(bytecode_hash, CompileErrorKind::Link)?;
+            stats.link_ms = Some(elapsed_ms(start));
+        }
+        // Written last, so that its presence implies a complete artifact
+        check_cancelled()?;
+        self.opt
+            .read_signing_key()
+            .and_then(|key| {
+                ArtifactManifest {
+                    format: self.opt.format,
+                    max_stack_height,
+                    ..ArtifactManifest::new(bytecode_hash, self.opt.artifact_options())
+                }
+                .seal(&out_dir, key.as_ref())?
+                .write(&out_dir)
+            })
+            .compile_err(bytecode_hash, CompileErrorKind::Io)?;
+        Ok(stats)
+    }
+
+    /// Like `compile_aot`, but gives up waiting after `timeout`. As LLVM can't be interrupted,
+    /// the compilation keeps running in the background, but writes no further files.
+    pub fn compile_aot_with_timeout(
+        &self,
+        bytecode: &[u8],
+        timeout: Duration,
+    ) -> Result<CompileStats, CompileError> {
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let compiler = self.clone();
+        let owned_bytecode = bytecode.to_vec();
+        let thread_cancelled = cancelled.clone();
+        std::thread::spawn(move || {
+            let _ = sender.send(compiler.compile_aot_until(&owned_bytecode, &thread_cancelled));
+        });
+        receiver.recv_timeout(timeout).unwrap_or_else(|_| {
+            cancelled.store(true, Ordering::Relaxed);
+            Err(CompileError::new(
+                revm::primitives::keccak256(bytecode),
+                CompileErrorKind::Timeout,
+                format!("no result after {timeout:?}"),
+            ))
+        })
+    }
 
-        let out_dir = self.out_dir(&name)?;
-        let obj = Self::write_precompiled_obj(&mut compiler, &name, &out_dir)?;
-        if !self.opt.no_link {
-            Self::link(&obj, &out_dir)?;
+    /// Like `compile_aot`, but in a worker of the pool.
+    pub(crate) fn compile_aot_in(
+        &self,
+        bytecode: &[u8],
+        pool: &WorkerPool,
+    ) -> Result<CompileStats, CompileError> {
+        pool.compile(bytecode, &self.opt)
+    }
+
+    /// Whether an up-to-date artifact for the bytecode already exists in the out dir.
+    pub fn is_cached(&self, bytecode: &[u8]) -> bool {
+        let bytecode_hash = revm::primitives::keccak256(bytecode);
+        let artifact_dir = self.opt.artifact_dir(&bytecode_hash);
+        let manifest_check = ArtifactManifest::read(&artifact_dir).and_then(|m| {
+            m.check(&bytecode_hash, &self.opt.artifact_options())?;
+            m.integrity.verify_all(&artifact_dir)?;
+            match self.opt.read_signing_key()? {
+                Some(key) => m.verify_signature(&key),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = manifest_check {
+            if artifact_dir.exists() {
+                debug!("Stale AOT artifact for {bytecode_hash}: {e}");
+            }
+            return false;
         }
-        Ok(())
+        self.has_library(&artifact_dir)
+    }
+
+    /// Whether the dir has the library `format` needs, if any.
+    fn has_library(&self, dir: &Path) -> bool {
+        self.opt
+            .format
+            .library_file()
+            .map_or(true, |library| dir.join(library).exists())
+    }
+
+    /// Downloads the artifact from the remote cache, if one is set. Returns whether an
+    /// up-to-date artifact was found, a broken one is left to be overwritten by compiling.
+    pub(crate) fn fetch_remote(&self, bytecode: &[u8]) -> bool {
+        let Some(remote) = &self.opt.remote_cache else {
+            return false;
+        };
+        let bytecode_hash = revm::primitives::keccak256(bytecode);
+        match remote.fetch(
+            &self.opt.out_dir,
+            &bytecode_hash,
+            &self.opt.artifact_options(),
+        ) {
+            Ok(found) => found && self.is_cached(bytecode),
+            Err(e) => {
+                warn!("Failed to fetch {bytecode_hash} from the remote cache: {e:#}");
+                false
+            }
+        }
+    }
+
+    /// Uploads the artifact to the remote cache, if one is set. Artifacts without a shared
+    /// object are kept local.
+    pub(crate) fn upload_remote(&self, bytecode: &[u8]) {
+        let Some(remote) = &self.opt.remote_cache else {
+            return;
+        };
+        if self.opt.format != ArtifactFormat::SharedObject {
+            return;
+        }
+        let bytecode_hash = revm::primitives::keccak256(bytecode);
+        if let Err(e) = remote.upload(
+            &self.opt.out_dir,
+            &bytecode_hash,
+            &self.opt.artifact_options(),
+        ) {
+            warn!("Failed to upload {bytecode_hash} to the remote cache: {e:#}");
+        }
+    }
+
+    /// Translates all bytecodes with a single compiler and links them into one shared library
+    /// that exports a symbol per bytecode hash. Returns the batch directory and the stats of
+    /// the compiled bytecodes, which are empty if the batch was cached.
+    /// A translation error is reported for the failing bytecode only, while other errors
+    /// are reported for every bytecode of the batch.
+    pub fn compile_aot_batch(
+        &self,
+        bytecodes: &[impl AsRef<[u8]>],
+    ) -> Result<(PathBuf, Vec<CompileStats>), Vec<CompileError>> {
+        let mut entries = bytecodes
+            .iter()
+            .map(|bytecode| (revm::primitives::keccak256(bytecode), bytecode.as_ref()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(bytecode_hash, _)| *bytecode_hash);
+        entries.dedup_by_key(|(bytecode_hash, _)| *bytecode_hash);
+        let bytecode_hashes = entries.iter().map(|(h, _)| *h).collect::<Vec<_>>();
+
+        let options = self.opt.artifact_options();
+        let batch_id = manifest::batch_id(&bytecode_hashes, &options);
+        let out_dir = manifest::batch_dir(&self.opt.out_dir, &batch_id);
+        let is_cached = BatchIndex::read(&out_dir).and_then(|index| {
+            index.check(&options)?;
+            index.integrity.verify_all(&out_dir)?;
+            match self.opt.read_signing_key()? {
+                Some(key) => index.verify_signature(&key),
+                None => Ok(()),
+            }
+        });
+        if is_cached.is_ok() && self.has_library(&out_dir) {
+            debug!("AOT batch {batch_id} is already compiled");
+            return Ok((out_dir, vec![]));
+        }
+        debug!(
+            "Compiling AOT batch {batch_id} with {} contracts",
+            entries.len()
+        );
+
+        let batch_err = |kind| {
+            let bytecode_hashes = &bytecode_hashes;
+            move |e: eyre::Report| {
+                bytecode_hashes
+                    .iter()
+                    .map(|bytecode_hash| CompileError::new(*bytecode_hash, kind, &e))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let ctx = Context::create();
+        let mut compiler = self
+            .create_compiler(&ctx, &batch_id, true)
+            .map_err(batch_err(CompileErrorKind::Codegen))?;
+        let mut stats = Vec::with_capacity(entries.len());
+        let mut max_stack_heights = BTreeMap::new();
+        for (bytecode_hash, bytecode) in &entries {
+            let mut contract_stats = CompileStats::new(
+                *bytecode_hash,
+                CompileMode::Aot,
+                options.clone(),
+                bytecode.len(),
+            );
+            contract_stats.module_size = entries.len();
+            if let Some(height) = self.set_len_checks(&mut compiler, bytecode) {
+                max_stack_heights.insert(*bytecode_hash, height);
+            }
+            let start = Instant::now();
+            compiler
+                .translate(&bytecode_hash.to_string(), bytecode, self.opt.spec_id)
+                .compile_err(*bytecode_hash, CompileErrorKind::Translate)
+                .map_err(|e| vec![e])?;
+            contract_stats.translate_ms = elapsed_ms(start);
+            stats.push(contract_stats);
+        }
+
+        Self::write_batch_bytecodes(&out_dir, &entries).map_err(batch_err(CompileErrorKind::Io))?;
+        let start = Instant::now();
+        let obj = Self::write_precompiled_obj(&mut compiler, &batch_id, &out_dir)
+            .map_err(batch_err(CompileErrorKind::Codegen))?;
+        let optimize_codegen_ms = elapsed_ms(start);
+        let object_size = std::fs::metadata(&obj).ok().map(|m| m.len());
+        let mut link_ms = None;
+        if let Some(library) = self.opt.format.library_file() {
+            let start = Instant::now();
+            self.opt
+                .linker
+                .link_shared(&[&obj], &out_dir.join(library))
+                .map_err(batch_err(CompileErrorKind::Link))?;
+            link_ms = Some(elapsed_ms(start));
+        }
+        for contract_stats in &mut stats {
+            contract_stats.optimize_codegen_ms = optimize_codegen_ms;
+            contract_stats.object_size = object_size;
+            contract_stats.link_ms = link_ms;
+        }
+        self.opt
+            .read_signing_key()
+            .and_then(|key| {
+                BatchIndex {
+                    format: self.opt.format,
+                    max_stack_heights,
+                    ..BatchIndex::new(bytecode_hashes.clone(), options)
+                }
+                .seal(&out_dir, key.as_ref())?
+                .write(&out_dir)
+            })
+            .map_err(batch_err(CompileErrorKind::Io))?;
+        Ok((out_dir, stats))
     }
 
     pub fn compile_jit(&self, bytecode: &[u8]) -> Result<JitCompileOut> {
         self.compile_jit_many(&[bytecode])
     }
 
+    /// Compiles the bytecodes into one module. A failure of a bytecode is reported as its
+    /// `CompileError`, which fails the whole module.
     pub fn compile_jit_many(&self, bytecodes: &[impl AsRef<[u8]>]) -> Result<JitCompileOut> {
-        let ctx: &'static Context = Box::leak(Box::new(Context::create()));
-        let mut compiler = self.create_compiler(ctx, "compile_many", false)?;
+        let module = JitModule::new(|ctx| self.create_compiler(ctx, "compile_many", false))?;
+        let options = self.opt.artifact_options();
 
-        // First we translate all at once, only then we finalize them
-        let fn_ids = bytecodes
-            .iter()
-            .map(|bytecode| {
-                let bytecode_hash = revm::primitives::keccak256(bytecode);
-                let name = bytecode_hash.to_string();
-                debug!("Compiling JIT contract with name {}", name);
-                let fn_id = compiler.translate(&name, bytecode.as_ref(), self.opt.spec_id)?;
-                Ok((bytecode_hash, fn_id))
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let fncs = fn_ids
+        let (fncs, stats) = {
+            let mut compiler = module.compiler();
+            // First we translate all at once, only then we finalize them
+            let (fn_ids, mut stats): (Vec<_>, Vec<_>) = bytecodes
+                .iter()
+                .map(|bytecode| {
+                    let bytecode = bytecode.as_ref();
+                    let bytecode_hash = revm::primitives::keccak256(bytecode);
+                    let name = bytecode_hash.to_string();
+                    debug!("Compiling JIT contract with name {}", name);
+                    let mut stats = CompileStats::new(
+                        bytecode_hash,
+                        CompileMode::Jit,
+                        options.clone(),
+                        bytecode.len(),
+                    );
+                    stats.module_size = bytecodes.len();
+                    self.set_len_checks(&mut compiler, bytecode);
+                    let start = Instant::now();
+                    let fn_id = compiler
+                        .translate(&name, bytecode, self.opt.spec_id)
+                        .compile_err(bytecode_hash, CompileErrorKind::Translate)?;
+                    stats.translate_ms = elapsed_ms(start);
+                    Ok(((bytecode_hash, fn_id, bytecode.len()), stats))
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .unzip();
+            // The module is optimized and emitted on the first lookup
+            let start = Instant::now();
+            let fncs = fn_ids
+                .into_iter()
+                .map(|(bytecode_hash, fn_id, bytecode_size)| {
+                    let fnc = unsafe { compiler.jit_function(fn_id) }
+                        .compile_err(bytecode_hash, CompileErrorKind::Codegen)?;
+                    Ok((bytecode_hash, fnc, fn_id, bytecode_size))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let optimize_codegen_ms = elapsed_ms(start);
+            for contract_stats in &mut stats {
+                contract_stats.optimize_codegen_ms = optimize_codegen_ms;
+            }
+            (fncs, stats)
+        };
+
+        let module = Arc::new(module);
+        let entries = fncs
             .into_iter()
-            .map(|(bytecode_hash, fn_id)| {
-                let fnc = unsafe { compiler.jit_function(fn_id)? };
-                Ok((bytecode_hash, fnc))
+            .map(|(bytecode_hash, fnc, fn_id, bytecode_size)| {
+                let fnc =
+                    JitFunction::new(fnc, fn_id, bytecode_size, self.opt.spec_id, module.clone());
+                (bytecode_hash, Arc::new(fnc))
             })
-            .collect::<Result<Vec<_>>>()?;
-
-        let cmp_ptr_wrapper =
-            PtrWrapper::new_with_dep(Box::leak(Box::new(compiler)), PtrWrapper::new(ctx));
+            .collect();
         Ok(JitCompileOut {
-            ctx: JitCompileCtx(vec![cmp_ptr_wrapper]),
-            entries: fncs,
+            entries,
+            stats,
+            ..Default::default()
         })
     }
 
+    /// Compiles the bytecodes into one module, or if that fails, every bytecode on its own, so
+    /// that one failing bytecode doesn't keep the others from being compiled. Failures are
+    /// listed in `JitCompileOut::errors`.
+    pub fn compile_jit_many_or_each(&self, bytecodes: &[impl AsRef<[u8]>]) -> JitCompileOut {
+        let e = match self.compile_jit_many(bytecodes) {
+            Ok(out) => return out,
+            Err(e) => e,
+        };
+        if let [bytecode] = bytecodes {
+            let error = e.downcast::<CompileError>().unwrap_or_else(|e| {
+                CompileError::new(
+                    revm::primitives::keccak256(bytecode.as_ref()),
+                    CompileErrorKind::Codegen,
+                    e,
+                )
+            });
+            return JitCompileOut {
+                errors: vec![error],
+                ..Default::default()
+            };
+        }
+        debug!("JIT module failed, compiling its contracts one by one: {e}");
+        let mut out = JitCompileOut::default();
+        for bytecode in bytecodes {
+            out.merge(self.compile_jit_many_or_each(std::slice::from_ref(bytecode)));
+        }
+        out
+    }
+
+    /// Compiles the bytecode to an object in `dump_dir`, next to the IR and assembly dumped
+    /// along the way, and reports on the output.
+    pub fn inspect(&self, bytecode: &[u8], dump_dir: &Path) -> Result<InspectReport> {
+        let bytecode_hash = revm::primitives::keccak256(bytecode);
+        let name = bytecode_hash.to_string();
+        revmc_toolkit_utils::misc::make_dir(dump_dir)?;
+
+        let ctx = Context::create();
+        let mut compiler = self.create_compiler(&ctx, &name, true)?;
+        compiler.set_dump_to(Some(dump_dir.to_path_buf()));
+        self.set_len_checks(&mut compiler, bytecode);
+        compiler.translate(&name, bytecode, self.opt.spec_id)?;
+        let obj = Self::write_precompiled_obj(&mut compiler, &name, dump_dir)?;
+        InspectReport::new(
+            bytecode_hash,
+            bytecode,
+            self.opt.artifact_options(),
+            dump_dir,
+            obj,
+        )
+    }
+
     fn create_compiler<'a>(
         &self,
         ctx: &'a Context,
@@ -194,6 +664,23 @@ impl Compiler {
         Ok(compiler)
     }
 
+    /// Turns off stack length checks for the next translation if `infer_len_checks` is set
+    /// and the stack height of the bytecode is proven to stay in bounds. Returns the proven
+    /// height in that case.
+    fn set_len_checks(
+        &self,
+        compiler: &mut EvmCompiler<EvmLlvmBackend>,
+        bytecode: &[u8],
+    ) -> Option<usize> {
+        if self.opt.no_len_checks || !self.opt.infer_len_checks {
+            return None;
+        }
+        let max_stack_height = Cfg::new(bytecode).max_stack_height();
+        // Safe, as the checks are only left out when they can't fail
+        unsafe { compiler.stack_bound_checks(max_stack_height.is_none()) };
+        max_stack_height
+    }
+
     fn create_target(&self) -> revmc::Target {
         revmc::Target::new(
             &self.opt.target,
@@ -216,25 +703,17 @@ impl Compiler {
         Ok(obj)
     }
 
-    fn link(obj: &Path, out_dir: &Path) -> Result<()> {
-        let so = out_dir.join("a.so");
-        let obj_str = obj.to_str().ok_or_eyre("Invalid object file path")?;
-
-        for _ in 0..10 {
-            revmc::Linker::new().link(&so, [obj_str])?;
-            if so.exists() {
-                debug!("Linked shared object file to {}", so.display());
-                return Ok(());
-            }
-            std::thread::sleep(std::time::Duration::from_millis(100));
+    fn write_batch_bytecodes(out_dir: &Path, entries: &[(B256, &[u8])]) -> Result<()> {
+        revmc_toolkit_utils::misc::make_dir(out_dir)?;
+        for (bytecode_hash, bytecode) in entries {
+            std::fs::write(out_dir.join(format!("{bytecode_hash}.bin")), bytecode)?;
         }
-
-        Err(eyre::eyre!("Failed to link object file after 10 attempts"))
+        Ok(())
     }
 
-    fn out_dir(&self, name: &str) -> Result<PathBuf> {
-        let out_dir = self.opt.out_dir.join(name);
+    fn out_dir(&self, bytecode_hash: &B256) -> Result<PathBuf> {
+        let out_dir = self.opt.artifact_dir(bytecode_hash);
         revmc_toolkit_utils::misc::make_dir(&out_dir)?;
-        Ok(out_dir.to_path_buf())
+        Ok(out_dir)
     }
 }
@@ -0,0 +1,197 @@
+use revm::primitives::{keccak256, Bytecode, B256};
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::link::ArtifactFormat;
+use crate::manifest::STATIC_LIB_NAME;
+use crate::{CompileSummary, CompilerOptions};
+
+/// File the generated module is written to in `OUT_DIR`.
+pub const EMBED_MODULE_FILE: &str = "revmc_aot_fns.rs";
+
+/// Compiles contracts from a build script and links them into the crate being built as a
+/// static archive, so that it ships with them and loads nothing at runtime. The generated
+/// module defines `compiled_fns() -> EvmCompilerFns`, to be included with
+/// `include!(concat!(env!("OUT_DIR"), "/revmc_aot_fns.rs"))`. It refers to
+/// `revmc_toolkit_load`, `revmc` and `revm`, which the crate has to depend on, and the
+/// builtins the functions call come from `revmc-builtins`, so `revmc_build::emit()` is still
+/// needed. Contracts that are rejected or fail to compile are left out with a warning.
+#[derive(Debug, Clone)]
+pub struct EmbedConfig {
+    pub bytecodes: Vec<Vec<u8>>,
+    /// The format is always `ArtifactFormat::StaticArchive`.
+    pub options: CompilerOptions,
+    /// Where the module is written, `OUT_DIR/EMBED_MODULE_FILE` by default.
+    pub module_path: PathBuf,
+}
+
+/// Entry of a gas guzzlers output file, as written by `gas-guzzlers` without `--hashed`.
+#[derive(Deserialize)]
+struct GasGuzzlerEntry {
+    bytecode: Bytecode,
+}
+
+impl EmbedConfig {
+    /// Artifacts go to `OUT_DIR/revmc-aot` and are compiled for the `TARGET` cargo builds
+    /// for, rather than for the host. Fails outside a build script.
+    pub fn new() -> Result<Self> {
+        let out_dir = PathBuf::from(std::env::var("OUT_DIR").wrap_err("OUT_DIR is not set")?);
+        let target = std::env::var("TARGET").wrap_err("TARGET is not set")?;
+        Ok(Self {
+            bytecodes: vec![],
+            options: CompilerOptions::default()
+                .with_out_dir(out_dir.join("revmc-aot"))
+                .with_target(target),
+            module_path: out_dir.join(EMBED_MODULE_FILE),
+        })
+    }
+    pub fn with_bytecodes(mut self, bytecodes: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.bytecodes.extend(bytecodes);
+        self
+    }
+    pub fn with_options(mut self, options: CompilerOptions) -> Self {
+        self.options = options;
+        self
+    }
+    pub fn with_module_path(mut self, module_path: impl Into<PathBuf>) -> Self {
+        self.module_path = module_path.into();
+        self
+    }
+
+    /// Adds the bytecodes of a manifest: a JSON array of hex strings if the file ends with
+    /// `.json`, otherwise a hex bytecode per line.
+    pub fn with_manifest(self, path: &Path) -> Result<Self> {
+        rerun_if_changed(path);
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let encoded = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str::<Vec<String>>(&content)
+                .wrap_err_with(|| format!("Failed to parse {}", path.display()))?
+        } else {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        let bytecodes = encoded
+            .iter()
+            .map(|bytecode| hex::decode(bytecode.trim_start_matches("0x")))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err_with(|| format!("Invalid hex in {}", path.display()))?;
+        Ok(self.with_bytecodes(bytecodes))
+    }
+
+    /// Adds the bytecodes of a `gas-guzzlers` output file.
+    pub fn with_gas_guzzlers(self, path: &Path) -> Result<Self> {
+        rerun_if_changed(path);
+        let entries: Vec<GasGuzzlerEntry> = serde_json::from_reader(std::fs::File::open(path)?)
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to parse {}, was it written with --hashed?",
+                    path.display()
+                )
+            })?;
+        Ok(self.with_bytecodes(
+            entries
+                .into_iter()
+                .map(|entry| entry.bytecode.original_bytes().to_vec()),
+        ))
+    }
+
+    /// Compiles the contracts, tells cargo to link the archive and writes the module.
+    pub fn build(self) -> Result<CompileSummary> {
+        let mut seen = HashSet::new();
+        let bytecodes = self
+            .bytecodes
+            .into_iter()
+            .filter(|bytecode| !bytecode.is_empty() && seen.insert(keccak256(bytecode)))
+            .collect::<Vec<_>>();
+        let options = self.options.with_format(ArtifactFormat::StaticArchive);
+        let spec_id = options.spec_id;
+        let summary = if options.aot_batch_size.is_some() {
+            crate::compile_contracts_aot_batched(&bytecodes, Some(options))?.1
+        } else {
+            crate::compile_contracts_aot(&bytecodes, Some(options))?
+        };
+        for rejection in &summary.rejected {
+            println!("cargo:warning=Not embedding {rejection}");
+        }
+        for error in &summary.errors {
+            println!("cargo:warning=Not embedding {error}");
+        }
+        let skipped = summary.skipped_hashes();
+        let mut embedded = seen
+            .into_iter()
+            .filter(|bytecode_hash| !skipped.contains(bytecode_hash))
+            .collect::<Vec<_>>();
+        embedded.sort();
+        if let Some(archive) = &summary.archive {
+            let dir = archive.parent().expect("archive path has a parent");
+            println!("cargo:rustc-link-search=native={}", dir.display());
+            println!("cargo:rustc-link-lib=static={STATIC_LIB_NAME}");
+        } else {
+            embedded.clear();
+        }
+
+        let module = generate_module(&embedded, &format!("{spec_id:?}"));
+        if let Some(dir) = self.module_path.parent() {
+            revmc_toolkit_utils::misc::make_dir(dir)?;
+        }
+        std::fs::write(&self.module_path, module)?;
+        Ok(summary)
+    }
+}
+
+fn rerun_if_changed(path: &Path) {
+    println!("cargo:rerun-if-changed={}", path.display());
+}
+
+/// Declares every function under its bytecode hash symbol. They are declared without a
+/// signature and cast to `RawEvmCompilerFn`, as the symbols aren't valid identifiers.
+fn generate_module(bytecode_hashes: &[B256], spec_id: &str) -> String {
+    let mut module = String::from("// Generated by revmc-toolkit-build, do not edit.\n\n");
+    module.push_str("extern \"C\" {\n");
+    for (i, bytecode_hash) in bytecode_hashes.iter().enumerate() {
+        let _ = writeln!(module, "    #[link_name = \"{bytecode_hash}\"]");
+        let _ = writeln!(module, "    fn revmc_aot_{i}();");
+    }
+    module.push_str("}\n\n");
+    module.push_str("#[allow(dead_code)]\n");
+    module.push_str(
+        "unsafe fn revmc_aot_fn(f: unsafe extern \"C\" fn()) -> ::revmc::EvmCompilerFn {\n",
+    );
+    module.push_str("    ::revmc::EvmCompilerFn::new(::core::mem::transmute::<\n");
+    module.push_str("        unsafe extern \"C\" fn(),\n");
+    module.push_str("        ::revmc::RawEvmCompilerFn,\n");
+    module.push_str("    >(f))\n");
+    module.push_str("}\n\n");
+    module.push_str("/// Functions of the contracts compiled in at build time.\n");
+    module.push_str("pub fn compiled_fns() -> ::revmc_toolkit_load::EvmCompilerFns {\n");
+    // Typed, as several conversions into `EvmCompilerFns` would fit an empty table
+    module.push_str(
+        "    let fns: ::std::vec::Vec<(::revm::primitives::B256, ::revmc::EvmCompilerFn)> =\n",
+    );
+    module.push_str("        ::std::vec![\n");
+    for (i, bytecode_hash) in bytecode_hashes.iter().enumerate() {
+        let _ = writeln!(
+            module,
+            "            (::revm::primitives::B256::new({:?}), unsafe {{ revmc_aot_fn(revmc_aot_{i}) }}),",
+            bytecode_hash.0
+        );
+    }
+    module.push_str("        ];\n");
+    let _ = writeln!(
+        module,
+        "    (::revm::primitives::SpecId::{spec_id}, fns).into()"
+    );
+    module.push_str("}\n");
+    module
+}
@@ -0,0 +1,174 @@
+use revm::primitives::B256;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::PathBuf,
+};
+use tracing::{debug, info, warn};
+
+use crate::admission::Rejection;
+use crate::stats::CompileStats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompileErrorKind {
+    /// Bytecode couldn't be translated to IR.
+    Translate,
+    /// IR couldn't be compiled to machine code.
+    Codegen,
+    Link,
+    /// Artifact couldn't be loaded, eg. as it's missing, corrupted or unsigned.
+    Load,
+    /// Loaded function behaved differently from the interpreter.
+    SmokeTest,
+    Timeout,
+    /// Worker process died while compiling, eg. on an LLVM assertion or abort.
+    Crash,
+    /// Worker process couldn't be started or talked to.
+    Worker,
+    /// Artifact files or the signing key couldn't be read or written, eg. as the disk is full.
+    Io,
+}
+
+impl CompileErrorKind {
+    /// Whether the failure is caused by the bytecode itself, rather than by the environment
+    /// (eg. a missing linker or a deleted artifact), so that retrying is pointless. Timeouts
+    /// aren't, as they depend on the load of the machine.
+    pub fn is_persistent(&self) -> bool {
+        matches!(
+            self,
+            CompileErrorKind::Translate
+                | CompileErrorKind::Codegen
+                | CompileErrorKind::Crash
+                | CompileErrorKind::SmokeTest
+        )
+    }
+}
+
+impl fmt::Display for CompileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            CompileErrorKind::Translate => "translate",
+            CompileErrorKind::Codegen => "codegen",
+            CompileErrorKind::Link => "link",
+            CompileErrorKind::Load => "load",
+            CompileErrorKind::SmokeTest => "smoke test",
+            CompileErrorKind::Timeout => "timeout",
+            CompileErrorKind::Crash => "crash",
+            CompileErrorKind::Worker => "worker",
+            CompileErrorKind::Io => "io",
+        };
+        f.write_str(kind)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileError {
+    pub bytecode_hash: B256,
+    pub kind: CompileErrorKind,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(bytecode_hash: B256, kind: CompileErrorKind, err: impl fmt::Display) -> Self {
+        Self {
+            bytecode_hash,
+            kind,
+            message: format!("{err:#}"),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed for {}: {}",
+            self.kind, self.bytecode_hash, self.message
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+pub(crate) trait CompileResultExt<T> {
+    fn compile_err(self, bytecode_hash: B256, kind: CompileErrorKind) -> Result<T, CompileError>;
+}
+
+impl<T, E: fmt::Display> CompileResultExt<T> for Result<T, E> {
+    fn compile_err(self, bytecode_hash: B256, kind: CompileErrorKind) -> Result<T, CompileError> {
+        self.map_err(|e| CompileError::new(bytecode_hash, kind, e))
+    }
+}
+
+/// Outcome of compiling a selection of contracts.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileSummary {
+    pub compiled: usize,
+    pub cached: usize,
+    /// Downloaded from the remote cache instead of compiled.
+    pub fetched: usize,
+    /// Skipped, as they failed in an earlier run.
+    pub quarantined: Vec<B256>,
+    /// Left to the interpreter by the admission policy.
+    pub rejected: Vec<Rejection>,
+    pub errors: Vec<CompileError>,
+    /// Stats of the compiled contracts, the cached ones have none.
+    pub stats: Vec<CompileStats>,
+    /// Static archive of every contract with an artifact, for `ArtifactFormat::StaticArchive`.
+    pub archive: Option<PathBuf>,
+}
+
+impl CompileSummary {
+    pub fn merge(&mut self, other: Self) {
+        self.compiled += other.compiled;
+        self.cached += other.cached;
+        self.fetched += other.fetched;
+        self.quarantined.extend(other.quarantined);
+        self.rejected.extend(other.rejected);
+        self.errors.extend(other.errors);
+        self.stats.extend(other.stats);
+        self.archive = other.archive.or(self.archive.take());
+    }
+
+    pub fn failures_by_kind(&self) -> BTreeMap<CompileErrorKind, usize> {
+        let mut failures = BTreeMap::new();
+        for error in &self.errors {
+            *failures.entry(error.kind).or_default() += 1;
+        }
+        failures
+    }
+
+    /// Contracts that have no artifact, as they were rejected or failed now or in an
+    /// earlier run.
+    pub fn skipped_hashes(&self) -> HashSet<B256> {
+        self.quarantined
+            .iter()
+            .copied()
+            .chain(self.rejected.iter().map(|r| r.bytecode_hash))
+            .chain(self.errors.iter().map(|e| e.bytecode_hash))
+            .collect()
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Compiled {} contracts, {} cached, {} fetched, {} quarantined, {} rejected, {} failed",
+            self.compiled,
+            self.cached,
+            self.fetched,
+            self.quarantined.len(),
+            self.rejected.len(),
+            self.errors.len()
+        );
+        for rejection in &self.rejected {
+            debug!("{rejection}");
+        }
+        for (kind, count) in self.failures_by_kind() {
+            warn!("{count} contracts failed at {kind}");
+        }
+    }
+}
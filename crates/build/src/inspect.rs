@@ -0,0 +1,125 @@
+use revm::primitives::B256;
+
+use eyre::Result;
+use object::{Object, ObjectSymbol};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::analysis::{BytecodeMetrics, Cfg, CFG_FILE};
+use crate::manifest::ArtifactOptions;
+
+/// File names revmc dumps the IR and assembly to.
+pub const UNOPT_IR_FILE: &str = "unopt.ll";
+pub const OPT_IR_FILE: &str = "opt.ll";
+pub const ASM_FILE: &str = "opt.s";
+
+const BUILTIN_PREFIX: &str = "@__revmc_builtin_";
+
+/// What a contract compiles to, see `Compiler::inspect`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectReport {
+    pub bytecode_hash: B256,
+    pub options: ArtifactOptions,
+    pub bytecode_size: usize,
+    pub unopt_ir: Option<PathBuf>,
+    pub opt_ir: Option<PathBuf>,
+    pub asm: Option<PathBuf>,
+    pub object: PathBuf,
+    /// CFG of the bytecode in DOT format.
+    pub cfg: PathBuf,
+    /// Machine code size of the contract function, if the object format records it.
+    pub function_size: Option<u64>,
+    /// Calls to revmc builtins in the optimized IR, by builtin name.
+    pub builtin_calls: BTreeMap<String, usize>,
+    pub analysis: BytecodeMetrics,
+}
+
+impl InspectReport {
+    pub(crate) fn new(
+        bytecode_hash: B256,
+        bytecode: &[u8],
+        options: ArtifactOptions,
+        dump_dir: &Path,
+        obj: PathBuf,
+    ) -> Result<Self> {
+        let opt_ir = find_file(dump_dir, OPT_IR_FILE)?;
+        let builtin_calls = match &opt_ir {
+            Some(path) => count_builtin_calls(&std::fs::read_to_string(path)?),
+            None => BTreeMap::new(),
+        };
+        let bytecode_cfg = Cfg::new(bytecode);
+        let cfg = dump_dir.join(CFG_FILE);
+        std::fs::write(&cfg, bytecode_cfg.to_dot())?;
+        Ok(Self {
+            bytecode_hash,
+            options,
+            bytecode_size: bytecode.len(),
+            unopt_ir: find_file(dump_dir, UNOPT_IR_FILE)?,
+            opt_ir,
+            asm: find_file(dump_dir, ASM_FILE)?,
+            function_size: function_size(&obj, &bytecode_hash.to_string())?,
+            object: obj,
+            cfg,
+            builtin_calls,
+            analysis: bytecode_cfg.metrics(bytecode.len()),
+        })
+    }
+
+    pub fn total_builtin_calls(&self) -> usize {
+        self.builtin_calls.values().sum()
+    }
+}
+
+/// revmc nests the dumps in a dir named after the module, so the name is looked up
+/// recursively.
+fn find_file(dir: &Path, name: &str) -> Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name)? {
+                return Ok(Some(found));
+            }
+        } else if path.file_name().is_some_and(|n| n == name) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+fn count_builtin_calls(ir: &str) -> BTreeMap<String, usize> {
+    let mut calls = BTreeMap::new();
+    for line in ir.lines() {
+        let line = line.trim_start();
+        // Skip declarations, only call sites count
+        if line.starts_with("declare") || !line.contains("call ") {
+            continue;
+        }
+        for (_, rest) in line.match_indices(BUILTIN_PREFIX) {
+            let name = rest[BUILTIN_PREFIX.len()..]
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default();
+            *calls.entry(name.to_string()).or_default() += 1;
+        }
+    }
+    calls
+}
+
+fn function_size(obj: &Path, name: &str) -> Result<Option<u64>> {
+    let data = std::fs::read(obj)?;
+    let file = object::File::parse(data.as_slice())?;
+    // Mach-O prefixes symbols with an underscore and doesn't record sizes
+    let size = file
+        .symbols()
+        .find(|s| s.name().is_ok_and(|n| n.trim_start_matches('_') == name))
+        .map(|s| s.size())
+        .filter(|size| *size > 0);
+    Ok(size)
+}
@@ -0,0 +1,144 @@
+use revm::primitives::{keccak256, B256};
+
+use eyre::{OptionExt, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::BTreeMap, path::Path};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secret shared by the machines that compile and load artifacts. Read from a file holding
+/// the hex encoded key, eg. one created with `openssl rand -hex 32`.
+#[derive(Clone)]
+pub struct ArtifactKey(Vec<u8>);
+
+impl ArtifactKey {
+    pub fn read(path: &Path) -> Result<Self> {
+        let encoded = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("Failed to read key {}: {e}", path.display()))?;
+        let key = hex::decode(encoded.trim())?;
+        if key.len() < 16 {
+            return Err(eyre::eyre!(
+                "Key {} is shorter than 16 bytes",
+                path.display()
+            ));
+        }
+        Ok(Self(key))
+    }
+
+    /// HMAC-SHA256 of the payload.
+    pub fn sign(&self, payload: &[u8]) -> B256 {
+        B256::from_slice(&self.mac(payload).finalize().into_bytes())
+    }
+
+    /// Whether `signature` is the payload's, compared in constant time.
+    pub fn verify(&self, payload: &[u8], signature: &B256) -> bool {
+        self.mac(payload).verify_slice(signature.as_slice()).is_ok()
+    }
+
+    fn mac(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Checksums of the files in an artifact dir, optionally signed with an `ArtifactKey`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Integrity {
+    /// Keccak256 of every file next to the manifest, by file name.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, B256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<B256>,
+}
+
+impl Integrity {
+    /// Checksums all files in `dir` except `exclude`.
+    pub fn of_dir(dir: &Path, exclude: &str) -> Result<Self> {
+        let mut checksums = BTreeMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_str().ok_or_eyre("Invalid file name")?;
+            if name != exclude && entry.file_type()?.is_file() {
+                checksums.insert(name.to_string(), file_checksum(&entry.path())?);
+            }
+        }
+        Ok(Self {
+            checksums,
+            signature: None,
+        })
+    }
+
+    /// Errors if the file differs from the one the manifest was written for.
+    pub fn verify_file(&self, dir: &Path, name: &str) -> Result<()> {
+        let expected = self
+            .checksums
+            .get(name)
+            .ok_or_else(|| eyre::eyre!("missing checksum for {name}"))?;
+        let found = file_checksum(&dir.join(name))?;
+        if &found != expected {
+            return Err(eyre::eyre!(
+                "checksum mismatch for {name}: expected {expected} found {found}"
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn verify_all(&self, dir: &Path) -> Result<()> {
+        self.checksums
+            .keys()
+            .try_for_each(|name| self.verify_file(dir, name))
+    }
+}
+
+/// Signs `value` with its `Integrity::signature` unset.
+pub(crate) fn sign<T: Serialize>(value: &T, key: &ArtifactKey) -> Result<B256> {
+    Ok(key.sign(&serde_json::to_vec(value)?))
+}
+
+pub(crate) fn verify_signature<T: Serialize>(
+    value: &T,
+    signature: Option<B256>,
+    key: &ArtifactKey,
+) -> Result<()> {
+    let signature = signature.ok_or_eyre("missing signature")?;
+    if !key.verify(&serde_json::to_vec(value)?, &signature) {
+        return Err(eyre::eyre!("invalid signature"));
+    }
+    Ok(())
+}
+
+pub fn file_checksum(path: &Path) -> Result<B256> {
+    let content =
+        std::fs::read(path).map_err(|e| eyre::eyre!("Failed to read {}: {e}", path.display()))?;
+    Ok(keccak256(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_is_hmac_sha256() {
+        // RFC 4231, test case 2
+        let key = ArtifactKey(b"Jefe".to_vec());
+        let signature = key.sign(b"what do ya want for nothing?");
+        assert_eq!(
+            hex::encode(signature),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn verify_rejects_other_payloads_and_keys() {
+        let key = ArtifactKey(vec![1; 32]);
+        let signature = key.sign(b"payload");
+        assert!(key.verify(b"payload", &signature));
+        assert!(!key.verify(b"payloae", &signature));
+        assert!(!ArtifactKey(vec![2; 32]).verify(b"payload", &signature));
+    }
+}
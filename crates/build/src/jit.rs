@@ -0,0 +1,159 @@
+use revm::primitives::{SpecId, B256};
+use revmc::{llvm::inkwell::context::Context, Backend, EvmCompiler, EvmCompilerFn, EvmLlvmBackend};
+
+use eyre::Result;
+use std::{
+    mem::ManuallyDrop,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tracing::error;
+
+use crate::admission::Rejection;
+use crate::error::CompileError;
+use crate::stats::CompileStats;
+
+type JitCompiler = EvmCompiler<EvmLlvmBackend<'static>>;
+type FuncId = <EvmLlvmBackend<'static> as Backend>::FuncId;
+
+static LIVE_FUNCTIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTECODE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JitMemoryStats {
+    /// JIT functions that were compiled and not yet freed.
+    pub functions: usize,
+    /// Summed bytecode size of the live functions. Their machine code isn't measured, it's
+    /// usually several times larger.
+    pub bytecode_bytes: usize,
+}
+
+pub fn jit_memory_stats() -> JitMemoryStats {
+    JitMemoryStats {
+        functions: LIVE_FUNCTIONS.load(Ordering::Relaxed),
+        bytecode_bytes: LIVE_BYTECODE_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Default)]
+pub struct JitCompileOut {
+    pub entries: Vec<(B256, Arc<JitFunction>)>,
+    pub stats: Vec<CompileStats>,
+    /// Left to the interpreter by the admission policy.
+    pub rejected: Vec<Rejection>,
+    /// Left to the interpreter, as they failed to compile.
+    pub errors: Vec<CompileError>,
+}
+
+impl JitCompileOut {
+    pub fn merge(&mut self, other: Self) {
+        self.entries.extend(other.entries);
+        self.stats.extend(other.stats);
+        self.rejected.extend(other.rejected);
+        self.errors.extend(other.errors);
+    }
+}
+
+/// Owns an LLVM context together with the JIT compiler that lives in it.
+pub struct JitModule {
+    compiler: ManuallyDrop<Mutex<JitCompiler>>,
+    ctx: NonNull<Context>,
+}
+
+// The context is only accessed through the compiler, which is behind a mutex.
+unsafe impl Send for JitModule {}
+unsafe impl Sync for JitModule {}
+
+impl JitModule {
+    pub(crate) fn new<F>(make_compiler: F) -> Result<Self>
+    where
+        F: FnOnce(&'static Context) -> Result<JitCompiler>,
+    {
+        let ctx = NonNull::from(Box::leak(Box::new(Context::create())));
+        // The context outlives the compiler, as it's only freed in `drop` after the compiler
+        match make_compiler(unsafe { ctx.as_ref() }) {
+            Ok(compiler) => Ok(Self {
+                compiler: ManuallyDrop::new(Mutex::new(compiler)),
+                ctx,
+            }),
+            Err(e) => {
+                drop(unsafe { Box::from_raw(ctx.as_ptr()) });
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn compiler(&self) -> std::sync::MutexGuard<'_, JitCompiler> {
+        self.compiler.lock().unwrap()
+    }
+}
+
+impl Drop for JitModule {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.compiler);
+            drop(Box::from_raw(self.ctx.as_ptr()));
+        }
+    }
+}
+
+/// JIT-compiled function, freed once the last reference to it is dropped.
+pub struct JitFunction {
+    fnc: EvmCompilerFn,
+    id: FuncId,
+    bytecode_size: usize,
+    spec_id: SpecId,
+    module: Arc<JitModule>,
+}
+
+// The function id is only used while holding the module's compiler lock.
+unsafe impl Send for JitFunction {}
+unsafe impl Sync for JitFunction {}
+
+impl JitFunction {
+    pub(crate) fn new(
+        fnc: EvmCompilerFn,
+        id: FuncId,
+        bytecode_size: usize,
+        spec_id: SpecId,
+        module: Arc<JitModule>,
+    ) -> Self {
+        LIVE_FUNCTIONS.fetch_add(1, Ordering::Relaxed);
+        LIVE_BYTECODE_BYTES.fetch_add(bytecode_size, Ordering::Relaxed);
+        Self {
+            fnc,
+            id,
+            bytecode_size,
+            spec_id,
+            module,
+        }
+    }
+
+    pub fn fnc(&self) -> EvmCompilerFn {
+        self.fnc
+    }
+
+    /// Size of the bytecode the function was compiled from.
+    pub fn bytecode_size(&self) -> usize {
+        self.bytecode_size
+    }
+
+    /// Spec the function was compiled for, it must not run on blocks of any other spec.
+    pub fn spec_id(&self) -> SpecId {
+        self.spec_id
+    }
+}
+
+impl Drop for JitFunction {
+    fn drop(&mut self) {
+        LIVE_FUNCTIONS.fetch_sub(1, Ordering::Relaxed);
+        LIVE_BYTECODE_BYTES.fetch_sub(self.bytecode_size, Ordering::Relaxed);
+        // Users of the function hold a reference to it, so it can't be running anymore
+        if let Err(e) = unsafe { self.module.compiler().free_function(self.id) } {
+            error!("Failed to free JIT function: {e}");
+        }
+    }
+}
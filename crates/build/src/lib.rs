@@ -1,52 +1,459 @@
+mod admission;
+mod analysis;
+mod archive;
+mod cache;
 mod compiler;
+mod embed;
+mod error;
+mod inspect;
+mod integrity;
+mod jit;
+mod link;
+mod manifest;
+mod policy;
+mod quarantine;
+mod remote;
+mod stats;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod worker;
 
 use eyre::Result;
 use rayon::prelude::*;
-use std::path::Path;
+use revm::primitives::{SpecId, B256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-pub use compiler::{Compiler, CompilerOptions, JitCompileCtx, JitCompileOut, PtrWrapper};
-pub use utils::{default_dir, OptimizationLevelDeseralizable};
+pub use admission::{estimate_ir_size, AdmissionPolicy, RejectReason, Rejection};
+pub use analysis::{
+    disassemble, metadata_len, BasicBlock, BytecodeMetrics, Cfg, Instruction, OpcodeClass, CFG_FILE,
+};
+pub use archive::{
+    export_cache, import_cache, read_archive_manifest, ArchiveEntry, ArchiveFile, ArchiveManifest,
+    TargetInfo,
+};
+pub use cache::{scan_cache, CacheEntry, CacheEntryId, CacheEntryProblem};
+pub use compiler::{Compiler, CompilerOptions};
+pub use embed::{EmbedConfig, EMBED_MODULE_FILE};
+pub use error::{CompileError, CompileErrorKind, CompileSummary};
+pub use inspect::{InspectReport, ASM_FILE, OPT_IR_FILE, UNOPT_IR_FILE};
+pub use integrity::{file_checksum, ArtifactKey, Integrity};
+pub use jit::{jit_memory_stats, JitCompileOut, JitFunction, JitMemoryStats, JitModule};
+pub use link::{ArtifactFormat, LinkerConfig};
+pub use manifest::{
+    archive_path, artifact_dir, batch_dir, ArtifactManifest, ArtifactOptions, BatchIndex,
+    ARCHIVES_DIR, BATCHES_DIR, BATCH_INDEX_FILE, BYTECODE_FILE, MANIFEST_FILE, REVMC_VERSION,
+    STATIC_LIB_NAME,
+};
+pub use policy::{ContractFacts, OptionOverrides, OptionsPolicy, PolicyMatch, PolicyRule};
+pub use quarantine::{
+    quarantine_errors, quarantine_errors_with, Quarantine, QuarantineEntry, QUARANTINE_FILE,
+};
+pub use remote::{serve_remote_cache, RemoteCache};
+pub use stats::{
+    append_compile_stats, read_compile_stats, CompileMode, CompileStats, COMPILE_STATS_FILE,
+};
+pub use utils::{bytecode_hash_str, default_dir, OptimizationLevelDeseralizable};
+pub use worker::{run_compile_worker, WorkerConfig};
 
+const DEFAULT_AOT_BATCH_SIZE: usize = 500;
+
+enum CompileOutcome {
+    Compiled(CompileStats),
+    Cached,
+    Fetched,
+    Quarantined(B256),
+    Rejected(Rejection),
+    Failed(Vec<CompileError>),
+}
+
+impl FromIterator<CompileOutcome> for CompileSummary {
+    fn from_iter<I: IntoIterator<Item = CompileOutcome>>(outcomes: I) -> Self {
+        let mut summary = CompileSummary::default();
+        for outcome in outcomes {
+            match outcome {
+                CompileOutcome::Compiled(stats) => {
+                    summary.compiled += 1;
+                    summary.stats.push(stats);
+                }
+                CompileOutcome::Cached => summary.cached += 1,
+                CompileOutcome::Fetched => summary.fetched += 1,
+                CompileOutcome::Quarantined(bytecode_hash) => {
+                    summary.quarantined.push(bytecode_hash)
+                }
+                CompileOutcome::Rejected(rejection) => summary.rejected.push(rejection),
+                CompileOutcome::Failed(errors) => summary.errors.extend(errors),
+            }
+        }
+        summary
+    }
+}
+
+/// Compiles the contracts that are neither cached, quarantined nor rejected by the admission
+/// policy. New failures are added to the quarantine, so later runs skip them, and the stats
+/// of the compiled ones are appended to `COMPILE_STATS_FILE`. Bytecodes are keyed by their
+/// keccak256 hash, so initcode is compiled like runtime code and found by the hash of the
+/// initcode. Each contract is compiled with its options under the policy, see
+/// `CompilerOptions::for_contract`.
 pub fn compile_contracts_aot(
     args: &[Vec<u8>],
     fallback_opt: Option<CompilerOptions>,
-) -> Result<Vec<Result<()>>> {
-    // todo: try compiling multiple contracts with the same ctx and compiler
+) -> Result<CompileSummary> {
+    compile_contracts_aot_with_progress(args, fallback_opt, |_, _| {})
+}
+
+/// Like `compile_contracts_aot`, but calls `on_progress` with the number of processed and
+/// total contracts after each contract.
+pub fn compile_contracts_aot_with_progress(
+    args: &[Vec<u8>],
+    fallback_opt: Option<CompilerOptions>,
+    on_progress: impl Fn(usize, usize) + Sync,
+) -> Result<CompileSummary> {
     let opt = fallback_opt.unwrap_or_default();
-    let compiled_contracts = load_compiled(&opt.out_dir).unwrap_or_default();
-    let compiler: Compiler = opt.into();
-    Ok(args
+    let out_dir = opt.out_dir.clone();
+    let timeout = opt.compile_timeout;
+    let pool = opt
+        .workers
+        .clone()
+        .map(|config| WorkerPool::new(config, timeout));
+    let quarantine = Quarantine::load(&out_dir)?;
+    let done = AtomicUsize::new(0);
+    let (outcomes, options): (Vec<_>, HashMap<_, _>) = args
         .par_iter()
-        .filter(|arg| !compiled_contracts.contains(&utils::bytecode_hash_str(arg)))
-        .map(|arg| compiler.compile_aot(arg))
-        .collect())
+        .map(|arg| {
+            let bytecode_hash = revm::primitives::keccak256(arg);
+            let contract_opt = opt.for_contract(arg);
+            let options = contract_opt.artifact_options();
+            let rejection = contract_opt.admission.check(bytecode_hash, arg);
+            let compiler: Compiler = contract_opt.into();
+            let outcome = if let Some(rejection) = rejection {
+                CompileOutcome::Rejected(rejection)
+            } else if quarantine.contains(&bytecode_hash, &options) {
+                CompileOutcome::Quarantined(bytecode_hash)
+            } else if compiler.is_cached(arg) {
+                CompileOutcome::Cached
+            } else if compiler.fetch_remote(arg) {
+                CompileOutcome::Fetched
+            } else {
+                let res = match (&pool, timeout) {
+                    (Some(pool), _) => compiler.compile_aot_in(arg, pool),
+                    (None, Some(timeout)) => compiler.compile_aot_with_timeout(arg, timeout),
+                    (None, None) => compiler.compile_aot(arg),
+                };
+                match res {
+                    Ok(stats) => {
+                        compiler.upload_remote(arg);
+                        CompileOutcome::Compiled(stats)
+                    }
+                    Err(e) => CompileOutcome::Failed(vec![e]),
+                }
+            };
+            on_progress(done.fetch_add(1, Ordering::Relaxed) + 1, args.len());
+            (outcome, (bytecode_hash, options))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .unzip();
+    let mut summary = outcomes.into_iter().collect::<CompileSummary>();
+    quarantine_errors_with(&out_dir, &summary.errors, |bytecode_hash| {
+        &options[bytecode_hash]
+    })?;
+    append_compile_stats(&out_dir, &summary.stats)?;
+    let skipped = summary.skipped_hashes();
+    let objects = options
+        .iter()
+        .filter(|(bytecode_hash, _)| !skipped.contains(bytecode_hash))
+        .map(|(bytecode_hash, options)| {
+            artifact_dir(&out_dir, bytecode_hash, options).join(format!("{bytecode_hash}.o"))
+        })
+        .collect();
+    summary.archive = archive_objects(&opt, objects)?;
+    Ok(summary)
 }
 
-pub fn compile_contracts_jit(
+/// Compiles the contracts into shared libraries of up to `aot_batch_size` contracts each
+/// and returns the batch directories. Contracts failing translation are quarantined and
+/// their batch is compiled without them, rejected ones are left out.
+pub fn compile_contracts_aot_batched(
     args: &[Vec<u8>],
     fallback_opt: Option<CompilerOptions>,
-) -> Result<JitCompileOut> {
-    let compiler: Compiler = fallback_opt.unwrap_or_default().into();
-    args.par_chunks(10) // todo: make configurable
-        .map(|chunk| compiler.compile_jit_many(chunk))
-        .reduce_with(|acc, res| {
-            let mut acc = acc?;
-            acc.merge(res?);
-            Ok(acc)
+) -> Result<(Vec<PathBuf>, CompileSummary)> {
+    compile_contracts_aot_batched_with_progress(args, fallback_opt, |_, _| {})
+}
+
+/// Like `compile_contracts_aot_batched`, but calls `on_progress` with the number of
+/// processed and total contracts after each batch.
+pub fn compile_contracts_aot_batched_with_progress(
+    args: &[Vec<u8>],
+    fallback_opt: Option<CompilerOptions>,
+    on_progress: impl Fn(usize, usize) + Sync,
+) -> Result<(Vec<PathBuf>, CompileSummary)> {
+    let opt = fallback_opt.unwrap_or_default();
+    let out_dir = opt.out_dir.clone();
+    let batch_size = opt.aot_batch_size.unwrap_or(DEFAULT_AOT_BATCH_SIZE).max(1);
+    let quarantine = Quarantine::load(&out_dir)?;
+    // Sorted so that the buckets of `stable_batches` are contiguous
+    let mut args = args
+        .iter()
+        .map(|arg| (revm::primitives::keccak256(arg), arg))
+        .collect::<Vec<_>>();
+    args.sort_by_key(|(bytecode_hash, _)| *bytecode_hash);
+    args.dedup_by_key(|(bytecode_hash, _)| *bytecode_hash);
+
+    // Contracts are only batched with others compiled with the same options
+    let mut groups = BTreeMap::<String, (Compiler, Vec<_>)>::new();
+    let mut options = HashMap::new();
+    let mut skipped = vec![];
+    for (bytecode_hash, arg) in args {
+        let contract_opt = opt.for_contract(arg);
+        let contract_options = contract_opt.artifact_options();
+        if let Some(rejection) = contract_opt.admission.check(bytecode_hash, arg) {
+            skipped.push(CompileOutcome::Rejected(rejection));
+            continue;
+        }
+        if quarantine.contains(&bytecode_hash, &contract_options) {
+            skipped.push(CompileOutcome::Quarantined(bytecode_hash));
+            continue;
+        }
+        groups
+            .entry(contract_options.cache_key())
+            .or_insert_with(|| (contract_opt.into(), vec![]))
+            .1
+            .push((bytecode_hash, arg));
+        options.insert(bytecode_hash, contract_options);
+    }
+    let chunks = groups
+        .values()
+        .flat_map(|(compiler, args)| {
+            stable_batches(args, batch_size)
+                .into_iter()
+                .map(move |chunk| (compiler, chunk))
         })
-        .unwrap_or_else(|| Ok(JitCompileOut::default()))
+        .collect::<Vec<_>>();
+
+    let (done, total) = (
+        AtomicUsize::new(skipped.len()),
+        options.len() + skipped.len(),
+    );
+    let results = chunks
+        .par_iter()
+        .map(|&(compiler, chunk)| {
+            let result = compile_batch_chunk(compiler, chunk);
+            on_progress(
+                done.fetch_add(chunk.len(), Ordering::Relaxed) + chunk.len(),
+                total,
+            );
+            result
+        })
+        .collect::<Vec<_>>();
+
+    let mut batch_dirs = vec![];
+    let mut outcomes = skipped;
+    for (batch_dir, batch_outcomes) in results {
+        batch_dirs.extend(batch_dir);
+        outcomes.extend(batch_outcomes);
+    }
+    let mut summary = outcomes.into_iter().collect::<CompileSummary>();
+    // Only translation errors are attributed to a single contract
+    let translate_errors = summary
+        .errors
+        .iter()
+        .filter(|e| e.kind == CompileErrorKind::Translate)
+        .cloned()
+        .collect::<Vec<_>>();
+    quarantine_errors_with(&out_dir, &translate_errors, |bytecode_hash| {
+        &options[bytecode_hash]
+    })?;
+    append_compile_stats(&out_dir, &summary.stats)?;
+    let objects = batch_dirs
+        .iter()
+        .filter_map(|batch_dir| {
+            let batch_id = batch_dir.file_name()?.to_str()?;
+            Some(batch_dir.join(format!("{batch_id}.o")))
+        })
+        .collect();
+    summary.archive = archive_objects(&opt, objects)?;
+    Ok((batch_dirs, summary))
+}
+
+/// Splits contracts sorted by hash into batches by the leading bits of their hash, using the
+/// fewest bits that keep the average batch within `batch_size`. Adding or removing a contract
+/// then only changes its own batch, unless it changes the number of bits. Buckets that still
+/// exceed `batch_size` are split into consecutive chunks.
+fn stable_batches<T>(args: &[(B256, T)], batch_size: usize) -> Vec<&[(B256, T)]> {
+    let buckets = args.len().div_ceil(batch_size).next_power_of_two();
+    let bits = buckets.trailing_zeros();
+    let bucket_of = |bytecode_hash: &B256| {
+        let prefix = u64::from_be_bytes(bytecode_hash[..8].try_into().unwrap());
+        prefix.checked_shr(64 - bits).unwrap_or(0)
+    };
+    args.chunk_by(|(a, _), (b, _)| bucket_of(a) == bucket_of(b))
+        .flat_map(|bucket| bucket.chunks(batch_size))
+        .collect()
 }
 
-fn load_compiled(path: &Path) -> Result<Vec<String>> {
-    let vec = std::fs::read_dir(path)?
-        .map(|res| {
-            res.map(|e| {
-                let path = e.path();
-                let file_name = path.file_name().unwrap().to_owned().into_string().unwrap();
-                file_name
-            })
+/// Packs the objects into one static archive if `ArtifactFormat::StaticArchive` is selected.
+fn archive_objects(opt: &CompilerOptions, mut objects: Vec<PathBuf>) -> Result<Option<PathBuf>> {
+    if opt.format != ArtifactFormat::StaticArchive || objects.is_empty() {
+        return Ok(None);
+    }
+    objects.sort();
+    let path = archive_path(&opt.out_dir, &objects);
+    revmc_toolkit_utils::misc::make_dir(path.parent().expect("archive path has a parent"))?;
+    opt.linker.archive(&objects, &path)?;
+    Ok(Some(path))
+}
+
+/// Compiles the chunk into a batch, retrying without contracts that fail translation.
+fn compile_batch_chunk(
+    compiler: &Compiler,
+    chunk: &[(B256, &Vec<u8>)],
+) -> (Option<PathBuf>, Vec<CompileOutcome>) {
+    let mut chunk = chunk.to_vec();
+    let mut outcomes = vec![];
+    while !chunk.is_empty() {
+        let bytecodes = chunk.iter().map(|(_, arg)| arg).collect::<Vec<_>>();
+        match compiler.compile_aot_batch(&bytecodes) {
+            Ok((batch_dir, stats)) if stats.is_empty() => {
+                outcomes.extend(chunk.iter().map(|_| CompileOutcome::Cached));
+                return (Some(batch_dir), outcomes);
+            }
+            Ok((batch_dir, stats)) => {
+                outcomes.extend(stats.into_iter().map(CompileOutcome::Compiled));
+                return (Some(batch_dir), outcomes);
+            }
+            Err(errors) => {
+                let failed = errors.iter().map(|e| e.bytecode_hash).collect::<Vec<_>>();
+                let is_translate_err = errors.len() == 1
+                    && errors[0].kind == CompileErrorKind::Translate
+                    && chunk.len() > 1;
+                outcomes.push(CompileOutcome::Failed(errors));
+                if !is_translate_err {
+                    break;
+                }
+                chunk.retain(|(bytecode_hash, _)| !failed.contains(bytecode_hash));
+            }
+        }
+    }
+    (None, outcomes)
+}
+
+/// Compiles the contracts once for every spec, as a function only runs on blocks of the
+/// spec it was compiled for.
+pub fn compile_contracts_aot_for_specs(
+    args: &[Vec<u8>],
+    fallback_opt: Option<CompilerOptions>,
+    spec_ids: &[SpecId],
+) -> Result<Vec<(SpecId, CompileSummary)>> {
+    let opt = fallback_opt.unwrap_or_default();
+    spec_ids
+        .iter()
+        .map(|&spec_id| {
+            let opt = opt.clone().with_spec_id(spec_id);
+            Ok((spec_id, compile_contracts_aot(args, Some(opt))?))
         })
-        .collect::<Result<Vec<_>, std::io::Error>>()?;
-    Ok(vec)
+        .collect()
+}
+
+/// Contracts rejected by the admission policy are left out and listed in
+/// `JitCompileOut::rejected`, ones failing to compile in `JitCompileOut::errors`.
+pub fn compile_contracts_jit(
+    args: &[Vec<u8>],
+    fallback_opt: Option<CompilerOptions>,
+) -> Result<JitCompileOut> {
+    let opt = fallback_opt.unwrap_or_default();
+    let out_dir = opt.out_dir.clone();
+    // Contracts are only compiled into a module with others sharing their options
+    let mut groups = BTreeMap::<String, (Compiler, Vec<_>)>::new();
+    let mut rejected = vec![];
+    for arg in args {
+        let contract_opt = opt.for_contract(arg);
+        let bytecode_hash = revm::primitives::keccak256(arg);
+        if let Some(rejection) = contract_opt.admission.check(bytecode_hash, arg) {
+            rejected.push(rejection);
+            continue;
+        }
+        groups
+            .entry(contract_opt.artifact_options().cache_key())
+            .or_insert_with(|| (contract_opt.into(), vec![]))
+            .1
+            .push(arg);
+    }
+    // todo: make the module size configurable
+    let chunks = groups
+        .values()
+        .flat_map(|(compiler, args)| args.chunks(10).map(move |chunk| (compiler, chunk)))
+        .collect::<Vec<_>>();
+    let mut out = chunks
+        .par_iter()
+        .map(|&(compiler, chunk)| compiler.compile_jit_many_or_each(chunk))
+        .reduce(JitCompileOut::default, |mut acc, out| {
+            acc.merge(out);
+            acc
+        });
+    out.rejected.extend(rejected);
+    append_compile_stats(&out_dir, &out.stats)?;
+    Ok(out)
+}
+
+/// Compiles the contracts once for every spec. The functions are told apart by
+/// `JitFunction::spec_id`.
+pub fn compile_contracts_jit_for_specs(
+    args: &[Vec<u8>],
+    fallback_opt: Option<CompilerOptions>,
+    spec_ids: &[SpecId],
+) -> Result<JitCompileOut> {
+    let opt = fallback_opt.unwrap_or_default();
+    let mut out = JitCompileOut::default();
+    for &spec_id in spec_ids {
+        out.merge(compile_contracts_jit(
+            args,
+            Some(opt.clone().with_spec_id(spec_id)),
+        )?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::keccak256;
+
+    fn sorted_hashes(seeds: impl IntoIterator<Item = u32>) -> Vec<(B256, ())> {
+        let mut hashes = seeds
+            .into_iter()
+            .map(|seed| (keccak256(seed.to_be_bytes()), ()))
+            .collect::<Vec<_>>();
+        hashes.sort_by_key(|(bytecode_hash, _)| *bytecode_hash);
+        hashes
+    }
+
+    #[test]
+    fn stable_batches_cover_all_within_size() {
+        let hashes = sorted_hashes(0..1000);
+        let batches = stable_batches(&hashes, 30);
+        assert!(batches
+            .iter()
+            .all(|batch| !batch.is_empty() && batch.len() <= 30));
+        assert_eq!(batches.concat(), hashes);
+    }
+
+    #[test]
+    fn stable_batches_only_change_the_batch_of_an_added_contract() {
+        let before = sorted_hashes(0..100);
+        let after = sorted_hashes((0..100).chain([1000]));
+        let added = keccak256(1000u32.to_be_bytes());
+        // 100 and 101 contracts both take 4 buckets, by the 2 leading bits
+        let (batches_before, batches_after) =
+            (stable_batches(&before, 30), stable_batches(&after, 30));
+        for batch in batches_before {
+            if batch[0].0[0] >> 6 != added[0] >> 6 {
+                assert!(batches_after.contains(&batch));
+            }
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::debug;
+
+/// What AOT compilation produces besides the relocatable objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ArtifactFormat {
+    /// An `a.so` per artifact or batch, loadable with `EvmCompilerFnLoader`.
+    #[default]
+    SharedObject,
+    /// One static archive of all compiled contracts, see `CompileSummary::archive`, for
+    /// linking into a binary. Artifacts only keep their objects.
+    StaticArchive,
+    /// Only the objects.
+    Objects,
+}
+
+impl ArtifactFormat {
+    /// File every artifact needs besides its object, if any.
+    pub fn library_file(&self) -> Option<&'static str> {
+        match self {
+            ArtifactFormat::SharedObject => Some("a.so"),
+            ArtifactFormat::StaticArchive | ArtifactFormat::Objects => None,
+        }
+    }
+
+    pub fn is_shared_object(&self) -> bool {
+        *self == ArtifactFormat::SharedObject
+    }
+}
+
+/// Commands turning objects into shared objects and static archives. A failing command is
+/// reported with its exit status and stderr, and is not retried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkerConfig {
+    /// C compiler driver linking shared objects.
+    pub command: PathBuf,
+    /// Extra flags, passed before the objects.
+    pub args: Vec<String>,
+    /// Archiver packing static archives.
+    pub archiver: PathBuf,
+}
+
+impl Default for LinkerConfig {
+    fn default() -> Self {
+        Self {
+            command: "cc".into(),
+            args: vec![],
+            archiver: "ar".into(),
+        }
+    }
+}
+
+impl LinkerConfig {
+    pub fn with_command(mut self, command: impl Into<PathBuf>) -> Self {
+        self.command = command.into();
+        self
+    }
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+    pub fn with_archiver(mut self, archiver: impl Into<PathBuf>) -> Self {
+        self.archiver = archiver.into();
+        self
+    }
+
+    /// Links the objects into a shared object. The builtins the functions call are left
+    /// undefined, as they are resolved from the loading process.
+    pub fn link_shared(&self, objects: &[impl AsRef<Path>], out: &Path) -> Result<()> {
+        let mut cmd = Command::new(&self.command);
+        cmd.arg("-shared").arg("-o").arg(out);
+        if cfg!(target_os = "macos") {
+            cmd.args(["-undefined", "dynamic_lookup"]);
+        }
+        cmd.args(&self.args);
+        cmd.args(objects.iter().map(AsRef::as_ref));
+        run(cmd, out)?;
+        debug!("Linked shared object file to {}", out.display());
+        Ok(())
+    }
+
+    /// Packs the objects into a static archive, replacing an existing one.
+    pub fn archive(&self, objects: &[impl AsRef<Path>], out: &Path) -> Result<()> {
+        // The archiver would otherwise keep members that are no longer given
+        match std::fs::remove_file(out) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut cmd = Command::new(&self.archiver);
+        cmd.arg("crs").arg(out);
+        cmd.args(objects.iter().map(AsRef::as_ref));
+        run(cmd, out)?;
+        debug!("Packed static archive to {}", out.display());
+        Ok(())
+    }
+}
+
+fn run(mut cmd: Command, out: &Path) -> Result<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let output = cmd
+        .output()
+        .wrap_err_with(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "{program} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    if !out.exists() {
+        return Err(eyre::eyre!("{program} didn't write {}", out.display()));
+    }
+    Ok(())
+}
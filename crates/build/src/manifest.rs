@@ -0,0 +1,261 @@
+use revm::primitives::{keccak256, SpecId, B256};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::integrity::{self, ArtifactKey, Integrity};
+use crate::link::{ArtifactFormat, LinkerConfig};
+use crate::{utils::OptimizationLevelDeseralizable, CompilerOptions};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const BATCHES_DIR: &str = "batches";
+pub const BATCH_INDEX_FILE: &str = "index.json";
+pub const ARCHIVES_DIR: &str = "archives";
+/// Static archives are named `lib<STATIC_LIB_NAME>.a`, to be linked with `-l<STATIC_LIB_NAME>`.
+pub const STATIC_LIB_NAME: &str = "revmc_aot";
+/// Bytecode the artifact was compiled from, used to smoke test the loaded function.
+pub const BYTECODE_FILE: &str = "bytecode.bin";
+pub const REVMC_VERSION: &str = env!("REVMC_VERSION");
+
+/// Subset of `CompilerOptions` that affects the produced machine code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactOptions {
+    pub spec_id: SpecId,
+    pub target: String,
+    pub target_cpu: Option<String>,
+    pub target_features: Option<String>,
+    pub opt_level: OptimizationLevelDeseralizable,
+    pub no_gas: bool,
+    pub no_len_checks: bool,
+    pub frame_pointers: bool,
+    pub debug_assertions: bool,
+    /// Left out when unset, so the cache key of existing artifacts doesn't change.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub infer_len_checks: bool,
+    /// What is produced besides the objects, and how. Left out when default like above.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub format: ArtifactFormat,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub linker: LinkerConfig,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl ArtifactOptions {
+    /// Short, stable identifier of the option set, used to name artifact directories.
+    pub fn cache_key(&self) -> String {
+        let encoded = serde_json::to_vec(self).expect("Failed to serialize artifact options");
+        hex::encode(&keccak256(encoded)[..8])
+    }
+}
+
+impl From<&CompilerOptions> for ArtifactOptions {
+    fn from(opt: &CompilerOptions) -> Self {
+        Self {
+            spec_id: opt.spec_id,
+            target: opt.target.clone(),
+            target_cpu: opt.target_cpu.clone(),
+            target_features: opt.target_features.clone(),
+            opt_level: opt.opt_level.clone(),
+            no_gas: opt.no_gas,
+            no_len_checks: opt.no_len_checks,
+            frame_pointers: opt.frame_pointers,
+            debug_assertions: opt.debug_assertions,
+            infer_len_checks: opt.infer_len_checks,
+            format: opt.format,
+            linker: opt.linker.clone(),
+        }
+    }
+}
+
+/// Directory holding the artifact for `bytecode_hash` compiled with `options`.
+/// Layout: `<out_dir>/<bytecode_hash>/<options_cache_key>/`.
+pub fn artifact_dir(out_dir: &Path, bytecode_hash: &B256, options: &ArtifactOptions) -> PathBuf {
+    out_dir
+        .join(bytecode_hash.to_string())
+        .join(options.cache_key())
+}
+
+/// Written next to every AOT artifact, describing what produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactManifest {
+    pub bytecode_hash: B256,
+    pub revmc_version: String,
+    pub options: ArtifactOptions,
+    /// What was produced besides the object. Left out for shared objects, so that manifests
+    /// signed before formats existed still verify.
+    #[serde(default, skip_serializing_if = "ArtifactFormat::is_shared_object")]
+    pub format: ArtifactFormat,
+    /// Set if stack length checks were left out, as the stack is proven to stay below
+    /// this height.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stack_height: Option<usize>,
+    #[serde(flatten)]
+    pub integrity: Integrity,
+}
+
+impl ArtifactManifest {
+    pub fn new(bytecode_hash: B256, options: ArtifactOptions) -> Self {
+        Self {
+            bytecode_hash,
+            revmc_version: REVMC_VERSION.to_string(),
+            options,
+            format: ArtifactFormat::default(),
+            max_stack_height: None,
+            integrity: Integrity::default(),
+        }
+    }
+
+    /// Records checksums of the files in `artifact_dir` and signs the manifest if a key is set.
+    pub fn seal(mut self, artifact_dir: &Path, key: Option<&ArtifactKey>) -> Result<Self> {
+        self.integrity = Integrity::of_dir(artifact_dir, MANIFEST_FILE)?;
+        if let Some(key) = key {
+            self.integrity.signature = Some(integrity::sign(&self, key)?);
+        }
+        Ok(self)
+    }
+
+    pub fn verify_signature(&self, key: &ArtifactKey) -> Result<()> {
+        let mut unsigned = self.clone();
+        let signature = unsigned.integrity.signature.take();
+        integrity::verify_signature(&unsigned, signature, key)
+    }
+
+    pub fn read(artifact_dir: &Path) -> Result<Self> {
+        let file = std::fs::File::open(artifact_dir.join(MANIFEST_FILE))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn write(&self, artifact_dir: &Path) -> Result<()> {
+        let file = std::fs::File::create(artifact_dir.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Errors if the artifact was not produced for `bytecode_hash` with `options` by the
+    /// current revmc version.
+    pub fn check(&self, bytecode_hash: &B256, options: &ArtifactOptions) -> Result<()> {
+        if &self.bytecode_hash != bytecode_hash {
+            return Err(eyre::eyre!(
+                "bytecode hash mismatch: expected {bytecode_hash} found {}",
+                self.bytecode_hash
+            ));
+        }
+        check_origin(&self.revmc_version, &self.options, options)
+    }
+}
+
+/// Directory holding the batch library `batch_id`.
+/// Layout: `<out_dir>/batches/<batch_id>/`.
+pub fn batch_dir(out_dir: &Path, batch_id: &str) -> PathBuf {
+    out_dir.join(BATCHES_DIR).join(batch_id)
+}
+
+/// Identifies a batch by its (sorted) contents and the options it was compiled with.
+pub fn batch_id(bytecode_hashes: &[B256], options: &ArtifactOptions) -> String {
+    let mut preimage = bytecode_hashes.iter().flat_map(|h| h.0).collect::<Vec<_>>();
+    preimage.extend_from_slice(options.cache_key().as_bytes());
+    hex::encode(&keccak256(preimage)[..8])
+}
+
+/// Static archive packing the objects, identified by their (sorted) paths.
+/// Layout: `<out_dir>/archives/<archive_id>/lib<STATIC_LIB_NAME>.a`.
+pub fn archive_path(out_dir: &Path, objects: &[PathBuf]) -> PathBuf {
+    let preimage = objects
+        .iter()
+        .map(|path| path.strip_prefix(out_dir).unwrap_or(path).to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let archive_id = hex::encode(&keccak256(preimage)[..8]);
+    out_dir
+        .join(ARCHIVES_DIR)
+        .join(archive_id)
+        .join(format!("lib{STATIC_LIB_NAME}.a"))
+}
+
+/// Symbol index of a shared library holding several contracts. Every bytecode hash
+/// is exported as a symbol named after the hash, and its bytecode is stored as `<hash>.bin`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchIndex {
+    pub revmc_version: String,
+    pub options: ArtifactOptions,
+    pub bytecode_hashes: Vec<B256>,
+    /// See `ArtifactManifest::format`.
+    #[serde(default, skip_serializing_if = "ArtifactFormat::is_shared_object")]
+    pub format: ArtifactFormat,
+    /// Contracts compiled without stack length checks, see `ArtifactManifest::max_stack_height`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_stack_heights: BTreeMap<B256, usize>,
+    #[serde(flatten)]
+    pub integrity: Integrity,
+}
+
+impl BatchIndex {
+    pub fn new(bytecode_hashes: Vec<B256>, options: ArtifactOptions) -> Self {
+        Self {
+            revmc_version: REVMC_VERSION.to_string(),
+            options,
+            bytecode_hashes,
+            format: ArtifactFormat::default(),
+            max_stack_heights: BTreeMap::new(),
+            integrity: Integrity::default(),
+        }
+    }
+
+    /// Records checksums of the files in `batch_dir` and signs the index if a key is set.
+    pub fn seal(mut self, batch_dir: &Path, key: Option<&ArtifactKey>) -> Result<Self> {
+        self.integrity = Integrity::of_dir(batch_dir, BATCH_INDEX_FILE)?;
+        if let Some(key) = key {
+            self.integrity.signature = Some(integrity::sign(&self, key)?);
+        }
+        Ok(self)
+    }
+
+    pub fn verify_signature(&self, key: &ArtifactKey) -> Result<()> {
+        let mut unsigned = self.clone();
+        let signature = unsigned.integrity.signature.take();
+        integrity::verify_signature(&unsigned, signature, key)
+    }
+
+    pub fn read(batch_dir: &Path) -> Result<Self> {
+        let file = std::fs::File::open(batch_dir.join(BATCH_INDEX_FILE))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn write(&self, batch_dir: &Path) -> Result<()> {
+        let file = std::fs::File::create(batch_dir.join(BATCH_INDEX_FILE))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn check(&self, options: &ArtifactOptions) -> Result<()> {
+        check_origin(&self.revmc_version, &self.options, options)
+    }
+}
+
+fn check_origin(
+    revmc_version: &str,
+    found: &ArtifactOptions,
+    expected: &ArtifactOptions,
+) -> Result<()> {
+    if revmc_version != REVMC_VERSION {
+        return Err(eyre::eyre!(
+            "revmc version mismatch: expected {REVMC_VERSION} found {revmc_version}"
+        ));
+    }
+    if found != expected {
+        return Err(eyre::eyre!(
+            "compiler options mismatch: expected {expected:?} found {found:?}"
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use revm::primitives::{keccak256, Address, B256};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::{utils::OptimizationLevelDeseralizable, CompilerOptions};
+
+/// Rules overriding the compiler options of the contracts they match, read from a TOML or
+/// JSON file. Every matching rule is applied in order, so later rules win.
+///
+/// ```toml
+/// [[rules]]
+/// match = { sizeAbove = 20000 }
+/// overrides = { optLevel = "Less" }
+///
+/// [[rules]]
+/// match = { rankBelow = 10 }
+/// overrides = { optLevel = "Aggressive", inferLenChecks = true }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionsPolicy {
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+    /// Facts the predicates can't derive from the bytecode, by bytecode hash.
+    #[serde(skip)]
+    contracts: HashMap<B256, ContractFacts>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyRule {
+    #[serde(rename = "match", default)]
+    pub matcher: PolicyMatch,
+    pub overrides: OptionOverrides,
+}
+
+/// Matches contracts meeting all of the set conditions, so an empty one matches all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyMatch {
+    /// Any of these bytecodes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bytecode_hashes: Vec<B256>,
+    /// Contracts deployed at any of these addresses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<Address>,
+    /// Bytecodes larger than this many bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_above: Option<usize>,
+    /// Bytecodes smaller than this many bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_below: Option<usize>,
+    /// Contracts among the top gas guzzlers, where the top one has rank 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rank_below: Option<usize>,
+}
+
+/// Options a rule sets, the unset ones are left as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opt_level: Option<OptimizationLevelDeseralizable>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_gas: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_len_checks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub infer_len_checks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_pointers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_assertions: Option<bool>,
+}
+
+/// What is known about a contract besides its bytecode.
+#[derive(Debug, Clone, Default)]
+pub struct ContractFacts {
+    pub addresses: Vec<Address>,
+    pub guzzler_rank: Option<usize>,
+}
+
+impl OptionsPolicy {
+    /// Reads a `.toml` file as TOML and anything else as JSON.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("Failed to read policy {}: {e}", path.display()))?;
+        let policy: Result<Self> = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content).map_err(Into::into)
+        } else {
+            serde_json::from_str(&content).map_err(Into::into)
+        };
+        policy.wrap_err_with(|| format!("Invalid policy {}", path.display()))
+    }
+
+    /// Writes the rules to a `.toml` file as TOML and to anything else as JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            revmc_toolkit_utils::misc::make_dir(dir)?;
+        }
+        let content = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::to_string_pretty(self)?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        std::fs::write(path, content)
+            .map_err(|e| eyre::eyre!("Failed to write policy {}: {e}", path.display()))
+    }
+
+    pub fn with_addresses(mut self, addresses: impl IntoIterator<Item = (B256, Address)>) -> Self {
+        for (bytecode_hash, address) in addresses {
+            let facts = self.contracts.entry(bytecode_hash).or_default();
+            facts.addresses.push(address);
+        }
+        self
+    }
+
+    /// Ranks the bytecodes by their position, the first being the top gas guzzler.
+    pub fn with_guzzler_ranks(mut self, bytecode_hashes: impl IntoIterator<Item = B256>) -> Self {
+        for (rank, bytecode_hash) in bytecode_hashes.into_iter().enumerate() {
+            let facts = self.contracts.entry(bytecode_hash).or_default();
+            facts.guzzler_rank = Some(facts.guzzler_rank.map_or(rank, |r| r.min(rank)));
+        }
+        self
+    }
+
+    /// Options of the contract after applying every rule that matches it.
+    pub fn apply(&self, bytecode: &[u8], mut opt: CompilerOptions) -> CompilerOptions {
+        let bytecode_hash = keccak256(bytecode);
+        let facts = self.contracts.get(&bytecode_hash);
+        for rule in &self.rules {
+            if rule.matcher.matches(&bytecode_hash, bytecode.len(), facts) {
+                opt = rule.overrides.apply(opt);
+            }
+        }
+        opt
+    }
+}
+
+impl PolicyMatch {
+    fn matches(&self, bytecode_hash: &B256, size: usize, facts: Option<&ContractFacts>) -> bool {
+        let addresses = facts.map_or(&[][..], |f| &f.addresses);
+        let rank = facts.and_then(|f| f.guzzler_rank);
+        (self.bytecode_hashes.is_empty() || self.bytecode_hashes.contains(bytecode_hash))
+            && (self.addresses.is_empty() || addresses.iter().any(|a| self.addresses.contains(a)))
+            && self.size_above.map_or(true, |n| size > n)
+            && self.size_below.map_or(true, |n| size < n)
+            && self
+                .rank_below
+                .map_or(true, |k| rank.is_some_and(|rank| rank < k))
+    }
+}
+
+impl OptionOverrides {
+    pub fn apply(&self, mut opt: CompilerOptions) -> CompilerOptions {
+        if let Some(opt_level) = &self.opt_level {
+            opt.opt_level = opt_level.clone();
+        }
+        opt.no_gas = self.no_gas.unwrap_or(opt.no_gas);
+        opt.no_len_checks = self.no_len_checks.unwrap_or(opt.no_len_checks);
+        opt.infer_len_checks = self.infer_len_checks.unwrap_or(opt.infer_len_checks);
+        opt.frame_pointers = self.frame_pointers.unwrap_or(opt.frame_pointers);
+        opt.debug_assertions = self.debug_assertions.unwrap_or(opt.debug_assertions);
+        opt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    const POLICY: &str = r#"
+[[rules]]
+match = { sizeAbove = 2 }
+overrides = { optLevel = "Less", noGas = true }
+
+[[rules]]
+match = { rankBelow = 1 }
+overrides = { optLevel = "Aggressive", inferLenChecks = true }
+"#;
+
+    #[test]
+    fn policy_round_trips_through_toml_and_json() {
+        let policy = toml::from_str::<OptionsPolicy>(POLICY).unwrap();
+        let dir = temp_dir("policy");
+        for file in ["policy.toml", "policy.json"] {
+            let path = dir.join(file);
+            policy.write(&path).unwrap();
+            let read = OptionsPolicy::read(&path).unwrap();
+            assert_eq!(read.rules.len(), 2);
+            assert_eq!(read.rules[0].matcher.size_above, Some(2));
+            assert_eq!(read.rules[1].matcher.rank_below, Some(1));
+            for (read, written) in read.rules.iter().zip(&policy.rules) {
+                assert_eq!(read.overrides, written.overrides);
+            }
+        }
+    }
+
+    #[test]
+    fn later_rules_win() {
+        let (small, large) = (vec![0x00], vec![0x60, 0x00, 0x00]);
+        let policy = toml::from_str::<OptionsPolicy>(POLICY)
+            .unwrap()
+            .with_guzzler_ranks([keccak256(&large), keccak256(&small)]);
+
+        let opt = policy.apply(&large, CompilerOptions::default());
+        assert_eq!(opt.opt_level, OptimizationLevelDeseralizable::Aggressive);
+        assert!(opt.no_gas && opt.infer_len_checks);
+
+        let opt = policy.apply(&small, CompilerOptions::default());
+        assert_eq!(opt.opt_level, OptimizationLevelDeseralizable::Default);
+        assert!(!opt.no_gas && !opt.infer_len_checks);
+    }
+
+    #[test]
+    fn rules_match_addresses_of_the_contract() {
+        let (bytecode, address) = (vec![0x00], Address::repeat_byte(1));
+        let mut policy = OptionsPolicy::default().with_addresses([(keccak256(&bytecode), address)]);
+        policy.rules.push(PolicyRule {
+            matcher: PolicyMatch {
+                addresses: vec![address],
+                ..Default::default()
+            },
+            overrides: OptionOverrides {
+                frame_pointers: Some(true),
+                ..Default::default()
+            },
+        });
+        assert!(
+            policy
+                .apply(&bytecode, CompilerOptions::default())
+                .frame_pointers
+        );
+        assert!(
+            !policy
+                .apply(&[0x01], CompilerOptions::default())
+                .frame_pointers
+        );
+    }
+}
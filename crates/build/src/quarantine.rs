@@ -0,0 +1,126 @@
+use revm::primitives::B256;
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::error::{CompileError, CompileErrorKind};
+use crate::manifest::{ArtifactOptions, REVMC_VERSION};
+
+pub const QUARANTINE_FILE: &str = "quarantine.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineEntry {
+    pub bytecode_hash: B256,
+    /// `ArtifactOptions::cache_key` of the failed compilation.
+    pub options_key: String,
+    pub revmc_version: String,
+    pub kind: CompileErrorKind,
+    pub message: String,
+    /// Unix timestamp in seconds.
+    pub failed_at: u64,
+}
+
+/// Contracts that failed to compile, stored in the AOT out dir. Entries only apply to the
+/// options and revmc version they failed with, so they are retried once either changes.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    path: PathBuf,
+    entries: Vec<QuarantineEntry>,
+}
+
+impl Quarantine {
+    pub fn load(out_dir: &Path) -> Result<Self> {
+        let path = out_dir.join(QUARANTINE_FILE);
+        let entries = if path.exists() {
+            serde_json::from_reader(std::fs::File::open(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(Self { path, entries })
+    }
+
+    pub fn get(&self, bytecode_hash: &B256, options: &ArtifactOptions) -> Option<&QuarantineEntry> {
+        let options_key = options.cache_key();
+        self.entries.iter().find(|entry| {
+            &entry.bytecode_hash == bytecode_hash
+                && entry.options_key == options_key
+                && entry.revmc_version == REVMC_VERSION
+        })
+    }
+
+    pub fn contains(&self, bytecode_hash: &B256, options: &ArtifactOptions) -> bool {
+        self.get(bytecode_hash, options).is_some()
+    }
+
+    /// Records the error if it's caused by the bytecode. Returns whether it was recorded.
+    pub fn insert(&mut self, error: &CompileError, options: &ArtifactOptions) -> bool {
+        if !error.kind.is_persistent() || self.contains(&error.bytecode_hash, options) {
+            return false;
+        }
+        self.entries.push(QuarantineEntry {
+            bytecode_hash: error.bytecode_hash,
+            options_key: options.cache_key(),
+            revmc_version: REVMC_VERSION.to_string(),
+            kind: error.kind,
+            message: error.message.clone(),
+            failed_at: crate::utils::unix_timestamp(),
+        });
+        true
+    }
+
+    pub fn entries(&self) -> &[QuarantineEntry] {
+        &self.entries
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            revmc_toolkit_utils::misc::make_dir(dir)?;
+        }
+        // Written aside and renamed, as a torn file would fail every later `load`
+        let tmp = crate::utils::tmp_path(&self.path);
+        let result = write_entries(&tmp, &self.entries)
+            .and_then(|()| std::fs::rename(&tmp, &self.path).map_err(Into::into));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result
+    }
+}
+
+fn write_entries(path: &Path, entries: &[QuarantineEntry]) -> Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, entries)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(())
+}
+
+/// Adds the errors to the quarantine of the out dir.
+pub fn quarantine_errors(
+    out_dir: &Path,
+    options: &ArtifactOptions,
+    errors: &[CompileError],
+) -> Result<()> {
+    quarantine_errors_with(out_dir, errors, |_| options)
+}
+
+/// Like `quarantine_errors`, for contracts compiled with different options.
+pub fn quarantine_errors_with<'a>(
+    out_dir: &Path,
+    errors: &[CompileError],
+    options_of: impl Fn(&B256) -> &'a ArtifactOptions,
+) -> Result<()> {
+    let mut quarantine = Quarantine::load(out_dir)?;
+    let mut changed = false;
+    for error in errors {
+        changed |= quarantine.insert(error, options_of(&error.bytecode_hash));
+    }
+    if changed {
+        quarantine.save()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use revm::primitives::{keccak256, B256};
+
+use eyre::{OptionExt, Result, WrapErr};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+use crate::archive::{pack_artifact, unpack_archive, TargetInfo};
+use crate::manifest::{artifact_dir, ArtifactOptions, REVMC_VERSION};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest artifact the server stores or the client downloads.
+const MAX_BODY_SIZE: usize = 256 << 20;
+/// Longest start or header line read.
+const MAX_LINE_SIZE: u64 = 8 << 10;
+/// Most header lines read.
+const MAX_HEADERS: usize = 100;
+
+/// AOT artifacts shared between machines through a plain HTTP store. An artifact is read with
+/// `GET` and stored with `PUT` at `{url}/{env key}/{bytecode hash}/{options key}`, packed like
+/// an `export_cache` archive. The env key covers the revmc version and the resolved target, so
+/// only artifacts the host can run are fetched. Any server mapping paths to blobs will do, eg.
+/// `serve_remote_cache`.
+#[derive(Debug, Clone)]
+pub struct RemoteCache {
+    /// `host:port` to connect to.
+    authority: String,
+    /// Path prefix, empty or starting with a slash.
+    prefix: String,
+}
+
+impl RemoteCache {
+    /// Only plain `http://` URLs are supported, eg. `http://cache.local:8080/aot`.
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_eyre("Remote cache URL must start with http://")?;
+        let (authority, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            return Err(eyre::eyre!("Missing host in remote cache URL {url}"));
+        }
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        let prefix = prefix.trim_matches('/');
+        Ok(Self {
+            authority,
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("/{prefix}")
+            },
+        })
+    }
+
+    /// Downloads the artifact into the out dir. Returns whether the store had it. Nothing is
+    /// written if the archive has files outside the dir of the artifact.
+    pub fn fetch(
+        &self,
+        out_dir: &Path,
+        bytecode_hash: &B256,
+        options: &ArtifactOptions,
+    ) -> Result<bool> {
+        let (status, body) = self.request("GET", &self.path(bytecode_hash, options), &[])?;
+        match status {
+            200 => {
+                let within = artifact_dir(Path::new(""), bytecode_hash, options);
+                unpack_archive(out_dir, &within, body.as_slice())?;
+                debug!("Fetched {bytecode_hash} from the remote cache");
+                Ok(true)
+            }
+            404 => Ok(false),
+            _ => Err(eyre::eyre!("Remote cache GET returned {status}")),
+        }
+    }
+
+    /// Uploads the artifact of the out dir.
+    pub fn upload(
+        &self,
+        out_dir: &Path,
+        bytecode_hash: &B256,
+        options: &ArtifactOptions,
+    ) -> Result<()> {
+        let dir = artifact_dir(out_dir, bytecode_hash, options);
+        let archive = pack_artifact(out_dir, &dir, *bytecode_hash)?;
+        let (status, _) = self.request("PUT", &self.path(bytecode_hash, options), &archive)?;
+        if !(200..300).contains(&status) {
+            return Err(eyre::eyre!("Remote cache PUT returned {status}"));
+        }
+        debug!("Uploaded {bytecode_hash} to the remote cache");
+        Ok(())
+    }
+
+    fn path(&self, bytecode_hash: &B256, options: &ArtifactOptions) -> String {
+        format!(
+            "{}/{}/{bytecode_hash}/{}",
+            self.prefix,
+            env_key(options),
+            options.cache_key()
+        )
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+        let mut stream = TcpStream::connect(&self.authority)
+            .wrap_err_with(|| format!("Failed to connect to {}", self.authority))?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.authority,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let (status_line, headers) = read_head(&mut reader)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_eyre("Invalid HTTP status line")?;
+        let mut body = vec![];
+        match content_length(&headers)? {
+            Some(len) if len > MAX_BODY_SIZE => {
+                return Err(eyre::eyre!(
+                    "Remote cache response of {len} bytes is too large"
+                ));
+            }
+            Some(len) => {
+                body.resize(len, 0);
+                reader.read_exact(&mut body)?;
+            }
+            None => {
+                reader
+                    .take(MAX_BODY_SIZE as u64 + 1)
+                    .read_to_end(&mut body)?;
+                if body.len() > MAX_BODY_SIZE {
+                    return Err(eyre::eyre!("Remote cache response is too large"));
+                }
+            }
+        }
+        Ok((status, body))
+    }
+}
+
+/// Identifies the environments that can share artifacts: the revmc version and the target the
+/// options resolve to on this host.
+fn env_key(options: &ArtifactOptions) -> String {
+    let target = TargetInfo::of_options(options, &TargetInfo::host());
+    let preimage = format!(
+        "{REVMC_VERSION}\n{}\n{}\n{}",
+        target.triple, target.cpu, target.features
+    );
+    hex::encode(&keccak256(preimage)[..8])
+}
+
+/// Serves a remote cache store from the dir, a thread per connection, until the listener
+/// fails. Meant for tests and self-hosting on a trusted network, it has no authentication.
+pub fn serve_remote_cache(listener: TcpListener, dir: &Path) -> Result<()> {
+    revmc_toolkit_utils::misc::make_dir(dir)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let dir = dir.to_path_buf();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &dir) {
+                warn!("Remote cache request failed: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, dir: &Path) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (request_line, headers) = read_head(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    debug!("{method} {path}");
+    let Some(file) = store_path(dir, path) else {
+        return respond(&mut stream, 400, b"invalid path");
+    };
+    match method {
+        "GET" => match std::fs::read(&file) {
+            Ok(body) => respond(&mut stream, 200, &body),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => respond(&mut stream, 404, b""),
+            Err(e) => Err(e.into()),
+        },
+        "PUT" => {
+            let Some(len) = content_length(&headers)? else {
+                return respond(&mut stream, 411, b"");
+            };
+            if len > MAX_BODY_SIZE {
+                return respond(&mut stream, 413, b"");
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body)?;
+            revmc_toolkit_utils::misc::make_dir(file.parent().ok_or_eyre("Invalid path")?)?;
+            // Written aside and renamed, so that a GET never sees a partial artifact
+            static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+            let tmp = file.with_extension(format!(
+                "tmp{}",
+                TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::write(&tmp, &body)?;
+            std::fs::rename(&tmp, &file)?;
+            respond(&mut stream, 201, b"")
+        }
+        _ => respond(&mut stream, 405, b""),
+    }
+}
+
+/// File of the path in the store, if every segment of it is a plain name.
+fn store_path(dir: &Path, path: &str) -> Option<PathBuf> {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    let valid = segments.iter().all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    valid.then(|| {
+        segments
+            .iter()
+            .fold(dir.to_path_buf(), |acc, s| acc.join(s))
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &[u8]) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads the start line and the headers, with lowercased names.
+fn read_head(reader: &mut impl BufRead) -> Result<(String, Vec<(String, String)>)> {
+    let start_line = read_line(reader)?.unwrap_or_default();
+    let mut headers = vec![];
+    let mut lines = 0;
+    while let Some(line) = read_line(reader)? {
+        if line.is_empty() {
+            break;
+        }
+        lines += 1;
+        if lines > MAX_HEADERS {
+            return Err(eyre::eyre!("More than {MAX_HEADERS} HTTP header lines"));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    Ok((start_line, headers))
+}
+
+/// Reads a line without its line break, `None` at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if (&mut *reader).take(MAX_LINE_SIZE).read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && line.len() as u64 == MAX_LINE_SIZE {
+        return Err(eyre::eyre!("HTTP line longer than {MAX_LINE_SIZE} bytes"));
+    }
+    Ok(Some(line.trim_end().to_string()))
+}
+
+fn content_length(headers: &[(String, String)]) -> Result<Option<usize>> {
+    headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse().wrap_err("Invalid Content-Length"))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::MANIFEST_FILE;
+    use crate::test_utils::{temp_dir, write_artifact};
+    use crate::CompilerOptions;
+
+    fn serve(name: &str) -> RemoteCache {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/aot", listener.local_addr().unwrap());
+        let dir = temp_dir(name);
+        std::thread::spawn(move || serve_remote_cache(listener, &dir));
+        RemoteCache::new(&url).unwrap()
+    }
+
+    #[test]
+    fn serve_stores_and_returns_blobs() {
+        let remote = serve("remote-blobs");
+        assert_eq!(remote.request("GET", "/aot/a/b", &[]).unwrap().0, 404);
+        assert_eq!(remote.request("PUT", "/aot/a/b", b"blob").unwrap().0, 201);
+        assert_eq!(
+            remote.request("GET", "/aot/a/b", &[]).unwrap(),
+            (200, b"blob".to_vec())
+        );
+        assert_eq!(remote.request("GET", "/aot/../b", &[]).unwrap().0, 400);
+        assert_eq!(remote.request("DELETE", "/aot/a/b", &[]).unwrap().0, 405);
+    }
+
+    #[test]
+    fn upload_fetch_round_trip() {
+        let remote = serve("remote-store");
+        let (src, dst) = (temp_dir("remote-src"), temp_dir("remote-dst"));
+        let options = CompilerOptions::default().artifact_options();
+        let bytecode_hash = write_artifact(&src, &[0x60, 0x00, 0x00], &options);
+
+        assert!(!remote.fetch(&dst, &bytecode_hash, &options).unwrap());
+        remote.upload(&src, &bytecode_hash, &options).unwrap();
+        assert!(remote.fetch(&dst, &bytecode_hash, &options).unwrap());
+        let manifest = |out_dir| {
+            std::fs::read(artifact_dir(out_dir, &bytecode_hash, &options).join(MANIFEST_FILE))
+                .unwrap()
+        };
+        assert_eq!(manifest(&src), manifest(&dst));
+    }
+
+    #[test]
+    fn fetch_rejects_files_of_other_artifacts() {
+        let remote = serve("remote-other");
+        let (src, dst) = (temp_dir("remote-other-src"), temp_dir("remote-other-dst"));
+        let options = CompilerOptions::default().artifact_options();
+        let stored = write_artifact(&src, &[0x00], &options);
+        let requested = keccak256([0x01]);
+        let archive = pack_artifact(&src, &artifact_dir(&src, &stored, &options), stored).unwrap();
+        let path = remote.path(&requested, &options);
+        assert_eq!(remote.request("PUT", &path, &archive).unwrap().0, 201);
+
+        assert!(remote.fetch(&dst, &requested, &options).is_err());
+        assert!(!artifact_dir(&dst, &stored, &options).exists());
+    }
+
+    #[test]
+    fn read_head_limits_lines() {
+        let mut head = "HTTP/1.1 200 OK\r\n".to_string();
+        for i in 0..=MAX_HEADERS {
+            head += &format!("x-{i}: y\r\n");
+        }
+        head += "\r\n";
+        assert!(read_head(&mut head.as_bytes()).is_err());
+
+        let long = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_LINE_SIZE as usize)
+        );
+        assert!(read_head(&mut long.as_bytes()).is_err());
+
+        let (start_line, headers) =
+            read_head(&mut "GET / HTTP/1.1\r\nContent-Length: 3\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(start_line, "GET / HTTP/1.1");
+        assert_eq!(content_length(&headers).unwrap(), Some(3));
+    }
+}
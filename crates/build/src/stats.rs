@@ -0,0 +1,103 @@
+use revm::primitives::B256;
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use crate::manifest::ArtifactOptions;
+
+/// JSON lines file in the AOT out dir that every compilation appends its stats to.
+pub const COMPILE_STATS_FILE: &str = "compile_stats.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompileMode {
+    Aot,
+    Jit,
+}
+
+/// Cost of compiling a single contract.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileStats {
+    pub bytecode_hash: B256,
+    pub mode: CompileMode,
+    pub options: ArtifactOptions,
+    pub bytecode_size: usize,
+    /// Contracts compiled into the same module. Codegen and link time, and the object
+    /// size, are those of the whole module.
+    pub module_size: usize,
+    pub translate_ms: f64,
+    /// revmc runs the LLVM optimizer while emitting machine code, so both are measured
+    /// together.
+    pub optimize_codegen_ms: f64,
+    pub link_ms: Option<f64>,
+    pub object_size: Option<u64>,
+    /// Unix timestamp in seconds.
+    pub compiled_at: u64,
+}
+
+impl CompileStats {
+    pub(crate) fn new(
+        bytecode_hash: B256,
+        mode: CompileMode,
+        options: ArtifactOptions,
+        bytecode_size: usize,
+    ) -> Self {
+        Self {
+            bytecode_hash,
+            mode,
+            options,
+            bytecode_size,
+            module_size: 1,
+            translate_ms: 0.,
+            optimize_codegen_ms: 0.,
+            link_ms: None,
+            object_size: None,
+            compiled_at: crate::utils::unix_timestamp(),
+        }
+    }
+
+    pub fn total_ms(&self) -> f64 {
+        self.translate_ms + self.optimize_codegen_ms + self.link_ms.unwrap_or_default()
+    }
+}
+
+pub(crate) fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1e3
+}
+
+pub fn append_compile_stats(out_dir: &Path, stats: &[CompileStats]) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    revmc_toolkit_utils::misc::make_dir(out_dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(out_dir.join(COMPILE_STATS_FILE))?;
+    let mut writer = BufWriter::new(file);
+    for record in stats {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_compile_stats(out_dir: &Path) -> Result<Vec<CompileStats>> {
+    let path = out_dir.join(COMPILE_STATS_FILE);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
@@ -0,0 +1,45 @@
+use revm::primitives::{keccak256, B256};
+
+use std::path::{Path, PathBuf};
+
+use crate::link::ArtifactFormat;
+use crate::manifest::{artifact_dir, ArtifactManifest, ArtifactOptions, BYTECODE_FILE};
+
+/// Empty dir for a test, under the system temp dir.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("revmc-toolkit-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a complete shared object artifact of the bytecode with placeholder files.
+pub(crate) fn write_artifact(out_dir: &Path, bytecode: &[u8], options: &ArtifactOptions) -> B256 {
+    write_artifact_with_format(out_dir, bytecode, options, ArtifactFormat::SharedObject)
+}
+
+/// Like `write_artifact`, with the library of `format`, if any.
+pub(crate) fn write_artifact_with_format(
+    out_dir: &Path,
+    bytecode: &[u8],
+    options: &ArtifactOptions,
+    format: ArtifactFormat,
+) -> B256 {
+    let bytecode_hash = keccak256(bytecode);
+    let dir = artifact_dir(out_dir, &bytecode_hash, options);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(format!("{bytecode_hash}.o")), b"object").unwrap();
+    if let Some(library) = format.library_file() {
+        std::fs::write(dir.join(library), b"library").unwrap();
+    }
+    std::fs::write(dir.join(BYTECODE_FILE), bytecode).unwrap();
+    ArtifactManifest {
+        format,
+        ..ArtifactManifest::new(bytecode_hash, options.clone())
+    }
+    .seal(&dir, None)
+    .unwrap()
+    .write(&dir)
+    .unwrap();
+    bytecode_hash
+}
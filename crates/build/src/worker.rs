@@ -0,0 +1,280 @@
+use revm::primitives::{keccak256, Bytes, B256};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+use crate::error::{CompileError, CompileErrorKind, CompileResultExt};
+use crate::stats::CompileStats;
+use crate::{Compiler, CompilerOptions};
+
+/// Command starting a compile worker, which serves requests with `run_compile_worker`.
+/// Compiling in workers keeps an LLVM assertion or abort from taking down the caller.
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Most workers running at once.
+    pub workers: usize,
+}
+
+impl WorkerConfig {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            workers: rayon::current_num_threads(),
+        }
+    }
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerRequest {
+    bytecode: Bytes,
+    /// Options of this contract, with the policy already applied.
+    options: CompilerOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerResponse {
+    bytecode_hash: B256,
+    result: Result<WorkerOutput, CompileError>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerOutput {
+    artifact_dir: PathBuf,
+    stats: CompileStats,
+}
+
+/// Compiles the AOT requests read from `input` and writes a response for each to `output`,
+/// one JSON line each, until `input` is closed. Nothing else may be written to `output`.
+pub fn run_compile_worker(input: impl BufRead, mut output: impl Write) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let WorkerRequest { bytecode, options } = serde_json::from_str(&line)?;
+        let bytecode_hash = keccak256(&bytecode);
+        let artifact_dir = options.artifact_dir(&bytecode_hash);
+        let result = Compiler::from(options)
+            .compile_aot(&bytecode)
+            .map(|stats| WorkerOutput {
+                artifact_dir,
+                stats,
+            });
+        let response = WorkerResponse {
+            bytecode_hash,
+            result,
+        };
+        serde_json::to_writer(&mut output, &response)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Workers are started on demand, reused while they are healthy and killed once they crash,
+/// time out or the pool is dropped.
+pub(crate) struct WorkerPool {
+    config: WorkerConfig,
+    timeout: Option<Duration>,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: Vec<WorkerProcess>,
+    live: usize,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(config: WorkerConfig, timeout: Option<Duration>) -> Self {
+        Self {
+            config,
+            timeout,
+            state: Default::default(),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Compiles the bytecode in a worker. A crashed worker is reported as a `Crash` of the
+    /// bytecode and one taking longer than the timeout as a `Timeout`.
+    pub(crate) fn compile(
+        &self,
+        bytecode: &[u8],
+        options: &CompilerOptions,
+    ) -> Result<CompileStats, CompileError> {
+        let bytecode_hash = keccak256(bytecode);
+        let request = serde_json::to_string(&WorkerRequest {
+            bytecode: bytecode.to_vec().into(),
+            options: options.clone(),
+        })
+        .compile_err(bytecode_hash, CompileErrorKind::Worker)?;
+        let mut worker = self
+            .checkout()
+            .compile_err(bytecode_hash, CompileErrorKind::Worker)?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        match worker.exchange(&request, &bytecode_hash, deadline) {
+            Ok(result) => {
+                self.checkin(Some(worker));
+                result.map(|output| {
+                    debug!(
+                        "Worker compiled {bytecode_hash} into {:?}",
+                        output.artifact_dir
+                    );
+                    output.stats
+                })
+            }
+            Err((kind, message)) => {
+                drop(worker);
+                self.checkin(None);
+                Err(CompileError::new(bytecode_hash, kind, message))
+            }
+        }
+    }
+
+    fn checkout(&self) -> std::io::Result<WorkerProcess> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(worker) = state.idle.pop() {
+                return Ok(worker);
+            }
+            if state.live < self.config.workers {
+                state.live += 1;
+                drop(state);
+                return WorkerProcess::spawn(&self.config).map_err(|e| {
+                    self.checkin(None);
+                    e
+                });
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Returns a healthy worker to the pool, or frees the slot of a dead one.
+    fn checkin(&self, worker: Option<WorkerProcess>) {
+        let mut state = self.state.lock().unwrap();
+        match worker {
+            Some(worker) => state.idle.push(worker),
+            None => state.live -= 1,
+        }
+        self.available.notify_one();
+    }
+}
+
+struct WorkerProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines of the worker's stdout, read on a separate thread so that waiting on them can
+    /// time out.
+    lines: Receiver<String>,
+}
+
+impl WorkerProcess {
+    fn spawn(config: &WorkerConfig) -> std::io::Result<Self> {
+        let mut child = Command::new(&config.program)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        debug!("Started compile worker {}", child.id());
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    /// Sends the request and waits for its response. Lines that aren't the response (eg.
+    /// stray output of the worker) are skipped.
+    fn exchange(
+        &mut self,
+        request: &str,
+        bytecode_hash: &B256,
+        deadline: Option<Instant>,
+    ) -> Result<Result<WorkerOutput, CompileError>, (CompileErrorKind, String)> {
+        if let Err(e) = writeln!(self.stdin, "{request}").and_then(|_| self.stdin.flush()) {
+            return Err((CompileErrorKind::Crash, self.exit_message(e)));
+        }
+        loop {
+            let line = match deadline {
+                Some(deadline) => self
+                    .lines
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => self
+                    .lines
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let line = match line {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err((
+                        CompileErrorKind::Timeout,
+                        "worker gave no result in time".to_string(),
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err((CompileErrorKind::Crash, self.exit_message("no response")));
+                }
+            };
+            match serde_json::from_str::<WorkerResponse>(&line) {
+                Ok(response) if &response.bytecode_hash == bytecode_hash => {
+                    return Ok(response.result)
+                }
+                _ => debug!("Skipping worker output: {line}"),
+            }
+        }
+    }
+
+    fn exit_message(&mut self, reason: impl std::fmt::Display) -> String {
+        let _ = self.child.kill();
+        match self.child.wait() {
+            Ok(status) => format!("worker died ({status}): {reason}"),
+            Err(_) => format!("worker died: {reason}"),
+        }
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -1,51 +1,81 @@
 pub use libloading::Library;
-use revm::{handler::register::EvmHandler, primitives::B256, Database};
+use revm::{
+    handler::register::EvmHandler,
+    primitives::{keccak256, Bytecode, SpecId, B256},
+    Database,
+};
 pub use revmc::EvmCompilerFn;
 
 use revm::primitives::Address;
-use revmc_toolkit_build::{JitCompileCtx, JitCompileOut};
+use revmc_toolkit_build::{JitCompileOut, JitFunction};
 use rustc_hash::FxHashMap;
 use std::sync::Arc;
 
+use crate::lazy::LazyAotFns;
+use crate::overrides::ExecutionOverrides;
+use crate::registry::{FnRegistry, RegistryView};
+use crate::tiered::TieredJit;
+
+/// Compiled functions by bytecode hash and the spec they were compiled for.
 #[derive(Default, Clone)]
-pub struct EvmCompilerFns(pub Arc<FxHashMap<B256, (EvmCompilerFn, ReferenceDropObject)>>);
+pub struct EvmCompilerFns(pub Arc<FxHashMap<(B256, SpecId), (EvmCompilerFn, ReferenceDropObject)>>);
 
 impl EvmCompilerFns {
-    pub fn get(&self, bytecode_hash: &B256) -> Option<&(EvmCompilerFn, ReferenceDropObject)> {
-        self.0.get(bytecode_hash)
+    pub fn get(
+        &self,
+        bytecode_hash: &B256,
+        spec_id: SpecId,
+    ) -> Option<&(EvmCompilerFn, ReferenceDropObject)> {
+        self.0.get(&(*bytecode_hash, spec_id))
+    }
+
+    /// Adds the functions of `other`, eg. ones compiled for another spec.
+    pub fn merge(&mut self, other: Self) {
+        let fns = Arc::make_mut(&mut self.0);
+        fns.extend(other.0.iter().map(|(k, v)| (*k, v.clone())));
     }
 }
 
-impl From<Vec<(B256, EvmCompilerFn)>> for EvmCompilerFns {
-    fn from(fns: Vec<(B256, EvmCompilerFn)>) -> Self {
+impl From<(SpecId, Vec<(B256, EvmCompilerFn)>)> for EvmCompilerFns {
+    fn from((spec_id, fns): (SpecId, Vec<(B256, EvmCompilerFn)>)) -> Self {
         let compiled_fns = fns
             .into_iter()
-            .map(|(h, f)| (h, (f, ReferenceDropObject::None)))
+            .map(|(h, f)| ((h, spec_id), (f, ReferenceDropObject::None)))
+            .collect();
+        Self(Arc::new(compiled_fns))
+    }
+}
+
+impl From<(SpecId, Vec<(B256, (EvmCompilerFn, Library))>)> for EvmCompilerFns {
+    fn from((spec_id, fns): (SpecId, Vec<(B256, (EvmCompilerFn, Library))>)) -> Self {
+        let compiled_fns = fns
+            .into_iter()
+            .map(|(h, (fnc, lib))| {
+                let lib = ReferenceDropObject::Library(Arc::new(lib));
+                ((h, spec_id), (fnc, lib))
+            })
             .collect();
         Self(Arc::new(compiled_fns))
     }
 }
 
-impl From<Vec<(B256, (EvmCompilerFn, Library))>> for EvmCompilerFns {
-    fn from(fns: Vec<(B256, (EvmCompilerFn, Library))>) -> Self {
+impl From<(SpecId, Vec<(B256, (EvmCompilerFn, Arc<Library>))>)> for EvmCompilerFns {
+    fn from((spec_id, fns): (SpecId, Vec<(B256, (EvmCompilerFn, Arc<Library>))>)) -> Self {
         let compiled_fns = fns
             .into_iter()
-            .map(|(h, (fnc, lib))| (h, (fnc, ReferenceDropObject::Library(lib))))
+            .map(|(h, (fnc, lib))| ((h, spec_id), (fnc, ReferenceDropObject::Library(lib))))
             .collect();
         Self(Arc::new(compiled_fns))
     }
 }
 
 impl From<JitCompileOut> for EvmCompilerFns {
-    fn from(JitCompileOut { entries, ctx }: JitCompileOut) -> Self {
-        let compiler_ctx = Arc::new(ctx);
+    fn from(JitCompileOut { entries, .. }: JitCompileOut) -> Self {
         let compiled_fns = entries
             .into_iter()
             .map(|(h, fnc)| {
-                (
-                    h,
-                    (fnc, ReferenceDropObject::CompilerCtx(compiler_ctx.clone())),
-                )
+                let key = (h, fnc.spec_id());
+                (key, (fnc.fnc(), ReferenceDropObject::JitFunction(fnc)))
             })
             .collect();
         Self(Arc::new(compiled_fns))
@@ -55,6 +85,11 @@ impl From<JitCompileOut> for EvmCompilerFns {
 #[derive(Default, Clone)]
 pub struct RevmcExtCtx {
     compiled_fns: EvmCompilerFns,
+    registry: Option<RegistryView>,
+    lazy_aot: Option<LazyAotFns>,
+    tiered_jit: Option<TieredJit>,
+    overrides: Option<ExecutionOverrides>,
+    frame_fns: Vec<Option<(EvmCompilerFn, ReferenceDropObject)>>,
     pub touches: Option<Touches>,
 }
 
@@ -63,23 +98,42 @@ impl RevmcExtCtx {
         self.touches = Some(Touches::default());
         self
     }
+
+    /// Bytecodes without a compiled function are looked up in the registry, whose changes are
+    /// picked up when the next frame starts.
+    pub fn with_registry(mut self, registry: FnRegistry) -> Self {
+        self.registry = Some(RegistryView::new(registry));
+        self
+    }
+
+    /// Bytecodes without a compiled function are looked up in the AOT dir on first use.
+    pub fn with_lazy_aot(mut self, lazy_aot: LazyAotFns) -> Self {
+        self.lazy_aot = Some(lazy_aot);
+        self
+    }
+
+    /// Bytecodes without a compiled function are profiled and compiled in the background.
+    pub fn with_tiered_jit(mut self, tiered_jit: TieredJit) -> Self {
+        self.tiered_jit = Some(tiered_jit);
+        self
+    }
+
+    /// Contracts the overrides deny, or an allow list leaves out, are interpreted.
+    pub fn with_overrides(mut self, overrides: ExecutionOverrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
 }
 
-impl From<Vec<(B256, EvmCompilerFn)>> for RevmcExtCtx {
-    fn from(fns: Vec<(B256, EvmCompilerFn)>) -> Self {
-        Self {
-            compiled_fns: fns.into(),
-            touches: None,
-        }
+impl From<(SpecId, Vec<(B256, EvmCompilerFn)>)> for RevmcExtCtx {
+    fn from(fns: (SpecId, Vec<(B256, EvmCompilerFn)>)) -> Self {
+        EvmCompilerFns::from(fns).into()
     }
 }
 
-impl From<Vec<(B256, (EvmCompilerFn, Library))>> for RevmcExtCtx {
-    fn from(fns: Vec<(B256, (EvmCompilerFn, Library))>) -> Self {
-        Self {
-            compiled_fns: fns.into(),
-            touches: None,
-        }
+impl From<(SpecId, Vec<(B256, (EvmCompilerFn, Library))>)> for RevmcExtCtx {
+    fn from(fns: (SpecId, Vec<(B256, (EvmCompilerFn, Library))>)) -> Self {
+        EvmCompilerFns::from(fns).into()
     }
 }
 
@@ -87,20 +141,90 @@ impl From<EvmCompilerFns> for RevmcExtCtx {
     fn from(fns: EvmCompilerFns) -> Self {
         Self {
             compiled_fns: fns,
-            touches: None,
+            ..Default::default()
         }
     }
 }
 
 pub trait RevmcExtCtxExtTrait {
-    fn get_function(&self, bytecode_hash: B256) -> Option<EvmCompilerFn>;
+    /// Looks up the function compiled for `spec_id` for a new frame at `depth` and keeps it
+    /// alive until the frame is done.
+    fn enter_frame(
+        &mut self,
+        depth: usize,
+        bytecode_hash: B256,
+        spec_id: SpecId,
+    ) -> Option<EvmCompilerFn>;
+    /// Function the suspended frame at `depth` was started with.
+    fn resume_frame(&self, depth: usize) -> Option<EvmCompilerFn>;
     fn register_touch(&mut self, address: Address, non_native: bool);
     fn touches(&self) -> Option<&Touches>;
+    /// Whether a new frame of the contract has to be interpreted, even if it has a function.
+    /// Asked before `enter_frame`, so that functions of such contracts aren't even resolved.
+    fn forces_interpreter(&self, _address: Address, _bytecode_hash: &B256) -> bool {
+        false
+    }
+    /// Called instead of `enter_frame` when the new frame at `depth` is forced to the
+    /// interpreter. It has to be resumed without a function, so implementations of
+    /// `forces_interpreter` have to forget the one of an earlier frame at `depth` here.
+    fn force_interpreter(&mut self, _depth: usize, _address: Address) {}
+    /// Whether create frames should be looked up by the hash of their initcode, which costs
+    /// hashing the initcode.
+    fn compiles_initcode(&self) -> bool {
+        false
+    }
+    /// Called after every interpreted execution of a frame with a known bytecode hash.
+    fn record_interpreted(
+        &mut self,
+        _bytecode_hash: B256,
+        _spec_id: SpecId,
+        _bytecode: &Bytecode,
+        _new_frame: bool,
+        _gas_used: u64,
+    ) {
+    }
 }
 
 impl RevmcExtCtxExtTrait for RevmcExtCtx {
-    fn get_function(&self, bytecode_hash: B256) -> Option<EvmCompilerFn> {
-        self.compiled_fns.get(&bytecode_hash).map(|f| f.0)
+    fn enter_frame(
+        &mut self,
+        depth: usize,
+        bytecode_hash: B256,
+        spec_id: SpecId,
+    ) -> Option<EvmCompilerFn> {
+        // Frames deeper than this one are done, so their references can be released
+        self.frame_fns.resize(depth + 1, None);
+        let fnc = self
+            .compiled_fns
+            .get(&bytecode_hash, spec_id)
+            .cloned()
+            .or_else(|| {
+                let fns = self.registry.as_mut()?.current();
+                fns.get(&bytecode_hash, spec_id).cloned()
+            })
+            .or_else(|| self.lazy_aot.as_ref()?.get(&bytecode_hash, spec_id))
+            .or_else(|| {
+                let fnc = self.tiered_jit.as_ref()?.get(&bytecode_hash, spec_id)?;
+                Some((fnc.fnc(), ReferenceDropObject::JitFunction(fnc)))
+            });
+        let ext_fn = fnc.as_ref().map(|f| f.0);
+        self.frame_fns[depth] = fnc;
+        ext_fn
+    }
+    fn resume_frame(&self, depth: usize) -> Option<EvmCompilerFn> {
+        self.frame_fns.get(depth)?.as_ref().map(|f| f.0)
+    }
+    fn record_interpreted(
+        &mut self,
+        bytecode_hash: B256,
+        spec_id: SpecId,
+        bytecode: &Bytecode,
+        new_frame: bool,
+        gas_used: u64,
+    ) {
+        if let Some(tiered_jit) = &self.tiered_jit {
+            tiered_jit.record(bytecode_hash, spec_id, bytecode, new_frame, gas_used);
+        }
     }
     fn register_touch(&mut self, address: Address, non_native: bool) {
         if let Some(touches) = &mut self.touches {
@@ -110,12 +234,32 @@ impl RevmcExtCtxExtTrait for RevmcExtCtx {
     fn touches(&self) -> Option<&Touches> {
         self.touches.as_ref()
     }
+    fn forces_interpreter(&self, address: Address, bytecode_hash: &B256) -> bool {
+        self.overrides
+            .as_ref()
+            .is_some_and(|overrides| overrides.forces_interpreter(address, bytecode_hash))
+    }
+    fn force_interpreter(&mut self, depth: usize, address: Address) {
+        self.frame_fns.resize(depth + 1, None);
+        self.frame_fns[depth] = None;
+        if let Some(touches) = &mut self.touches {
+            touches.register_forced_native(address);
+        }
+    }
+    fn compiles_initcode(&self) -> bool {
+        !self.compiled_fns.0.is_empty()
+            || self.registry.is_some()
+            || self.lazy_aot.is_some()
+            || self.tiered_jit.is_some()
+    }
 }
 
+#[derive(Clone)]
 pub enum ReferenceDropObject {
     #[allow(dead_code)]
-    Library(Library),
-    CompilerCtx(Arc<JitCompileCtx>),
+    Library(Arc<Library>),
+    #[allow(dead_code)]
+    JitFunction(Arc<JitFunction>),
     None,
 }
 
@@ -126,23 +270,57 @@ where
 {
     let execute_frame_original = handler.execution.execute_frame.clone();
     handler.execution.execute_frame = Arc::new(move |frame, memory, tables, context| {
+        let depth = context.evm.journaled_state.depth;
+        // Functions only run on the spec they were compiled for, other specs are interpreted
+        let spec_id = context.evm.journaled_state.spec;
         let interpreter = frame.interpreter_mut();
-        let bytecode_hash = interpreter.contract.hash.unwrap_or_default();
-        let ext_fn = context.external.get_function(bytecode_hash);
+        // Compiled frames keep their resume point in the instruction pointer, so a suspended
+        // frame has to continue the way it was started, even if the available fns changed.
+        let new_frame = interpreter.instruction_pointer == interpreter.bytecode.as_ptr();
+        // Create frames have no code hash, their initcode is compiled under its own hash. It's
+        // only hashed when the frame starts, so resumed create frames aren't recorded.
+        let bytecode_hash = match interpreter.contract.hash {
+            Some(hash) => Some(hash),
+            None if new_frame && context.external.compiles_initcode() => Some(keccak256(
+                interpreter.contract.bytecode.original_byte_slice(),
+            )),
+            None => None,
+        };
+        let address = interpreter
+            .contract
+            .bytecode_address
+            .unwrap_or(interpreter.contract.target_address);
+        let ext_fn = if new_frame {
+            let bytecode_hash = bytecode_hash.unwrap_or_default();
+            if context.external.forces_interpreter(address, &bytecode_hash) {
+                context.external.force_interpreter(depth, address);
+                None
+            } else {
+                context.external.enter_frame(depth, bytecode_hash, spec_id)
+            }
+        } else {
+            context.external.resume_frame(depth)
+        };
 
         // todo: check how much overhead could this conditional add
-        context.external.register_touch(
-            interpreter
-                .contract
-                .bytecode_address
-                .unwrap_or(interpreter.contract.target_address),
-            ext_fn.is_some(),
-        );
+        context.external.register_touch(address, ext_fn.is_some());
 
         Ok(if let Some(f) = ext_fn {
             unsafe { f.call_with_interpreter_and_memory(interpreter, memory, context) }
         } else {
-            execute_frame_original(frame, memory, tables, context)?
+            let gas_spent_before = interpreter.gas.spent();
+            let action = execute_frame_original(frame, memory, tables, context)?;
+            if let Some(bytecode_hash) = bytecode_hash {
+                let interpreter = frame.interpreter();
+                context.external.record_interpreted(
+                    bytecode_hash,
+                    spec_id,
+                    &interpreter.contract.bytecode,
+                    new_frame,
+                    interpreter.gas.spent() - gas_spent_before,
+                );
+            }
+            action
         })
     });
 }
@@ -165,12 +343,18 @@ impl Touches {
             .and_modify(|c| c.increment(non_native))
             .or_insert(TouchCounter::new_with_increment(non_native));
     }
+
+    fn register_forced_native(&mut self, address: Address) {
+        self.0.entry(address).or_default().forced_native += 1;
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct TouchCounter {
     pub overall: usize,
     pub non_native: usize,
+    /// Frames the overrides forced to the interpreter.
+    pub forced_native: usize,
 }
 
 impl TouchCounter {
@@ -1,24 +1,146 @@
 use libloading::Library;
 use revm::primitives::B256;
 use revmc::EvmCompilerFn;
+use revmc_toolkit_build::{
+    artifact_dir, ArtifactKey, ArtifactManifest, ArtifactOptions, BatchIndex, CompileError,
+    CompileErrorKind, CompilerOptions, Integrity, BYTECODE_FILE,
+};
 
 use eyre::{OptionExt, Result};
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 use tracing::debug;
 
+use crate::smoke::smoke_test;
+
 pub struct EvmCompilerFnLoader<'a> {
     dir_path: &'a PathBuf,
+    options: ArtifactOptions,
+    /// Options of contracts compiled with a policy, by bytecode hash.
+    contract_options: HashMap<B256, ArtifactOptions>,
+    verification_key: Option<ArtifactKey>,
+    smoke_test: bool,
+    private_copies: bool,
 }
 
 impl<'a> EvmCompilerFnLoader<'a> {
     pub fn new(dir_path: &'a PathBuf) -> Self {
-        Self { dir_path }
+        Self {
+            dir_path,
+            options: CompilerOptions::default().artifact_options(),
+            contract_options: HashMap::new(),
+            verification_key: None,
+            smoke_test: false,
+            private_copies: false,
+        }
+    }
+
+    /// Only artifacts compiled with these options are loaded.
+    pub fn with_options(mut self, options: &CompilerOptions) -> Self {
+        self.options = options.artifact_options();
+        self
+    }
+
+    pub fn with_artifact_options(mut self, options: ArtifactOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Like `with_options`, but expects every bytecode to be compiled with its options
+    /// under the policy of `options`.
+    pub fn with_contract_options(
+        mut self,
+        options: &CompilerOptions,
+        bytecodes: &[impl AsRef<[u8]>],
+    ) -> Self {
+        self.options = options.artifact_options();
+        if options.policy.is_some() {
+            self.contract_options = bytecodes
+                .iter()
+                .map(|bytecode| {
+                    let bytecode = bytecode.as_ref();
+                    let contract_options = options.for_contract(bytecode).artifact_options();
+                    (revm::primitives::keccak256(bytecode), contract_options)
+                })
+                .collect();
+        }
+        self
+    }
+
+    /// Options the contract is expected to be compiled with.
+    pub fn options_for(&self, bytecode_hash: &B256) -> &ArtifactOptions {
+        self.contract_options
+            .get(bytecode_hash)
+            .unwrap_or(&self.options)
+    }
+
+    /// Only artifacts signed with this key are loaded.
+    pub fn with_verification_key(mut self, key: Option<ArtifactKey>) -> Self {
+        self.verification_key = key;
+        self
+    }
+
+    /// Compares every loaded function against the interpreter on a trivial call.
+    pub fn with_smoke_test(mut self, smoke_test: bool) -> Self {
+        self.smoke_test = smoke_test;
+        self
+    }
+
+    /// Loads every library from a copy under a unique name. The dynamic loader hands out the
+    /// library it already has open for a path, so without copies a library recompiled at the
+    /// same path isn't loaded again while the old one is alive.
+    pub fn with_private_copies(mut self, private_copies: bool) -> Self {
+        self.private_copies = private_copies;
+        self
     }
 
     pub fn load(&self, bytecode_hash: &B256) -> Result<(EvmCompilerFn, Library)> {
+        Ok(self.try_load(bytecode_hash)?)
+    }
+
+    /// Like `load`, telling failed smoke tests apart from artifacts that couldn't be loaded.
+    fn try_load(&self, bytecode_hash: &B256) -> Result<(EvmCompilerFn, Library), CompileError> {
         let name = bytecode_hash.to_string();
-        let path = self.dir_path.join(&name).join("a.so");
-        let fnc = Self::load_from_path(&name, path)?;
+        let options = self.options_for(bytecode_hash);
+        let dir = artifact_dir(self.dir_path, bytecode_hash, options);
+        let load_err =
+            |e: eyre::Report| CompileError::new(*bytecode_hash, CompileErrorKind::Load, e);
+        let manifest = ArtifactManifest::read(&dir)
+            .map_err(|e| load_err(eyre::eyre!("Missing manifest in {}: {e}", dir.display())))?;
+        manifest.check(bytecode_hash, options).map_err(|e| {
+            load_err(eyre::eyre!(
+                "Refusing stale artifact in {}: {e}",
+                dir.display()
+            ))
+        })?;
+        if let Some(key) = &self.verification_key {
+            manifest.verify_signature(key).map_err(|e| {
+                load_err(eyre::eyre!(
+                    "Refusing unsigned artifact in {}: {e}",
+                    dir.display()
+                ))
+            })?;
+        }
+        Self::verify_file(&manifest.integrity, &dir, "a.so").map_err(load_err)?;
+        let fnc = self
+            .load_from_path(&name, &dir.join("a.so"))
+            .map_err(load_err)?;
+        if self.smoke_test {
+            self.run_smoke_test(
+                &manifest.integrity,
+                &dir,
+                BYTECODE_FILE,
+                bytecode_hash,
+                fnc.0,
+            )?;
+        }
         Ok(fnc)
     }
 
@@ -26,20 +148,35 @@ impl<'a> EvmCompilerFnLoader<'a> {
         &self,
         bytecode_hashes: Vec<B256>,
     ) -> Vec<(B256, (EvmCompilerFn, Library))> {
+        let (fncs, errors) = self.load_many(bytecode_hashes);
+        for e in errors {
+            tracing::error!(
+                "Failed to load AOT compilation for {}: {}",
+                e.bytecode_hash,
+                e.message
+            );
+        }
+        fncs
+    }
+
+    /// Loads the functions that can be loaded and reports the rest.
+    pub fn load_many(
+        &self,
+        bytecode_hashes: Vec<B256>,
+    ) -> (Vec<(B256, (EvmCompilerFn, Library))>, Vec<CompileError>) {
         debug!(
             "Loading AOT compilations from dir {}: {bytecode_hashes:?}",
             self.dir_path.display()
         );
-        bytecode_hashes
-            .into_iter()
-            .filter_map(|hash| match self.load(&hash) {
-                Ok(fnc) => Some((hash, fnc)),
-                Err(e) => {
-                    tracing::error!("Failed to load AOT compilation for {hash}: {e}");
-                    None
-                }
-            })
-            .collect::<Vec<_>>()
+        let mut fncs = vec![];
+        let mut errors = vec![];
+        for hash in bytecode_hashes {
+            match self.try_load(&hash) {
+                Ok(fnc) => fncs.push((hash, fnc)),
+                Err(e) => errors.push(e),
+            }
+        }
+        (fncs, errors)
     }
 
     pub fn load_all(&self) -> Result<Vec<(B256, (EvmCompilerFn, Library))>> {
@@ -55,10 +192,12 @@ impl<'a> EvmCompilerFnLoader<'a> {
             }
             let name = entry.file_name();
             let name = name.to_str().ok_or_eyre("Invalid directory name")?;
-            let hash = B256::from_str(name)?;
-            let path = entry.path().join("a.so");
+            // Skip non-artifact dirs (eg. batches)
+            let Ok(hash) = B256::from_str(name) else {
+                continue;
+            };
 
-            match Self::load_from_path(name, path) {
+            match self.load(&hash) {
                 Ok(fnc) => hash_fn_pairs.push((hash, fnc)),
                 Err(e) => {
                     tracing::error!("Failed to load AOT compilation for {name}: {e}");
@@ -68,10 +207,117 @@ impl<'a> EvmCompilerFnLoader<'a> {
         Ok(hash_fn_pairs)
     }
 
-    fn load_from_path(name: &str, path: PathBuf) -> Result<(EvmCompilerFn, Library)> {
+    /// Resolves all functions of a batch library with a single dlopen.
+    pub fn load_batch(
+        &self,
+        batch_dir: &Path,
+    ) -> Result<Vec<(B256, (EvmCompilerFn, Arc<Library>))>> {
+        let index = BatchIndex::read(batch_dir)?;
+        for hash in &index.bytecode_hashes {
+            index
+                .check(self.options_for(hash))
+                .map_err(|e| eyre::eyre!("Refusing stale batch in {}: {e}", batch_dir.display()))?;
+        }
+        if let Some(key) = &self.verification_key {
+            index.verify_signature(key).map_err(|e| {
+                eyre::eyre!("Refusing unsigned batch in {}: {e}", batch_dir.display())
+            })?;
+        }
+        Self::verify_file(&index.integrity, batch_dir, "a.so")?;
+        let path = batch_dir.join("a.so");
+        debug!(
+            "Loading {} fns from batch {}",
+            index.bytecode_hashes.len(),
+            path.display()
+        );
+        let lib = Arc::new(self.open_library(&path)?);
+        let mut fncs = vec![];
+        for hash in &index.bytecode_hashes {
+            let f: libloading::Symbol<'_, EvmCompilerFn> =
+                unsafe { lib.get(hash.to_string().as_bytes())? };
+            if self.smoke_test {
+                // Only the failing function is left out, the others of the batch are fine
+                let bytecode_file = format!("{hash}.bin");
+                if let Err(e) =
+                    self.run_smoke_test(&index.integrity, batch_dir, &bytecode_file, hash, *f)
+                {
+                    tracing::error!("Leaving out {hash} of batch {}: {e}", batch_dir.display());
+                    continue;
+                }
+            }
+            fncs.push((*hash, (*f, lib.clone())));
+        }
+        Ok(fncs)
+    }
+
+    pub fn load_batches(
+        &self,
+        batch_dirs: &[PathBuf],
+    ) -> Vec<(B256, (EvmCompilerFn, Arc<Library>))> {
+        batch_dirs
+            .iter()
+            .filter_map(|dir| match self.load_batch(dir) {
+                Ok(fncs) => Some(fncs),
+                Err(e) => {
+                    tracing::error!("Failed to load AOT batch {}: {e}", dir.display());
+                    None
+                }
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Guards against truncated or tampered files before they are loaded or executed.
+    fn verify_file(integrity: &Integrity, dir: &Path, name: &str) -> Result<()> {
+        integrity
+            .verify_file(dir, name)
+            .map_err(|e| eyre::eyre!("Refusing corrupted artifact in {}: {e}", dir.display()))
+    }
+
+    fn run_smoke_test(
+        &self,
+        integrity: &Integrity,
+        dir: &Path,
+        bytecode_file: &str,
+        bytecode_hash: &B256,
+        fnc: EvmCompilerFn,
+    ) -> Result<(), CompileError> {
+        let load_err =
+            |e: eyre::Report| CompileError::new(*bytecode_hash, CompileErrorKind::Load, e);
+        Self::verify_file(integrity, dir, bytecode_file).map_err(load_err)?;
+        let bytecode = std::fs::read(dir.join(bytecode_file)).map_err(|e| load_err(e.into()))?;
+        smoke_test(
+            *bytecode_hash,
+            fnc,
+            &bytecode,
+            self.options_for(bytecode_hash),
+        )
+        .map_err(|e| CompileError::new(*bytecode_hash, CompileErrorKind::SmokeTest, e))
+    }
+
+    fn load_from_path(&self, name: &str, path: &Path) -> Result<(EvmCompilerFn, Library)> {
         debug!("Loading fn {name} from path {}", path.display());
-        let lib = unsafe { Library::new(path) }?;
+        let lib = self.open_library(path)?;
         let f: libloading::Symbol<'_, EvmCompilerFn> = unsafe { lib.get(name.as_bytes())? };
         Ok((*f, lib))
     }
+
+    fn open_library(&self, path: &Path) -> Result<Library> {
+        if !self.private_copies {
+            return Ok(unsafe { Library::new(path) }?);
+        }
+        static COPY_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("a.so");
+        let copy = path.with_file_name(format!(
+            ".{name}.{}-{}",
+            std::process::id(),
+            COPY_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::copy(path, &copy)
+            .map_err(|e| eyre::eyre!("Failed to copy {}: {e}", path.display()))?;
+        let lib = unsafe { Library::new(&copy) };
+        // The loader keeps the library mapped, the copy is only needed to open it
+        let _ = std::fs::remove_file(&copy);
+        Ok(lib?)
+    }
 }
@@ -0,0 +1,242 @@
+use revm::primitives::{SpecId, B256};
+use revmc_toolkit_build::JitFunction;
+use rustc_hash::FxHashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+use tracing::debug;
+
+/// Budget of the cache. Without limits nothing is ever evicted.
+#[derive(Debug, Clone, Default)]
+pub struct JitCacheConfig {
+    pub max_functions: Option<usize>,
+    /// Limit on the summed `JitFunction::bytecode_size` of resident functions. It bounds JIT
+    /// memory only roughly, as machine code is usually several times larger than its bytecode.
+    pub max_bytecode_bytes: Option<usize>,
+}
+
+impl JitCacheConfig {
+    pub fn with_max_functions(mut self, max_functions: usize) -> Self {
+        self.max_functions = Some(max_functions);
+        self
+    }
+
+    pub fn with_max_bytecode_bytes(mut self, max_bytecode_bytes: usize) -> Self {
+        self.max_bytecode_bytes = Some(max_bytecode_bytes);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JitCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    pub functions: usize,
+    pub bytecode_bytes: usize,
+}
+
+/// Bytecode hash and the spec the function was compiled for.
+type Key = (B256, SpecId);
+
+struct Entry {
+    fnc: Arc<JitFunction>,
+    /// Tick of the last lookup, updated under the read lock.
+    last_used: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: FxHashMap<Key, Entry>,
+    bytecode_bytes: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, key: Key, fnc: Arc<JitFunction>, tick: u64) -> Option<Arc<JitFunction>> {
+        let replaced = self.remove(&key);
+        self.bytecode_bytes += fnc.bytecode_size();
+        let last_used = AtomicU64::new(tick);
+        self.map.insert(key, Entry { fnc, last_used });
+        replaced
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Arc<JitFunction>> {
+        let entry = self.map.remove(key)?;
+        self.bytecode_bytes -= entry.fnc.bytecode_size();
+        Some(entry.fnc)
+    }
+
+    /// Scans all entries, which is fine as it only happens on inserts over budget.
+    fn pop_least_recent(&mut self) -> Option<Arc<JitFunction>> {
+        let key = *self
+            .map
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))?
+            .0;
+        debug!("Evicting JIT function {} ({:?})", key.0, key.1);
+        self.remove(&key)
+    }
+
+    fn exceeds(&self, config: &JitCacheConfig) -> bool {
+        config.max_functions.is_some_and(|max| self.map.len() > max)
+            || config
+                .max_bytecode_bytes
+                .is_some_and(|max| self.bytecode_bytes > max)
+    }
+}
+
+/// LRU cache of JIT functions with a memory budget. Lookups only take a read lock, so that
+/// EVMs on many threads don't contend on it. Evicted functions are freed once the frames
+/// still executing them are done.
+#[derive(Default)]
+pub struct JitCache {
+    config: JitCacheConfig,
+    entries: RwLock<Entries>,
+    tick: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl JitCache {
+    pub fn new(config: JitCacheConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, bytecode_hash: &B256, spec_id: SpecId) -> Option<Arc<JitFunction>> {
+        let fnc = self
+            .entries
+            .read()
+            .unwrap()
+            .map
+            .get(&(*bytecode_hash, spec_id))
+            .map(|entry| {
+                let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+                entry.last_used.store(tick, Ordering::Relaxed);
+                entry.fnc.clone()
+            });
+        let counter = if fnc.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        fnc
+    }
+
+    pub fn contains(&self, bytecode_hash: &B256, spec_id: SpecId) -> bool {
+        let key = (*bytecode_hash, spec_id);
+        self.entries.read().unwrap().map.contains_key(&key)
+    }
+
+    /// Inserts the function under its bytecode hash and `JitFunction::spec_id`.
+    pub fn insert(&self, bytecode_hash: B256, fnc: Arc<JitFunction>) {
+        let mut released = vec![];
+        {
+            let mut entries = self.entries.write().unwrap();
+            let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+            released.extend(entries.insert((bytecode_hash, fnc.spec_id()), fnc, tick));
+            // The newly inserted fn is the most recent one, so it's never evicted
+            while entries.map.len() > 1 && entries.exceeds(&self.config) {
+                released.extend(entries.pop_least_recent());
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        // Dropped outside of the lock, as freeing a function takes its compiler's lock
+        drop(released);
+    }
+
+    pub fn stats(&self) -> JitCacheStats {
+        let entries = self.entries.read().unwrap();
+        JitCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            functions: entries.map.len(),
+            bytecode_bytes: entries.bytecode_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::jit_fns;
+
+    /// Functions of distinct bytecodes of 3 bytes each.
+    fn fns(n: u8) -> Vec<(B256, Arc<JitFunction>)> {
+        let bytecodes = (0..n).map(|i| vec![0x60, i, 0x00]).collect::<Vec<_>>();
+        jit_fns(&bytecodes, SpecId::CANCUN)
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = JitCache::new(JitCacheConfig::default().with_max_functions(2));
+        let [a, b, c] = <[_; 3]>::try_from(fns(3)).ok().unwrap();
+        cache.insert(a.0, a.1);
+        cache.insert(b.0, b.1);
+        // Makes `b` the least recent one
+        assert!(cache.get(&a.0, SpecId::CANCUN).is_some());
+        cache.insert(c.0, c.1);
+
+        assert!(cache.contains(&a.0, SpecId::CANCUN));
+        assert!(!cache.contains(&b.0, SpecId::CANCUN));
+        assert!(cache.contains(&c.0, SpecId::CANCUN));
+        assert!(cache.get(&b.0, SpecId::CANCUN).is_none());
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.hits, stats.misses, stats.inserts, stats.evictions),
+            (1, 1, 3, 1)
+        );
+        assert_eq!((stats.functions, stats.bytecode_bytes), (2, 6));
+    }
+
+    #[test]
+    fn bytecode_budget_keeps_the_newest_function() {
+        let cache = JitCache::new(JitCacheConfig::default().with_max_bytecode_bytes(2));
+        let [a, b] = <[_; 2]>::try_from(fns(2)).ok().unwrap();
+        cache.insert(a.0, a.1);
+        // Over budget on its own, but the newest function is never evicted
+        assert!(cache.contains(&a.0, SpecId::CANCUN));
+        cache.insert(b.0, b.1);
+
+        assert!(!cache.contains(&a.0, SpecId::CANCUN));
+        assert!(cache.contains(&b.0, SpecId::CANCUN));
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.functions, stats.bytecode_bytes, stats.evictions),
+            (1, 3, 1)
+        );
+    }
+
+    #[test]
+    fn reinserting_replaces_without_evicting() {
+        let cache = JitCache::new(JitCacheConfig::default().with_max_functions(1));
+        let [(hash, fnc)] = <[_; 1]>::try_from(fns(1)).ok().unwrap();
+        cache.insert(hash, fnc.clone());
+        cache.insert(hash, fnc);
+
+        let stats = cache.stats();
+        assert_eq!((stats.functions, stats.bytecode_bytes), (1, 3));
+        assert_eq!((stats.inserts, stats.evictions), (2, 0));
+    }
+
+    #[test]
+    fn functions_are_keyed_by_spec() {
+        let cache = JitCache::default();
+        let [(hash, fnc)] = <[_; 1]>::try_from(fns(1)).ok().unwrap();
+        cache.insert(hash, fnc);
+
+        assert!(cache.get(&hash, SpecId::CANCUN).is_some());
+        assert!(cache.get(&hash, SpecId::SHANGHAI).is_none());
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+    }
+}
@@ -0,0 +1,256 @@
+use revm::primitives::{SpecId, B256};
+use revmc::EvmCompilerFn;
+use revmc_toolkit_build::{
+    artifact_dir, batch_dir, ArtifactKey, ArtifactOptions, BatchIndex, CompilerOptions,
+    BATCHES_DIR, BATCH_INDEX_FILE, MANIFEST_FILE,
+};
+
+use rustc_hash::FxHashMap;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, OnceLock, RwLock},
+};
+use tracing::{debug, error};
+
+use crate::evm_components::ReferenceDropObject;
+use crate::fn_loader::EvmCompilerFnLoader;
+
+pub(crate) type CachedFn = Option<(EvmCompilerFn, ReferenceDropObject)>;
+
+/// AOT functions loaded from the out dir on their first lookup instead of up front, so that
+/// startup doesn't depend on the size of the cache. The dir is indexed on the first lookup,
+/// and every looked up function, or its absence, is cached. Cheap to clone and safe to share
+/// across threads; clones share the cache.
+#[derive(Clone)]
+pub struct LazyAotFns(Arc<LazyAotInner>);
+
+struct LazyAotInner {
+    dir_path: PathBuf,
+    options: ArtifactOptions,
+    verification_key: Option<ArtifactKey>,
+    smoke_test: bool,
+    index: OnceLock<FxHashMap<B256, IndexEntry>>,
+    cache: RwLock<FxHashMap<B256, CachedFn>>,
+}
+
+/// Dir holding the function of a bytecode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum IndexEntry {
+    Artifact(PathBuf),
+    Batch(PathBuf),
+}
+
+impl IndexEntry {
+    /// File written last, so its presence implies a complete entry.
+    pub(crate) fn marker_file(&self) -> PathBuf {
+        match self {
+            IndexEntry::Artifact(dir) => dir.join(MANIFEST_FILE),
+            IndexEntry::Batch(dir) => dir.join(BATCH_INDEX_FILE),
+        }
+    }
+}
+
+impl LazyAotFns {
+    /// Only artifacts compiled with the options (without a policy) are loaded, and only for
+    /// their spec. Artifacts are verified and smoke tested like with `EvmCompilerFnLoader`.
+    pub fn new(
+        options: &CompilerOptions,
+        verification_key: Option<ArtifactKey>,
+        smoke_test: bool,
+    ) -> Self {
+        Self(Arc::new(LazyAotInner {
+            dir_path: options.out_dir.clone(),
+            options: options.artifact_options(),
+            verification_key,
+            smoke_test,
+            index: OnceLock::new(),
+            cache: Default::default(),
+        }))
+    }
+
+    pub fn get(&self, bytecode_hash: &B256, spec_id: SpecId) -> CachedFn {
+        if spec_id != self.0.options.spec_id {
+            return None;
+        }
+        if let Some(cached) = self.0.cache.read().unwrap().get(bytecode_hash) {
+            return cached.clone();
+        }
+        // Loaded without holding the lock, a function loaded twice meanwhile is dropped
+        let loaded = self.load(bytecode_hash);
+        let mut cache = self.0.cache.write().unwrap();
+        for (hash, fnc) in loaded {
+            cache.entry(hash).or_insert(fnc);
+        }
+        cache.entry(*bytecode_hash).or_insert(None).clone()
+    }
+
+    /// Number of functions loaded so far.
+    pub fn loaded(&self) -> usize {
+        let cache = self.0.cache.read().unwrap();
+        cache.values().filter(|fnc| fnc.is_some()).count()
+    }
+
+    fn load(&self, bytecode_hash: &B256) -> Vec<(B256, CachedFn)> {
+        let inner = &self.0;
+        let loader = EvmCompilerFnLoader::new(&inner.dir_path)
+            .with_artifact_options(inner.options.clone())
+            .with_verification_key(inner.verification_key.clone())
+            .with_smoke_test(inner.smoke_test);
+        match self.index().get(bytecode_hash) {
+            Some(entry) => load_entry(&loader, bytecode_hash, entry),
+            None => vec![],
+        }
+    }
+
+    fn index(&self) -> &FxHashMap<B256, IndexEntry> {
+        self.0
+            .index
+            .get_or_init(|| index_aot_dir(&self.0.dir_path, &self.0.options))
+    }
+}
+
+/// Loads the function and, if it's in a batch, the others of the batch along with it.
+pub(crate) fn load_entry(
+    loader: &EvmCompilerFnLoader<'_>,
+    bytecode_hash: &B256,
+    entry: &IndexEntry,
+) -> Vec<(B256, CachedFn)> {
+    match entry {
+        IndexEntry::Artifact(_) => match loader.load(bytecode_hash) {
+            Ok((fnc, lib)) => {
+                debug!("Lazily loaded {bytecode_hash}");
+                let lib = ReferenceDropObject::Library(Arc::new(lib));
+                vec![(*bytecode_hash, Some((fnc, lib)))]
+            }
+            Err(e) => {
+                error!("Failed to load AOT compilation for {bytecode_hash}: {e}");
+                vec![]
+            }
+        },
+        IndexEntry::Batch(dir) => match loader.load_batch(dir) {
+            Ok(fncs) => {
+                debug!("Lazily loaded {} fns of {}", fncs.len(), dir.display());
+                fncs.into_iter()
+                    .map(|(hash, (fnc, lib))| {
+                        (hash, Some((fnc, ReferenceDropObject::Library(lib))))
+                    })
+                    .collect()
+            }
+            Err(e) => {
+                error!("Failed to load AOT batch {}: {e}", dir.display());
+                vec![]
+            }
+        },
+    }
+}
+
+/// Bytecodes with a complete artifact for the options, and the batches holding the others.
+pub(crate) fn index_aot_dir(
+    dir_path: &Path,
+    options: &ArtifactOptions,
+) -> FxHashMap<B256, IndexEntry> {
+    let mut index = FxHashMap::default();
+    let Ok(dir_entries) = std::fs::read_dir(dir_path) else {
+        debug!("No AOT dir at {}", dir_path.display());
+        return index;
+    };
+    for dir_entry in dir_entries.flatten() {
+        let name = dir_entry.file_name();
+        let Some(bytecode_hash) = name.to_str().and_then(|n| B256::from_str(n).ok()) else {
+            continue;
+        };
+        let entry = IndexEntry::Artifact(artifact_dir(dir_path, &bytecode_hash, options));
+        if entry.marker_file().exists() {
+            index.insert(bytecode_hash, entry);
+        }
+    }
+    let batches = std::fs::read_dir(dir_path.join(BATCHES_DIR));
+    for batch_entry in batches.into_iter().flatten().flatten() {
+        let Some(batch_id) = batch_entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        let dir = batch_dir(dir_path, &batch_id);
+        let Ok(batch) = BatchIndex::read(&dir) else {
+            continue;
+        };
+        if batch.check(options).is_err() {
+            continue;
+        }
+        for bytecode_hash in batch.bytecode_hashes {
+            // Standalone artifacts take precedence, they need no other functions
+            index
+                .entry(bytecode_hash)
+                .or_insert_with(|| IndexEntry::Batch(dir.clone()));
+        }
+    }
+    debug!(
+        "Indexed {} AOT functions in {}",
+        index.len(),
+        dir_path.display()
+    );
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{options_in, temp_dir, write_artifact, write_batch};
+    use revm::primitives::keccak256;
+
+    #[test]
+    fn missing_hash_is_cached_as_absent() {
+        let dir = temp_dir("lazy-missing");
+        let options = options_in(&dir);
+        let lazy = LazyAotFns::new(&options, None, false);
+        let missing = keccak256([0x00]);
+
+        assert!(lazy.get(&missing, options.spec_id).is_none());
+        assert_eq!(lazy.loaded(), 0);
+        assert!(lazy.index().is_empty());
+        let cache = lazy.0.cache.read().unwrap();
+        assert!(matches!(cache.get(&missing), Some(None)));
+    }
+
+    #[test]
+    fn other_specs_are_not_looked_up() {
+        let dir = temp_dir("lazy-spec");
+        let options = options_in(&dir).with_spec_id(SpecId::CANCUN);
+        let lazy = LazyAotFns::new(&options, None, false);
+
+        assert!(lazy.get(&keccak256([0x00]), SpecId::SHANGHAI).is_none());
+        assert!(lazy.0.index.get().is_none());
+        assert!(lazy.0.cache.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn index_prefers_artifacts_over_batches() {
+        let dir = temp_dir("lazy-index");
+        let options = options_in(&dir).artifact_options();
+        let standalone = write_artifact(&dir, &[0x00], &options);
+        let batched = keccak256([0x60, 0x00, 0x00]);
+        let batch = write_batch(&dir, "batch", vec![standalone, batched], &options);
+        // Incomplete, as its manifest is written last
+        let incomplete = keccak256([0x01]);
+        std::fs::create_dir_all(artifact_dir(&dir, &incomplete, &options)).unwrap();
+
+        let index = index_aot_dir(&dir, &options);
+        assert_eq!(index.len(), 2);
+        let artifact = artifact_dir(&dir, &standalone, &options);
+        assert_eq!(index[&standalone], IndexEntry::Artifact(artifact));
+        assert_eq!(index[&batched], IndexEntry::Batch(batch));
+        assert!(!index.contains_key(&incomplete));
+    }
+
+    #[test]
+    fn index_skips_entries_of_other_options() {
+        let dir = temp_dir("lazy-options");
+        let options = options_in(&dir).artifact_options();
+        let other = options_in(&dir).with_no_gas(true).artifact_options();
+        write_artifact(&dir, &[0x00], &other);
+        write_batch(&dir, "other", vec![keccak256([0x01])], &other);
+
+        assert!(index_aot_dir(&dir, &options).is_empty());
+        assert!(index_aot_dir(&dir.join("missing"), &options).is_empty());
+    }
+}
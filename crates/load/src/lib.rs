@@ -1,5 +1,19 @@
 mod evm_components;
 mod fn_loader;
+mod jit_cache;
+mod lazy;
+mod overrides;
+mod registry;
+mod smoke;
+#[cfg(test)]
+mod test_utils;
+mod tiered;
 
 pub use evm_components::*;
 pub use fn_loader::EvmCompilerFnLoader;
+pub use jit_cache::{JitCache, JitCacheConfig, JitCacheStats};
+pub use lazy::LazyAotFns;
+pub use overrides::{ExecutionOverrides, OverrideLists, OverrideMatch};
+pub use registry::{FnRegistry, RegistryWatcher};
+pub use smoke::smoke_test;
+pub use tiered::{TieredJit, TieredJitConfig};
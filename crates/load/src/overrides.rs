@@ -0,0 +1,204 @@
+use revm::primitives::{Address, B256};
+
+use eyre::{Result, WrapErr};
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+/// Contracts that run interpreted even if they have a compiled function, read from a TOML or
+/// JSON file. Denied contracts are always interpreted, and if an allow list is set, so is every
+/// contract it doesn't match.
+///
+/// ```toml
+/// [deny]
+/// addresses = ["0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"]
+///
+/// [allow]
+/// addresses = ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverrideLists {
+    #[serde(default)]
+    pub deny: OverrideMatch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<OverrideMatch>,
+}
+
+/// Matches contracts deployed at any of the addresses or with any of the bytecodes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverrideMatch {
+    #[serde(default, skip_serializing_if = "FxHashSet::is_empty")]
+    pub addresses: FxHashSet<Address>,
+    #[serde(default, skip_serializing_if = "FxHashSet::is_empty")]
+    pub bytecode_hashes: FxHashSet<B256>,
+}
+
+impl OverrideMatch {
+    pub fn matches(&self, address: Address, bytecode_hash: &B256) -> bool {
+        self.addresses.contains(&address) || self.bytecode_hashes.contains(bytecode_hash)
+    }
+}
+
+impl OverrideLists {
+    /// Reads a `.toml` file as TOML and anything else as JSON.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("Failed to read overrides {}: {e}", path.display()))?;
+        let lists: Result<Self> = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content).map_err(Into::into)
+        } else {
+            serde_json::from_str(&content).map_err(Into::into)
+        };
+        lists.wrap_err_with(|| format!("Invalid overrides {}", path.display()))
+    }
+
+    pub fn forces_interpreter(&self, address: Address, bytecode_hash: &B256) -> bool {
+        self.deny.matches(address, bytecode_hash)
+            || self
+                .allow
+                .as_ref()
+                .is_some_and(|allow| !allow.matches(address, bytecode_hash))
+    }
+}
+
+/// Override lists shared by the EVMs using them, which can be changed while they run. Changes
+/// apply to frames started afterwards, a running frame continues the way it was started. Cheap
+/// to clone; clones share the lists.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionOverrides(Arc<RwLock<OverrideLists>>);
+
+impl From<OverrideLists> for ExecutionOverrides {
+    fn from(lists: OverrideLists) -> Self {
+        Self(Arc::new(RwLock::new(lists)))
+    }
+}
+
+impl ExecutionOverrides {
+    pub fn read(path: &Path) -> Result<Self> {
+        OverrideLists::read(path).map(Into::into)
+    }
+
+    /// Replaces the lists with the ones of the file. They are left as they are if it's invalid.
+    pub fn reload(&self, path: &Path) -> Result<()> {
+        self.set(OverrideLists::read(path)?);
+        Ok(())
+    }
+
+    pub fn set(&self, lists: OverrideLists) {
+        *self.0.write().unwrap() = lists;
+    }
+
+    /// Changes the lists in place, eg. `overrides.update(|l| l.deny.addresses.insert(address))`.
+    pub fn update<T>(&self, f: impl FnOnce(&mut OverrideLists) -> T) -> T {
+        f(&mut self.0.write().unwrap())
+    }
+
+    pub fn lists(&self) -> OverrideLists {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn forces_interpreter(&self, address: Address, bytecode_hash: &B256) -> bool {
+        self.0
+            .read()
+            .unwrap()
+            .forces_interpreter(address, bytecode_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: Address = Address::repeat_byte(1);
+    const USDC: Address = Address::repeat_byte(2);
+    const OTHER: Address = Address::repeat_byte(3);
+
+    fn lists(deny: &[Address], allow: Option<&[Address]>) -> OverrideLists {
+        let matcher = |addresses: &[Address]| OverrideMatch {
+            addresses: addresses.iter().copied().collect(),
+            ..Default::default()
+        };
+        OverrideLists {
+            deny: matcher(deny),
+            allow: allow.map(matcher),
+        }
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let hash = B256::ZERO;
+        let lists = lists(&[WETH], Some(&[WETH, USDC]));
+        assert!(lists.forces_interpreter(WETH, &hash));
+        assert!(!lists.forces_interpreter(USDC, &hash));
+        // Not allowed, so interpreted too
+        assert!(lists.forces_interpreter(OTHER, &hash));
+    }
+
+    #[test]
+    fn without_allow_list_only_denied_are_interpreted() {
+        let hash = B256::ZERO;
+        let lists = lists(&[WETH], None);
+        assert!(lists.forces_interpreter(WETH, &hash));
+        assert!(!lists.forces_interpreter(USDC, &hash));
+        assert!(!OverrideLists::default().forces_interpreter(WETH, &hash));
+    }
+
+    #[test]
+    fn bytecode_hashes_match_at_any_address() {
+        let (denied, allowed) = (B256::repeat_byte(1), B256::repeat_byte(2));
+        let mut lists = OverrideLists::default();
+        lists.deny.bytecode_hashes.insert(denied);
+        assert!(lists.forces_interpreter(OTHER, &denied));
+        assert!(!lists.forces_interpreter(OTHER, &allowed));
+    }
+
+    #[test]
+    fn lists_read_from_toml_and_json() {
+        let dir =
+            std::env::temp_dir().join(format!("revmc-toolkit-{}-overrides", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml_path = dir.join("overrides.toml");
+        std::fs::write(
+            &toml_path,
+            format!("[deny]\naddresses = [\"{WETH}\"]\n\n[allow]\naddresses = [\"{USDC}\"]\n"),
+        )
+        .unwrap();
+        let json_path = dir.join("overrides.json");
+        std::fs::write(
+            &json_path,
+            format!(r#"{{"deny": {{"bytecodeHashes": ["{}"]}}}}"#, B256::ZERO),
+        )
+        .unwrap();
+
+        let from_toml = OverrideLists::read(&toml_path).unwrap();
+        assert!(from_toml.deny.addresses.contains(&WETH));
+        assert!(from_toml.allow.unwrap().addresses.contains(&USDC));
+        let from_json = OverrideLists::read(&json_path).unwrap();
+        assert!(from_json.deny.bytecode_hashes.contains(&B256::ZERO));
+        assert!(from_json.allow.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn changes_are_shared_and_invalid_reloads_keep_the_lists() {
+        let hash = B256::ZERO;
+        let overrides = ExecutionOverrides::default();
+        let clone = overrides.clone();
+        overrides.update(|lists| lists.deny.addresses.insert(WETH));
+        assert!(clone.forces_interpreter(WETH, &hash));
+
+        let path = std::env::temp_dir().join(format!(
+            "revmc-toolkit-{}-invalid-overrides.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(clone.reload(&path).is_err());
+        assert!(overrides.forces_interpreter(WETH, &hash));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,300 @@
+use revm::primitives::{SpecId, B256};
+use revmc_toolkit_build::{ArtifactKey, ArtifactOptions, CompilerOptions};
+
+use rustc_hash::FxHashMap;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info};
+
+use crate::evm_components::{EvmCompilerFns, ReferenceDropObject};
+use crate::fn_loader::EvmCompilerFnLoader;
+use crate::lazy::{index_aot_dir, load_entry, IndexEntry};
+use revmc::EvmCompilerFn;
+
+/// Compiled functions that can change while EVMs use them. Every change publishes a new
+/// snapshot, which an `RevmcExtCtx` picks up when its next frame starts. Frames keep the
+/// library of their function alive, so a removed function stays loaded until the frames
+/// running it are done. Cheap to clone and safe to share across threads.
+#[derive(Clone, Default)]
+pub struct FnRegistry(Arc<RegistryInner>);
+
+#[derive(Default)]
+struct RegistryInner {
+    fns: RwLock<EvmCompilerFns>,
+    /// Bumped on every change, so that readers only clone the snapshot when it changed.
+    version: AtomicU64,
+}
+
+impl From<EvmCompilerFns> for FnRegistry {
+    fn from(fns: EvmCompilerFns) -> Self {
+        Self(Arc::new(RegistryInner {
+            fns: RwLock::new(fns),
+            version: AtomicU64::new(0),
+        }))
+    }
+}
+
+impl FnRegistry {
+    /// Adds the functions, replacing ones already registered for the same bytecode and spec.
+    pub fn insert(&self, fns: EvmCompilerFns) {
+        self.update(|map| map.extend(fns.0.iter().map(|(k, v)| (*k, v.clone()))));
+    }
+
+    pub fn remove(&self, bytecode_hash: &B256, spec_id: SpecId) {
+        self.update(|map| {
+            map.remove(&(*bytecode_hash, spec_id));
+        });
+    }
+
+    pub fn snapshot(&self) -> EvmCompilerFns {
+        self.0.fns.read().unwrap().clone()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.0.version.load(Ordering::Acquire)
+    }
+
+    /// Polls the AOT dir every `interval` and registers new or recompiled artifacts for the
+    /// options, and removes the functions whose artifacts are gone. Only functions the
+    /// watcher registered are removed. Stops when the returned watcher is dropped.
+    pub fn watch(
+        &self,
+        options: &CompilerOptions,
+        verification_key: Option<ArtifactKey>,
+        smoke_test: bool,
+        interval: Duration,
+    ) -> RegistryWatcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut poller = DirPoller {
+            registry: self.clone(),
+            dir_path: options.out_dir.clone(),
+            options: options.artifact_options(),
+            verification_key,
+            smoke_test,
+            known: FxHashMap::default(),
+        };
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                poller.poll();
+                // Woken up early when the watcher is dropped
+                std::thread::park_timeout(interval);
+            }
+        });
+        RegistryWatcher {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn update(
+        &self,
+        f: impl FnOnce(&mut FxHashMap<(B256, SpecId), (EvmCompilerFn, ReferenceDropObject)>),
+    ) {
+        let mut fns = self.0.fns.write().unwrap();
+        // Snapshots handed out keep the previous map
+        f(Arc::make_mut(&mut fns.0));
+        self.0.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Stops watching the AOT dir when dropped.
+pub struct RegistryWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for RegistryWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Snapshot of a registry held by an `RevmcExtCtx`, refreshed when the registry changed.
+#[derive(Clone)]
+pub(crate) struct RegistryView {
+    registry: FnRegistry,
+    version: u64,
+    fns: EvmCompilerFns,
+}
+
+impl RegistryView {
+    pub(crate) fn new(registry: FnRegistry) -> Self {
+        Self {
+            version: registry.version(),
+            fns: registry.snapshot(),
+            registry,
+        }
+    }
+
+    pub(crate) fn current(&mut self) -> &EvmCompilerFns {
+        let version = self.registry.version();
+        if version != self.version {
+            self.fns = self.registry.snapshot();
+            self.version = version;
+        }
+        &self.fns
+    }
+}
+
+struct DirPoller {
+    registry: FnRegistry,
+    dir_path: PathBuf,
+    options: ArtifactOptions,
+    verification_key: Option<ArtifactKey>,
+    smoke_test: bool,
+    /// Entries seen so far and the modification time of their marker file, to notice them
+    /// being recompiled. Failed ones are only retried once they change.
+    known: FxHashMap<B256, (IndexEntry, Option<SystemTime>)>,
+}
+
+impl DirPoller {
+    fn poll(&mut self) {
+        let index = index_aot_dir(&self.dir_path, &self.options);
+        let spec_id = self.options.spec_id;
+
+        let removed = self
+            .known
+            .keys()
+            .filter(|bytecode_hash| !index.contains_key(bytecode_hash))
+            .copied()
+            .collect::<Vec<_>>();
+        for bytecode_hash in &removed {
+            self.known.remove(bytecode_hash);
+            self.registry.remove(bytecode_hash, spec_id);
+        }
+
+        let loader = EvmCompilerFnLoader::new(&self.dir_path)
+            .with_artifact_options(self.options.clone())
+            .with_verification_key(self.verification_key.clone())
+            .with_smoke_test(self.smoke_test)
+            // Recompiled libraries are loaded while the previous ones are still open
+            .with_private_copies(true);
+        let mut loaded = vec![];
+        for (bytecode_hash, entry) in &index {
+            let modified = std::fs::metadata(entry.marker_file())
+                .and_then(|m| m.modified())
+                .ok();
+            let stamp = (entry.clone(), modified);
+            if self.known.get(bytecode_hash) == Some(&stamp) {
+                continue;
+            }
+            // The other functions of a batch are current too, unless indexed elsewhere
+            let fns = load_entry(&loader, bytecode_hash, entry)
+                .into_iter()
+                .filter(|(hash, _)| index.get(hash) == Some(entry))
+                .collect::<Vec<_>>();
+            for (hash, _) in &fns {
+                self.known.insert(*hash, stamp.clone());
+            }
+            self.known.insert(*bytecode_hash, stamp);
+            loaded.extend(fns.into_iter().filter_map(|(hash, fnc)| Some((hash, fnc?))));
+        }
+
+        if !removed.is_empty() || !loaded.is_empty() {
+            info!(
+                "Registered {} AOT functions, removed {}",
+                loaded.len(),
+                removed.len()
+            );
+        } else {
+            debug!("No AOT changes in {}", self.dir_path.display());
+        }
+        if !loaded.is_empty() {
+            let fns = loaded
+                .into_iter()
+                .map(|(hash, fnc)| ((hash, spec_id), fnc))
+                .collect();
+            self.registry.insert(EvmCompilerFns(Arc::new(fns)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{jit_fns, options_in, temp_dir, write_artifact};
+    use revmc_toolkit_build::{artifact_dir, JitCompileOut};
+
+    fn compiled(bytecode: &[u8], spec_id: SpecId) -> (B256, EvmCompilerFns) {
+        let entries = jit_fns(&[bytecode.to_vec()], spec_id);
+        let hash = entries[0].0;
+        let out = JitCompileOut {
+            entries,
+            ..Default::default()
+        };
+        (hash, out.into())
+    }
+
+    #[test]
+    fn view_refreshes_on_version_bump() {
+        let spec_id = SpecId::CANCUN;
+        let (hash, fns) = compiled(&[0x00], spec_id);
+        let registry = FnRegistry::default();
+        let mut view = RegistryView::new(registry.clone());
+        assert!(view.current().get(&hash, spec_id).is_none());
+
+        registry.insert(fns);
+        assert_eq!(registry.version(), 1);
+        assert!(view.current().get(&hash, spec_id).is_some());
+        assert_eq!(view.version, 1);
+
+        let before_removal = registry.snapshot();
+        registry.remove(&hash, spec_id);
+        assert_eq!(registry.version(), 2);
+        assert!(view.current().get(&hash, spec_id).is_none());
+        // Snapshots handed out keep the functions they had
+        assert!(before_removal.get(&hash, spec_id).is_some());
+    }
+
+    #[test]
+    fn view_keeps_its_snapshot_without_changes() {
+        let spec_id = SpecId::CANCUN;
+        let (hash, fns) = compiled(&[0x00], spec_id);
+        let registry = FnRegistry::from(fns);
+        let mut view = RegistryView::new(registry.clone());
+        let snapshot = Arc::as_ptr(&view.current().0);
+        assert_eq!(Arc::as_ptr(&view.current().0), snapshot);
+        assert!(view.current().get(&hash, spec_id).is_some());
+    }
+
+    #[test]
+    fn poller_only_removes_entries_it_indexed() {
+        let dir = temp_dir("registry-poll");
+        let options = options_in(&dir).artifact_options();
+        let spec_id = options.spec_id;
+        let (inserted, fns) = compiled(&[0x00], spec_id);
+        let registry = FnRegistry::from(fns);
+        // Indexed, but its placeholder library can't be loaded
+        let indexed = write_artifact(&dir, &[0x01], &options);
+        let mut poller = DirPoller {
+            registry: registry.clone(),
+            dir_path: dir.clone(),
+            options: options.clone(),
+            verification_key: None,
+            smoke_test: false,
+            known: FxHashMap::default(),
+        };
+
+        poller.poll();
+        assert!(poller.known.contains_key(&indexed));
+        assert_eq!(registry.version(), 0);
+
+        let artifact = artifact_dir(&dir, &indexed, &options);
+        std::fs::remove_dir_all(artifact.parent().unwrap()).unwrap();
+        poller.poll();
+        assert!(poller.known.is_empty());
+        assert!(registry.snapshot().get(&inserted, spec_id).is_some());
+    }
+}
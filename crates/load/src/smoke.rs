@@ -0,0 +1,103 @@
+use revm::{
+    db::InMemoryDB,
+    primitives::{
+        AccountInfo, Address, BlockEnv, Bytecode, CfgEnv, CfgEnvWithHandlerCfg, EnvWithHandlerCfg,
+        ExecutionResult, SpecId, TransactTo, TxEnv, B256, U256,
+    },
+    Evm,
+};
+use revmc::EvmCompilerFn;
+use revmc_toolkit_build::ArtifactOptions;
+
+use eyre::Result;
+
+use crate::{revmc_register_handler, RevmcExtCtx};
+
+const SMOKE_TEST_GAS_LIMIT: u64 = 1_000_000;
+
+/// Calls the contract with empty calldata once with `fnc` and once interpreted, and
+/// errors if the outcomes differ. `options` are the ones `fnc` was compiled with; gas is
+/// not compared for functions compiled without gas metering.
+pub fn smoke_test(
+    bytecode_hash: B256,
+    fnc: EvmCompilerFn,
+    bytecode: &[u8],
+    options: &ArtifactOptions,
+) -> Result<()> {
+    let spec_id = options.spec_id;
+    let compiled_fns = (spec_id, vec![(bytecode_hash, fnc)]).into();
+    let mut compiled = run_trivial_call(bytecode, spec_id, compiled_fns)?;
+    let mut interpreted = run_trivial_call(bytecode, spec_id, RevmcExtCtx::default())?;
+    if options.no_gas {
+        (compiled, interpreted) = (without_gas(compiled), without_gas(interpreted));
+    }
+    if compiled != interpreted {
+        return Err(eyre::eyre!(
+            "smoke test failed for {bytecode_hash}: expected {interpreted:?} found {compiled:?}"
+        ));
+    }
+    Ok(())
+}
+
+fn run_trivial_call(
+    bytecode: &[u8],
+    spec_id: SpecId,
+    ext_ctx: RevmcExtCtx,
+) -> Result<ExecutionResult> {
+    let address = Address::from_slice(&[1; 20]);
+    let bytecode = Bytecode::new_raw(bytecode.to_vec().into());
+    let mut db = InMemoryDB::default();
+    db.insert_account_info(
+        address,
+        AccountInfo {
+            code_hash: bytecode.hash_slow(),
+            code: Some(bytecode),
+            ..Default::default()
+        },
+    );
+
+    let tx_env = TxEnv {
+        transact_to: TransactTo::Call(address),
+        gas_limit: SMOKE_TEST_GAS_LIMIT,
+        gas_price: U256::ZERO,
+        ..TxEnv::default()
+    };
+    let cfg_env = CfgEnvWithHandlerCfg::new_with_spec_id(CfgEnv::default(), spec_id);
+    let env = EnvWithHandlerCfg::new_with_cfg_env(cfg_env, BlockEnv::default(), tx_env);
+
+    let mut evm = Evm::builder()
+        .with_db(db)
+        .with_external_context(ext_ctx)
+        .with_env_with_handler_cfg(env)
+        .append_handler_register(revmc_register_handler)
+        .build();
+    let result = evm
+        .transact()
+        .map_err(|e| eyre::eyre!("smoke test call failed: {e:?}"))?;
+    Ok(result.result)
+}
+
+fn without_gas(result: ExecutionResult) -> ExecutionResult {
+    match result {
+        ExecutionResult::Success {
+            reason,
+            logs,
+            output,
+            ..
+        } => ExecutionResult::Success {
+            reason,
+            gas_used: 0,
+            gas_refunded: 0,
+            logs,
+            output,
+        },
+        ExecutionResult::Revert { output, .. } => ExecutionResult::Revert {
+            gas_used: 0,
+            output,
+        },
+        ExecutionResult::Halt { reason, .. } => ExecutionResult::Halt {
+            reason,
+            gas_used: 0,
+        },
+    }
+}
@@ -0,0 +1,63 @@
+use revm::primitives::{keccak256, SpecId, B256};
+use revmc_toolkit_build::{
+    artifact_dir, batch_dir, ArtifactManifest, ArtifactOptions, BatchIndex, Compiler,
+    CompilerOptions, JitFunction,
+};
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Empty dir for a test, under the system temp dir.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("revmc-toolkit-load-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Options of artifacts under the dir.
+pub(crate) fn options_in(out_dir: &Path) -> CompilerOptions {
+    CompilerOptions::default().with_out_dir(out_dir)
+}
+
+/// Writes a manifest for the bytecode with a placeholder library, which is enough to be
+/// indexed but not to be loaded.
+pub(crate) fn write_artifact(out_dir: &Path, bytecode: &[u8], options: &ArtifactOptions) -> B256 {
+    let bytecode_hash = keccak256(bytecode);
+    let dir = artifact_dir(out_dir, &bytecode_hash, options);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.so"), b"library").unwrap();
+    ArtifactManifest::new(bytecode_hash, options.clone())
+        .seal(&dir, None)
+        .unwrap()
+        .write(&dir)
+        .unwrap();
+    bytecode_hash
+}
+
+/// Writes the index of a batch of the bytecodes with a placeholder library.
+pub(crate) fn write_batch(
+    out_dir: &Path,
+    batch_id: &str,
+    bytecode_hashes: Vec<B256>,
+    options: &ArtifactOptions,
+) -> PathBuf {
+    let dir = batch_dir(out_dir, batch_id);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.so"), b"library").unwrap();
+    BatchIndex::new(bytecode_hashes, options.clone())
+        .seal(&dir, None)
+        .unwrap()
+        .write(&dir)
+        .unwrap();
+    dir
+}
+
+/// JIT-compiles each bytecode into a function for `spec_id`.
+pub(crate) fn jit_fns(bytecodes: &[Vec<u8>], spec_id: SpecId) -> Vec<(B256, Arc<JitFunction>)> {
+    let compiler = Compiler::from(CompilerOptions::default().with_spec_id(spec_id));
+    compiler.compile_jit_many(bytecodes).unwrap().entries
+}
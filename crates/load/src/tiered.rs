@@ -0,0 +1,345 @@
+use revm::primitives::{keccak256, Bytecode, SpecId, B256};
+use revmc_toolkit_build::{Compiler, CompilerOptions, JitFunction};
+use rustc_hash::FxHashMap;
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex, Weak,
+};
+use tracing::{debug, error};
+
+use crate::jit_cache::{JitCache, JitCacheConfig, JitCacheStats};
+
+#[derive(Debug, Clone)]
+pub struct TieredJitConfig {
+    /// Number of interpreted frames after which the bytecode is queued for compilation.
+    pub invocation_threshold: u64,
+    /// Gas spent in interpreted frames after which the bytecode is queued for compilation.
+    pub gas_threshold: u64,
+    /// Max number of bytecodes compiled with the same compiler.
+    pub batch_size: usize,
+    /// `spec_id` is ignored, bytecodes are compiled for the spec they were interpreted with.
+    /// A policy is applied per contract, see `CompilerOptions::for_contract`.
+    pub compiler_options: CompilerOptions,
+    /// Budget for the compiled functions. Evicted bytecodes are profiled again.
+    pub cache: JitCacheConfig,
+}
+
+impl Default for TieredJitConfig {
+    fn default() -> Self {
+        Self {
+            invocation_threshold: 1_000,
+            gas_threshold: 10_000_000,
+            batch_size: 10,
+            compiler_options: CompilerOptions::default(),
+            cache: JitCacheConfig::default(),
+        }
+    }
+}
+
+impl TieredJitConfig {
+    pub fn with_invocation_threshold(mut self, invocation_threshold: u64) -> Self {
+        self.invocation_threshold = invocation_threshold;
+        self
+    }
+
+    pub fn with_gas_threshold(mut self, gas_threshold: u64) -> Self {
+        self.gas_threshold = gas_threshold;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_compiler_options(mut self, compiler_options: CompilerOptions) -> Self {
+        self.compiler_options = compiler_options;
+        self
+    }
+
+    pub fn with_cache_config(mut self, cache: JitCacheConfig) -> Self {
+        self.cache = cache;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Hotness {
+    Profiling {
+        invocations: u64,
+        gas_used: u64,
+    },
+    Queued,
+    Compiled,
+    /// Rejected by the admission policy or failed to compile, so it's interpreted from now on.
+    Interpreted,
+}
+
+const COLD: Hotness = Hotness::Profiling {
+    invocations: 0,
+    gas_used: 0,
+};
+
+/// Number of independently locked parts of the profile, so that frames of different
+/// bytecodes recorded on different threads rarely wait for each other.
+const HOTNESS_SHARDS: usize = 64;
+
+type HotnessShard = Mutex<FxHashMap<(B256, SpecId), Hotness>>;
+
+struct TieredJitInner {
+    config: TieredJitConfig,
+    compiled: JitCache,
+    hotness: Box<[HotnessShard]>,
+    queue: Sender<(Vec<u8>, SpecId)>,
+}
+
+impl TieredJitInner {
+    fn hotness_shard(&self, bytecode_hash: &B256) -> &HotnessShard {
+        &self.hotness[bytecode_hash[0] as usize % HOTNESS_SHARDS]
+    }
+
+    fn set_hotness(&self, bytecode_hash: B256, spec_id: SpecId, hotness: Hotness) {
+        let mut shard = self.hotness_shard(&bytecode_hash).lock().unwrap();
+        shard.insert((bytecode_hash, spec_id), hotness);
+    }
+}
+
+/// Profiles interpreted frames and JIT-compiles hot bytecodes on a background thread.
+/// Cloning is cheap and clones share the compiled functions.
+#[derive(Clone)]
+pub struct TieredJit(Arc<TieredJitInner>);
+
+impl TieredJit {
+    pub fn new(config: TieredJitConfig) -> Self {
+        let (queue, receiver) = mpsc::channel();
+        let inner = Arc::new(TieredJitInner {
+            compiled: JitCache::new(config.cache.clone()),
+            config,
+            hotness: (0..HOTNESS_SHARDS).map(|_| Default::default()).collect(),
+            queue,
+        });
+        let worker = Arc::downgrade(&inner);
+        std::thread::Builder::new()
+            .name("revmc-tiered-jit".to_string())
+            .spawn(move || Self::compile_worker(worker, receiver))
+            .expect("Failed to spawn tiered JIT worker");
+        Self(inner)
+    }
+
+    pub fn get(&self, bytecode_hash: &B256, spec_id: SpecId) -> Option<Arc<JitFunction>> {
+        self.0.compiled.get(bytecode_hash, spec_id)
+    }
+
+    pub fn cache_stats(&self) -> JitCacheStats {
+        self.0.compiled.stats()
+    }
+
+    /// Records an interpreted frame execution and queues the bytecode for compilation for
+    /// `spec_id` once it crosses one of the thresholds.
+    pub fn record(
+        &self,
+        bytecode_hash: B256,
+        spec_id: SpecId,
+        bytecode: &Bytecode,
+        new_frame: bool,
+        gas_used: u64,
+    ) {
+        let config = &self.0.config;
+        let mut hotness = self.0.hotness_shard(&bytecode_hash).lock().unwrap();
+        let entry = hotness.entry((bytecode_hash, spec_id)).or_insert(COLD);
+        // Interpreted despite being compiled, so it was evicted in the meantime
+        if matches!(entry, Hotness::Compiled) && !self.0.compiled.contains(&bytecode_hash, spec_id)
+        {
+            *entry = COLD;
+        }
+        let Hotness::Profiling {
+            invocations,
+            gas_used: total_gas_used,
+        } = entry
+        else {
+            return;
+        };
+        *invocations += new_frame as u64;
+        *total_gas_used += gas_used;
+        if *invocations >= config.invocation_threshold || *total_gas_used >= config.gas_threshold {
+            debug!("Queueing {bytecode_hash} ({spec_id:?}) for tiered JIT compilation");
+            *entry = Hotness::Queued;
+            let bytecode = bytecode.original_bytes().into();
+            if self.0.queue.send((bytecode, spec_id)).is_err() {
+                error!("Tiered JIT worker is not running");
+            }
+        }
+    }
+
+    fn compile_worker(inner: Weak<TieredJitInner>, receiver: Receiver<(Vec<u8>, SpecId)>) {
+        let (options, batch_size) = match inner.upgrade() {
+            Some(inner) => (
+                inner.config.compiler_options.clone(),
+                inner.config.batch_size,
+            ),
+            None => return,
+        };
+        // By the cache key of the contract options, which covers the spec
+        let mut compilers = FxHashMap::<String, Compiler>::default();
+        // Exits once all handles (and with them the sender) are dropped
+        while let Ok(queued) = receiver.recv() {
+            let mut batches = FxHashMap::<String, (SpecId, Vec<Vec<u8>>)>::default();
+            let mut rejected = vec![];
+            for (bytecode, spec_id) in std::iter::once(queued)
+                .chain(receiver.try_iter().take(batch_size.saturating_sub(1)))
+            {
+                let contract_opt = options
+                    .clone()
+                    .with_spec_id(spec_id)
+                    .for_contract(&bytecode);
+                if let Some(reason) = contract_opt.admission.reject_reason(&bytecode) {
+                    debug!("Tiered JIT rejected a bytecode: {reason}");
+                    rejected.push((keccak256(&bytecode), spec_id));
+                    continue;
+                }
+                let key = contract_opt.artifact_options().cache_key();
+                compilers
+                    .entry(key.clone())
+                    .or_insert_with(|| contract_opt.into());
+                batches
+                    .entry(key)
+                    .or_insert_with(|| (spec_id, vec![]))
+                    .1
+                    .push(bytecode);
+            }
+
+            match inner.upgrade() {
+                Some(inner) => {
+                    for (bytecode_hash, spec_id) in rejected {
+                        inner.set_hotness(bytecode_hash, spec_id, Hotness::Interpreted);
+                    }
+                }
+                None => return,
+            }
+            for (key, (spec_id, batch)) in batches {
+                let compiler = &compilers[&key];
+                let (entries, failed) = Self::compile_batch(compiler, &batch);
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                debug!("Tiered JIT compiled {} contracts", entries.len());
+                for (bytecode_hash, fnc) in entries {
+                    inner.compiled.insert(bytecode_hash, fnc);
+                    inner.set_hotness(bytecode_hash, spec_id, Hotness::Compiled);
+                }
+                for bytecode_hash in failed {
+                    inner.set_hotness(bytecode_hash, spec_id, Hotness::Interpreted);
+                }
+            }
+        }
+    }
+
+    /// Compiles the batch and returns its functions and the bytecodes left to the interpreter.
+    fn compile_batch(
+        compiler: &Compiler,
+        batch: &[Vec<u8>],
+    ) -> (Vec<(B256, Arc<JitFunction>)>, Vec<B256>) {
+        let out = compiler.compile_jit_many_or_each(batch);
+        for e in &out.errors {
+            error!("Tiered JIT compilation failed: {e}");
+        }
+        let failed = out
+            .rejected
+            .iter()
+            .map(|r| r.bytecode_hash)
+            .chain(out.errors.iter().map(|e| e.bytecode_hash))
+            .collect();
+        (out.entries, failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::jit_fns;
+
+    const SPEC_ID: SpecId = SpecId::CANCUN;
+
+    /// Never compiles on its own, so that tests control the hotness.
+    fn tiered(max_functions: usize) -> TieredJit {
+        let cache = JitCacheConfig::default().with_max_functions(max_functions);
+        TieredJit::new(
+            TieredJitConfig::default()
+                .with_invocation_threshold(u64::MAX)
+                .with_gas_threshold(u64::MAX)
+                .with_cache_config(cache),
+        )
+    }
+
+    fn hotness(tiered: &TieredJit, bytecode_hash: B256) -> Option<Hotness> {
+        let shard = tiered.0.hotness_shard(&bytecode_hash).lock().unwrap();
+        shard.get(&(bytecode_hash, SPEC_ID)).copied()
+    }
+
+    fn bytecode(code: &[u8]) -> Bytecode {
+        Bytecode::new_raw(code.to_vec().into())
+    }
+
+    #[test]
+    fn evicted_functions_are_profiled_again() {
+        let tiered = tiered(1);
+        let codes = [vec![0x60, 0x01, 0x00], vec![0x60, 0x02, 0x00]];
+        let [a, b] = <[_; 2]>::try_from(jit_fns(&codes, SPEC_ID)).ok().unwrap();
+        tiered.0.compiled.insert(a.0, a.1);
+        tiered.0.set_hotness(a.0, SPEC_ID, Hotness::Compiled);
+
+        // Still resident, so the frame was interpreted for another reason
+        tiered.record(a.0, SPEC_ID, &bytecode(&codes[0]), true, 10);
+        assert!(matches!(hotness(&tiered, a.0), Some(Hotness::Compiled)));
+
+        // Evicts `a`, whose next interpreted frame starts a new profile
+        tiered.0.compiled.insert(b.0, b.1);
+        tiered.record(a.0, SPEC_ID, &bytecode(&codes[0]), true, 10);
+        assert!(matches!(
+            hotness(&tiered, a.0),
+            Some(Hotness::Profiling {
+                invocations: 1,
+                gas_used: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn profiles_count_frames_and_gas() {
+        let tiered = tiered(1);
+        let code = bytecode(&[0x00]);
+        let hash = keccak256([0x00]);
+        tiered.record(hash, SPEC_ID, &code, true, 5);
+        // Resumed frames add their gas, but aren't new invocations
+        tiered.record(hash, SPEC_ID, &code, false, 7);
+        assert!(matches!(
+            hotness(&tiered, hash),
+            Some(Hotness::Profiling {
+                invocations: 1,
+                gas_used: 12
+            })
+        ));
+
+        tiered.0.set_hotness(hash, SPEC_ID, Hotness::Interpreted);
+        tiered.record(hash, SPEC_ID, &code, true, 5);
+        assert!(matches!(hotness(&tiered, hash), Some(Hotness::Interpreted)));
+    }
+
+    #[test]
+    fn crossing_a_threshold_queues_the_bytecode() {
+        let tiered = TieredJit::new(TieredJitConfig::default().with_invocation_threshold(2));
+        let code = bytecode(&[0x00]);
+        let hash = keccak256([0x00]);
+        tiered.record(hash, SPEC_ID, &code, true, 0);
+        assert!(matches!(
+            hotness(&tiered, hash),
+            Some(Hotness::Profiling { .. })
+        ));
+        tiered.record(hash, SPEC_ID, &code, true, 0);
+        // The worker may have compiled it already
+        assert!(!matches!(
+            hotness(&tiered, hash),
+            Some(Hotness::Profiling { .. })
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use eyre::Result;
 use reth_db::DatabaseEnv;
 use reth_provider::{ProviderFactory, StateProvider};
-use revm::interpreter::{CallInputs, CallOutcome};
+use revm::interpreter::{CallInputs, CallOutcome, CreateInputs, CreateOutcome};
 use revm::primitives::{Address, Bytes, B256};
 use revm::{self, Database, EvmContext, Inspector};
 use std::collections::HashSet;
@@ -11,12 +11,19 @@ use crate::sim_builder::{self, TxsSimBuilderExt};
 #[derive(Default)]
 struct BytecodeTouchInspector {
     touches: HashSet<Address>,
+    initcodes: HashSet<Bytes>,
 }
 
 impl BytecodeTouchInspector {
     pub fn record_touch(&mut self, address: Address) {
         self.touches.insert(address);
     }
+
+    pub fn record_initcode(&mut self, initcode: &Bytes) {
+        if !initcode.is_empty() {
+            self.initcodes.insert(initcode.clone());
+        }
+    }
 }
 
 impl<DB: Database> Inspector<DB> for BytecodeTouchInspector {
@@ -28,8 +35,18 @@ impl<DB: Database> Inspector<DB> for BytecodeTouchInspector {
         self.record_touch(inputs.bytecode_address);
         None
     }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.record_initcode(&inputs.init_code);
+        None
+    }
 }
 
+/// Runtime code of the called contracts and initcode of the executed creates.
 pub fn find_touched_bytecode_blocks(
     provider_factory: ProviderFactory<DatabaseEnv>,
     blocks: &[u64],
@@ -42,8 +59,9 @@ pub fn find_touched_bytecode_blocks(
             .with_handle_register(revm::inspector_handle_register)
             .into_block_sim(*block, None)?;
         sim.run()?;
-        let BytecodeTouchInspector { touches } = sim.into_evm().context.external;
+        let BytecodeTouchInspector { touches, initcodes } = sim.into_evm().context.external;
         let touched = contracts_to_bytecode(provider_factory.latest()?, touches)?
+            .chain(initcodes)
             .map(|code| code.into())
             .collect::<Vec<_>>();
         touched_bytecode.extend(touched);
@@ -51,6 +69,7 @@ pub fn find_touched_bytecode_blocks(
     Ok(touched_bytecode)
 }
 
+/// Runtime code of the called contracts and initcode of the executed creates.
 pub fn find_touched_bytecode(
     provider_factory: ProviderFactory<DatabaseEnv>,
     txs: Vec<B256>,
@@ -63,8 +82,9 @@ pub fn find_touched_bytecode(
             .with_handle_register(revm::inspector_handle_register)
             .into_tx_sim(tx_hash)?;
         sim.run()?;
-        let BytecodeTouchInspector { touches } = sim.into_evm().context.external;
+        let BytecodeTouchInspector { touches, initcodes } = sim.into_evm().context.external;
         let touched = contracts_to_bytecode(provider_factory.latest()?, touches)?
+            .chain(initcodes)
             .map(|code| code.into())
             .collect::<Vec<_>>();
         touched_bytecode.extend(touched);
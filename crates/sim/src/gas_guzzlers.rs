@@ -7,10 +7,11 @@ use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use reth_db::DatabaseEnv;
 use reth_provider::{BlockNumReader, ProviderFactory, StateProvider};
 use revm::{
-    interpreter::{CallInputs, CallOutcome},
-    primitives::{Address, Bytecode, B256},
+    interpreter::{CallInputs, CallOutcome, CreateInputs, CreateOutcome},
+    primitives::{Address, Bytecode, Bytes, B256},
     EvmContext, Inspector,
 };
+use revmc_toolkit_build::BytecodeMetrics;
 use revmc_toolkit_utils as utils;
 use std::{
     collections::{HashMap, VecDeque},
@@ -49,23 +50,58 @@ impl ContractUsage {
     }
 }
 
+/// Code executed by a frame.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CodeSource {
+    /// Runtime code of the contract at the address.
+    Contract(Address),
+    /// Initcode of a CREATE or CREATE2.
+    Initcode(Bytes),
+}
+
 #[derive(Default)]
 struct BytecodeContractUsageInspector {
-    account_to_usage: HashMap<Address, ContractUsage>,
-    parent_bytecode_stack: VecDeque<Address>,
-    current_bytecode: Option<Address>,
+    account_to_usage: HashMap<CodeSource, ContractUsage>,
+    parent_bytecode_stack: VecDeque<CodeSource>,
+    current_bytecode: Option<CodeSource>,
+    track_initcode: bool,
 }
 
 impl BytecodeContractUsageInspector {
-    fn record_usage(&mut self, contract: Address, gas_used: u64) {
-        let entry = self.account_to_usage.entry(contract).or_default();
+    fn new(track_initcode: bool) -> Self {
+        Self {
+            track_initcode,
+            ..Default::default()
+        }
+    }
+
+    fn record_usage(&mut self, code: CodeSource, gas_used: u64) {
+        let entry = self.account_to_usage.entry(code).or_default();
         entry.update(gas_used);
     }
 
-    fn record_gas_deficit(&mut self, caller: Address, gas_used: u64) {
+    fn record_gas_deficit(&mut self, caller: CodeSource, gas_used: u64) {
         let entry = self.account_to_usage.entry(caller).or_default();
         entry.gas_deficit += gas_used;
     }
+
+    fn enter(&mut self, code: CodeSource) {
+        if let Some(parent) = self.current_bytecode.take() {
+            self.parent_bytecode_stack.push_back(parent);
+        }
+        self.current_bytecode = Some(code);
+    }
+
+    fn exit(&mut self, code: CodeSource, gas_used: u64) {
+        self.record_usage(code, gas_used);
+
+        if let Some(parent_bytecode) = self.parent_bytecode_stack.pop_back() {
+            self.record_gas_deficit(parent_bytecode.clone(), gas_used);
+            self.current_bytecode = Some(parent_bytecode);
+        } else {
+            self.current_bytecode = None;
+        }
+    }
 }
 
 impl<DB: revm::Database> Inspector<DB> for BytecodeContractUsageInspector {
@@ -74,10 +110,7 @@ impl<DB: revm::Database> Inspector<DB> for BytecodeContractUsageInspector {
         _context: &mut EvmContext<DB>,
         inputs: &mut CallInputs,
     ) -> Option<CallOutcome> {
-        if let Some(bytecode_address) = self.current_bytecode {
-            self.parent_bytecode_stack.push_back(bytecode_address);
-        }
-        self.current_bytecode = Some(inputs.bytecode_address);
+        self.enter(CodeSource::Contract(inputs.bytecode_address));
         None
     }
 
@@ -88,17 +121,32 @@ impl<DB: revm::Database> Inspector<DB> for BytecodeContractUsageInspector {
         outcome: CallOutcome,
     ) -> CallOutcome {
         let contract = inputs.bytecode_address; // We care about which bytecode
-        let gas_used = outcome.gas().spent();
-
-        self.record_usage(contract, gas_used);
+        self.exit(CodeSource::Contract(contract), outcome.gas().spent());
+        outcome
+    }
 
-        if let Some(parent_bytecode) = self.parent_bytecode_stack.pop_back() {
-            self.record_gas_deficit(parent_bytecode, gas_used);
-            self.current_bytecode = Some(parent_bytecode);
-        } else {
-            self.current_bytecode = None;
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        // Otherwise the initcode gas is attributed to the caller, as before
+        if self.track_initcode {
+            self.enter(CodeSource::Initcode(inputs.init_code.clone()));
         }
+        None
+    }
 
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        if self.track_initcode {
+            let initcode = CodeSource::Initcode(inputs.init_code.clone());
+            self.exit(initcode, outcome.gas().spent());
+        }
         outcome
     }
 }
@@ -109,16 +157,22 @@ pub struct GasGuzzlerBytecodeUsage {
 }
 
 impl GasGuzzlerBytecodeUsage {
-    fn new(contract: Address, usage: ContractUsage) -> Self {
-        let contracts = [(contract, usage.frequency)].into_iter().collect();
+    /// `contract` is `None` for initcode.
+    fn new(contract: Option<Address>, usage: ContractUsage) -> Self {
+        let contracts = contract
+            .map(|contract| (contract, usage.frequency))
+            .into_iter()
+            .collect();
         Self { contracts, usage }
     }
 
-    fn update(&mut self, contract: Address, usage: &ContractUsage) {
-        self.contracts
-            .entry(contract)
-            .and_modify(|e| *e += usage.frequency)
-            .or_insert(usage.frequency);
+    fn update(&mut self, contract: Option<Address>, usage: &ContractUsage) {
+        if let Some(contract) = contract {
+            self.contracts
+                .entry(contract)
+                .and_modify(|e| *e += usage.frequency)
+                .or_insert(usage.frequency);
+        }
         self.usage.merge(usage);
     }
 }
@@ -133,6 +187,10 @@ pub struct BytecodeStat<T> {
     pub csum_prop_gas_used: f64,
     pub csum_prop_frequency: f64,
     pub most_used_address: Option<Address>,
+    /// Whether the bytecode is initcode rather than runtime code.
+    pub initcode: bool,
+    /// Structure of the bytecode, to relate its compile speedup to.
+    pub analysis: BytecodeMetrics,
 }
 
 impl BytecodeStat<Bytecode> {
@@ -147,18 +205,26 @@ impl BytecodeStat<Bytecode> {
 
 pub struct GasGuzzlerReport {
     pub csum_stats: ContractUsage,
-    pub bytecode_stats: HashMap<Vec<u8>, GasGuzzlerBytecodeUsage>,
+    /// Keyed by bytecode and whether it's initcode.
+    pub bytecode_stats: HashMap<(Vec<u8>, bool), GasGuzzlerBytecodeUsage>,
 }
 
 impl GasGuzzlerReport {
     pub fn new(
-        usage: MapWrapper<Address, ContractUsage>,
+        usage: MapWrapper<CodeSource, ContractUsage>,
         state_provider: &dyn StateProvider,
     ) -> Result<Self> {
         let mut bytecode_stats: HashMap<_, GasGuzzlerBytecodeUsage> = HashMap::new();
         let mut csum_stats = ContractUsage::default();
-        for (contract, usage) in usage.0.into_iter() {
-            let bytecode = Self::bytecode_for_contract(contract, state_provider)?;
+        for (code, usage) in usage.0.into_iter() {
+            let (contract, bytecode) = match code {
+                CodeSource::Contract(contract) => (
+                    Some(contract),
+                    Self::bytecode_for_contract(contract, state_provider)?
+                        .map(|bytecode| (bytecode, false)),
+                ),
+                CodeSource::Initcode(initcode) => (None, Some((initcode.into(), true))),
+            };
             if let Some(bytecode) = bytecode {
                 csum_stats.merge(&usage);
                 bytecode_stats
@@ -181,7 +247,7 @@ impl GasGuzzlerReport {
         let take_size = take_size.unwrap_or(csum_stats.frequency() as usize);
         let mut parsed = bytecode_stats
             .into_iter()
-            .map(|(bytecode, usage)| {
+            .map(|((bytecode, initcode), usage)| {
                 let most_used_address = usage
                     .contracts
                     .into_iter()
@@ -201,6 +267,7 @@ impl GasGuzzlerReport {
                     frequency,
                     prop_gas_used,
                     prop_frequency,
+                    initcode,
                 )
             })
             .collect::<Vec<_>>();
@@ -214,6 +281,7 @@ impl GasGuzzlerReport {
                 *gas_used += elements.4;
                 *freq += elements.5;
                 Some(BytecodeStat {
+                    analysis: BytecodeMetrics::of(&elements.0),
                     bytecode: Bytecode::new_raw(elements.0.into()),
                     gas_used: elements.2,
                     frequency: elements.3,
@@ -222,6 +290,7 @@ impl GasGuzzlerReport {
                     csum_prop_gas_used: *gas_used,
                     csum_prop_frequency: *freq,
                     most_used_address: elements.1,
+                    initcode: elements.6,
                 })
             })
             .collect()
@@ -279,6 +348,8 @@ pub struct GasGuzzlerConfig {
     pub end_block: Option<u64>,
     pub sample_size: Option<u64>,
     pub seed: Option<[u8; 32]>,
+    /// Also report the initcode of creates, otherwise its gas is attributed to the caller.
+    pub include_initcode: bool,
 }
 
 impl GasGuzzlerConfig {
@@ -302,6 +373,11 @@ impl GasGuzzlerConfig {
         self
     }
 
+    pub fn with_include_initcode(mut self, include_initcode: bool) -> Self {
+        self.include_initcode = include_initcode;
+        self
+    }
+
     pub fn find_gas_guzzlers(
         &self,
         provider_factory: ProviderFactory<DatabaseEnv>,
@@ -317,7 +393,11 @@ impl GasGuzzlerConfig {
         let contract_usage = sample_iter
             .into_par_iter()
             .map(|block_num| {
-                let mut sim = Self::make_sim_for_block(provider_factory.clone(), block_num)?;
+                let mut sim = Self::make_sim_for_block(
+                    provider_factory.clone(),
+                    block_num,
+                    self.include_initcode,
+                )?;
                 sim.run()?;
                 let evm = sim.into_evm();
                 Ok(MapWrapper(evm.context.external.account_to_usage))
@@ -340,10 +420,11 @@ impl GasGuzzlerConfig {
     fn make_sim_for_block(
         provider_factory: ProviderFactory<DatabaseEnv>,
         block_num: u64,
+        include_initcode: bool,
     ) -> Result<sim_builder::Simulation<BytecodeContractUsageInspector, StateProviderCacheDB>> {
         sim_builder::SimulationBuilder::default()
             .with_provider_factory(provider_factory)
-            .with_ext_ctx(BytecodeContractUsageInspector::default())
+            .with_ext_ctx(BytecodeContractUsageInspector::new(include_initcode))
             .with_handle_register(revm::inspector_handle_register)
             .into_block_sim(block_num, None)
     }